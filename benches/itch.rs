@@ -0,0 +1,171 @@
+//! ITCH 5.0 throughput in messages per second, parsing alone and building
+//! books. Reads the `BinaryFILE` named by `ITCH_SAMPLE`, or writes a
+//! synthetic one to the temp directory. Only the first `MAX_MESSAGES` are
+//! used, so full-day files work too.
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use hft_simulator::itch::{ItchBookBuilder, ItchReader};
+use hft_simulator::order_book::OrderBook;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+const MAX_MESSAGES: usize = 500_000;
+const SYNTHETIC_MESSAGES: usize = 200_000;
+const SYMBOLS: [&[u8; 8]; 4] = [b"AAPL    ", b"MSFT    ", b"NVDA    ", b"SPY     "];
+
+fn header(message: &mut Vec<u8>, kind: u8, locate: u16, timestamp_ns: u64) {
+    message.push(kind);
+    message.extend_from_slice(&locate.to_be_bytes());
+    message.extend_from_slice(&0u16.to_be_bytes());
+    message.extend_from_slice(&timestamp_ns.to_be_bytes()[2..]);
+}
+
+fn frame(file: &mut Vec<u8>, message: &[u8]) {
+    file.extend_from_slice(&(message.len() as u16).to_be_bytes());
+    file.extend_from_slice(message);
+}
+
+/// Adds around a fixed mid for a few symbols, with executions, cancels,
+/// deletes, replaces and hidden trades against the live orders.
+fn synthetic_sample() -> Vec<u8> {
+    let mut rng = StdRng::seed_from_u64(5);
+    let mut file = Vec::new();
+    let mut live: Vec<(u64, u16)> = Vec::new();
+    let mut next_ref = 1u64;
+    let mut message = Vec::with_capacity(64);
+
+    for i in 0..SYNTHETIC_MESSAGES {
+        let timestamp_ns = 34_200_000_000_000 + i as u64 * 1_000;
+        message.clear();
+        let roll = rng.gen_range(0..100);
+        if i == 0 {
+            header(&mut message, b'S', 0, timestamp_ns);
+            message.push(b'O');
+        } else if live.len() < 100 || roll < 45 {
+            let locate = rng.gen_range(0..SYMBOLS.len()) as u16 + 1;
+            let buy = rng.gen_bool(0.5);
+            let offset = rng.gen_range(1..50u32) * 100;
+            let price = if buy {
+                1_500_000 - offset
+            } else {
+                1_500_000 + offset
+            };
+            header(&mut message, b'A', locate, timestamp_ns);
+            message.extend_from_slice(&next_ref.to_be_bytes());
+            message.push(if buy { b'B' } else { b'S' });
+            message.extend_from_slice(&(rng.gen_range(1..10u32) * 100).to_be_bytes());
+            message.extend_from_slice(SYMBOLS[locate as usize - 1]);
+            message.extend_from_slice(&price.to_be_bytes());
+            live.push((next_ref, locate));
+            next_ref += 1;
+        } else if roll < 95 {
+            let (order_ref, locate) = live.swap_remove(rng.gen_range(0..live.len()));
+            match roll {
+                45..=59 => {
+                    header(&mut message, b'E', locate, timestamp_ns);
+                    message.extend_from_slice(&order_ref.to_be_bytes());
+                    message.extend_from_slice(&100u32.to_be_bytes());
+                    message.extend_from_slice(&(i as u64).to_be_bytes());
+                    live.push((order_ref, locate));
+                }
+                60..=69 => {
+                    header(&mut message, b'X', locate, timestamp_ns);
+                    message.extend_from_slice(&order_ref.to_be_bytes());
+                    message.extend_from_slice(&100u32.to_be_bytes());
+                    live.push((order_ref, locate));
+                }
+                70..=84 => {
+                    header(&mut message, b'D', locate, timestamp_ns);
+                    message.extend_from_slice(&order_ref.to_be_bytes());
+                }
+                _ => {
+                    header(&mut message, b'U', locate, timestamp_ns);
+                    message.extend_from_slice(&order_ref.to_be_bytes());
+                    message.extend_from_slice(&next_ref.to_be_bytes());
+                    message.extend_from_slice(&500u32.to_be_bytes());
+                    message
+                        .extend_from_slice(&rng.gen_range(1_450_000..1_550_000u32).to_be_bytes());
+                    live.push((next_ref, locate));
+                    next_ref += 1;
+                }
+            }
+        } else {
+            let locate = rng.gen_range(0..SYMBOLS.len()) as u16 + 1;
+            header(&mut message, b'P', locate, timestamp_ns);
+            message.extend_from_slice(&0u64.to_be_bytes());
+            message.push(b'B');
+            message.extend_from_slice(&100u32.to_be_bytes());
+            message.extend_from_slice(SYMBOLS[locate as usize - 1]);
+            message.extend_from_slice(&1_500_000u32.to_be_bytes());
+            message.extend_from_slice(&(i as u64).to_be_bytes());
+        }
+        frame(&mut file, &message);
+    }
+    file
+}
+
+/// The sample file's bytes, cut after `MAX_MESSAGES`.
+fn sample() -> Vec<u8> {
+    let path = match std::env::var("ITCH_SAMPLE") {
+        Ok(path) => PathBuf::from(path),
+        Err(_) => {
+            let path = std::env::temp_dir().join("hft_simulator_itch_sample.bin");
+            if !path.exists() {
+                std::fs::write(&path, synthetic_sample()).expect("write synthetic sample");
+            }
+            path
+        }
+    };
+    let mut data = std::fs::read(&path).expect("read ITCH sample");
+
+    let mut end = 0;
+    for _ in 0..MAX_MESSAGES {
+        let Some(prefix) = data.get(end..end + 2) else {
+            break;
+        };
+        let next = end + 2 + u16::from_be_bytes([prefix[0], prefix[1]]) as usize;
+        if next > data.len() {
+            break;
+        }
+        end = next;
+    }
+    data.truncate(end);
+    data
+}
+
+fn bench_itch(c: &mut Criterion) {
+    let data = sample();
+    let messages = ItchReader::new(&data).count() as u64;
+
+    let mut group = c.benchmark_group("itch");
+    group.throughput(Throughput::Elements(messages));
+    group.sample_size(10);
+
+    group.bench_function("parse", |b| {
+        b.iter(|| {
+            for message in ItchReader::new(black_box(&data)) {
+                black_box(message.expect("valid ITCH sample"));
+            }
+        })
+    });
+
+    group.bench_function("build_books", |b| {
+        b.iter_batched(
+            || ItchBookBuilder::new(Arc::new(OrderBook::new()), Vec::new()),
+            |mut builder| {
+                builder
+                    .apply_all(black_box(&data))
+                    .expect("valid ITCH sample");
+                builder
+            },
+            BatchSize::LargeInput,
+        )
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_itch);
+criterion_main!(benches);
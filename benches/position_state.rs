@@ -0,0 +1,182 @@
+//! Position storage before and after the move to a scaled-integer atomic.
+//! The `mutex` cases rebuild the previous layout: a `Mutex<Decimal>` per
+//! position in one map and the lot ledger in another. Only position reads
+//! became lock-free; `record_transaction` still locks the lot ledger for
+//! every fill, so the `record_transaction` group measures the map layout
+//! rather than lock-free writes.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use dashmap::DashMap;
+use hft_simulator::order_book::OrderSide;
+use hft_simulator::position_ledger::{LotMethod, PositionLedger};
+use hft_simulator::risk_management::{AtomicDecimal, RiskManager};
+use rust_decimal::Decimal;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+const THREADS: usize = 4;
+const ADDS_PER_THREAD: usize = 10_000;
+
+struct MutexDecimal {
+    value: Mutex<Decimal>,
+}
+
+impl MutexDecimal {
+    fn new(value: Decimal) -> Self {
+        Self {
+            value: Mutex::new(value),
+        }
+    }
+
+    fn get(&self) -> Decimal {
+        *self.value.lock().unwrap()
+    }
+
+    fn set(&self, value: Decimal) {
+        *self.value.lock().unwrap() = value;
+    }
+
+    fn add(&self, delta: Decimal) {
+        *self.value.lock().unwrap() += delta;
+    }
+}
+
+/// The old `record_transaction`: ledger and position live in separate maps,
+/// each updated through its own write-locked entry.
+struct MutexPositions {
+    positions: DashMap<(u64, String), MutexDecimal>,
+    ledgers: DashMap<(u64, String), PositionLedger>,
+}
+
+impl MutexPositions {
+    fn record_transaction(&self, account_id: u64, symbol: &str, price: Decimal, qty: Decimal) {
+        let key = (account_id, symbol.to_string());
+        let mut ledger = self.ledgers.entry(key.clone()).or_default();
+        ledger.apply(qty, price, LotMethod::Fifo);
+        self.positions
+            .entry(key)
+            .or_insert_with(|| MutexDecimal::new(Decimal::ZERO))
+            .set(ledger.position());
+    }
+}
+
+/// Alternating buys and sells of fractional size around a drifting price.
+/// Each sell closes the buy before it, so the ledger stays small however
+/// many times the batch is replayed.
+fn fills() -> Vec<(Decimal, Decimal)> {
+    (0..1_000)
+        .map(|i| {
+            let price = Decimal::new(15_000 + (i % 37), 2);
+            let qty = Decimal::new(125 + (i / 2 % 11), 3);
+            let qty = if i % 2 == 1 { -qty } else { qty };
+            (price, qty)
+        })
+        .collect()
+}
+
+fn bench_single_thread(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decimal_cell");
+    let delta = Decimal::new(1_250, 3);
+
+    let atomic = AtomicDecimal::new(Decimal::ZERO).expect("zero fits");
+    group.bench_function("atomic_add_get", |b| {
+        b.iter(|| {
+            atomic.add(black_box(delta)).expect("sum in range");
+            black_box(atomic.get())
+        })
+    });
+
+    let mutex = MutexDecimal::new(Decimal::ZERO);
+    group.bench_function("mutex_add_get", |b| {
+        b.iter(|| {
+            mutex.add(black_box(delta));
+            black_box(mutex.get())
+        })
+    });
+
+    group.finish();
+}
+
+fn bench_contended(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decimal_cell_contended");
+    let delta = Decimal::new(1_250, 3);
+
+    group.bench_function(BenchmarkId::new("atomic_add", THREADS), |b| {
+        b.iter(|| {
+            let cell = Arc::new(AtomicDecimal::new(Decimal::ZERO).expect("zero fits"));
+            let workers: Vec<_> = (0..THREADS)
+                .map(|_| {
+                    let cell = cell.clone();
+                    thread::spawn(move || {
+                        for _ in 0..ADDS_PER_THREAD {
+                            cell.add(delta).expect("sum in range");
+                        }
+                    })
+                })
+                .collect();
+            workers.into_iter().for_each(|w| w.join().unwrap());
+            black_box(cell.get())
+        })
+    });
+
+    group.bench_function(BenchmarkId::new("mutex_add", THREADS), |b| {
+        b.iter(|| {
+            let cell = Arc::new(MutexDecimal::new(Decimal::ZERO));
+            let workers: Vec<_> = (0..THREADS)
+                .map(|_| {
+                    let cell = cell.clone();
+                    thread::spawn(move || {
+                        for _ in 0..ADDS_PER_THREAD {
+                            cell.add(delta);
+                        }
+                    })
+                })
+                .collect();
+            workers.into_iter().for_each(|w| w.join().unwrap());
+            black_box(cell.get())
+        })
+    });
+
+    group.finish();
+}
+
+fn bench_record_transaction(c: &mut Criterion) {
+    let mut group = c.benchmark_group("record_transaction");
+    let fills = fills();
+
+    group.bench_function("position_state", |b| {
+        let risk = RiskManager::new(Decimal::from(1_000_000));
+        b.iter(|| {
+            for (price, qty) in &fills {
+                let side = if qty.is_sign_negative() {
+                    OrderSide::Sell
+                } else {
+                    OrderSide::Buy
+                };
+                risk.record_transaction(1, "AAPL", *price, qty.abs(), side);
+            }
+        })
+    });
+
+    group.bench_function("mutex_maps", |b| {
+        let positions = MutexPositions {
+            positions: DashMap::new(),
+            ledgers: DashMap::new(),
+        };
+        b.iter(|| {
+            for (price, qty) in &fills {
+                positions.record_transaction(1, "AAPL", *price, *qty);
+            }
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_single_thread,
+    bench_contended,
+    bench_record_transaction
+);
+criterion_main!(benches);
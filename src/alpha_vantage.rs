@@ -0,0 +1,146 @@
+use crate::market_data::{MarketDataError, MarketDataProvider, MinuteData};
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use reqwest::Client;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Structure representing the Alpha Vantage API response.
+#[derive(Debug, Deserialize)]
+struct AlphaVantageResponse {
+    #[serde(rename = "Time Series (1min)")]
+    time_series: HashMap<String, MinuteData>,
+}
+
+/// Failures only Alpha Vantage has; they reach callers as
+/// `MarketDataError::Provider`.
+#[derive(Debug)]
+pub enum AlphaVantageError {
+    MissingApiKey,
+    Reqwest(reqwest::Error),
+}
+
+impl std::fmt::Display for AlphaVantageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AlphaVantageError::MissingApiKey => {
+                write!(f, "ALPHA_VANTAGE_API_KEY environment variable not set")
+            }
+            AlphaVantageError::Reqwest(e) => write!(f, "Reqwest error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for AlphaVantageError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AlphaVantageError::Reqwest(e) => Some(e),
+            AlphaVantageError::MissingApiKey => None,
+        }
+    }
+}
+
+impl From<AlphaVantageError> for MarketDataError {
+    fn from(e: AlphaVantageError) -> Self {
+        MarketDataError::Provider(Box::new(e))
+    }
+}
+
+impl From<reqwest::Error> for AlphaVantageError {
+    fn from(e: reqwest::Error) -> Self {
+        AlphaVantageError::Reqwest(e)
+    }
+}
+
+/// One-minute bars from Alpha Vantage's intraday time series. Quotes and
+/// trades are not on the free API.
+pub struct AlphaVantageProvider {
+    client: Client,
+    api_key: Option<String>,
+}
+
+impl AlphaVantageProvider {
+    pub fn new(client: Client, api_key: impl Into<String>) -> Self {
+        Self {
+            client,
+            api_key: Some(api_key.into()),
+        }
+    }
+
+    /// Reads the key from `ALPHA_VANTAGE_API_KEY`. Without one, every
+    /// request fails with `AlphaVantageError::MissingApiKey`.
+    pub fn from_env(client: Client) -> Self {
+        Self {
+            client,
+            api_key: std::env::var("ALPHA_VANTAGE_API_KEY").ok(),
+        }
+    }
+}
+
+/// Reads a "YYYY-MM-DD HH:MM:SS" timestamp as UTC.
+fn parse_timestamp(timestamp: &str) -> Option<DateTime<Utc>> {
+    NaiveDateTime::parse_from_str(timestamp, "%Y-%m-%d %H:%M:%S")
+        .ok()
+        .map(|dt| dt.and_utc())
+}
+
+#[async_trait]
+impl MarketDataProvider for AlphaVantageProvider {
+    fn name(&self) -> &str {
+        "Alpha Vantage"
+    }
+
+    async fn bars(
+        &self,
+        symbol: &str,
+    ) -> Result<Vec<(DateTime<Utc>, MinuteData)>, MarketDataError> {
+        let api_key = self
+            .api_key
+            .as_deref()
+            .ok_or(AlphaVantageError::MissingApiKey)?;
+        let url = format!(
+            "https://www.alphavantage.co/query?function=TIME_SERIES_INTRADAY&symbol={}&interval=1min&apikey={}",
+            symbol, api_key
+        );
+
+        let mut attempts = 3;
+        let mut last_error: Option<reqwest::Error> = None;
+
+        while attempts > 0 {
+            match self.client.get(&url).send().await {
+                Ok(response) => {
+                    let status = response.status();
+                    if !status.is_success() {
+                        let body = response.text().await.unwrap_or_default();
+                        return Err(MarketDataError::ApiError(format!(
+                            "Status {}: {}",
+                            status, body
+                        )));
+                    }
+
+                    let json_response = response
+                        .json::<AlphaVantageResponse>()
+                        .await
+                        .map_err(AlphaVantageError::from)?;
+                    let mut data = json_response
+                        .time_series
+                        .into_iter()
+                        .filter_map(|(timestamp, values)| {
+                            parse_timestamp(&timestamp).map(|dt| (dt, values))
+                        })
+                        .collect::<Vec<_>>();
+
+                    data.sort_by_key(|b| std::cmp::Reverse(b.0));
+                    return Ok(data);
+                }
+                Err(e) => {
+                    last_error = Some(e);
+                    tokio::time::sleep(Duration::from_secs(2)).await;
+                }
+            }
+            attempts -= 1;
+        }
+        Err(AlphaVantageError::Reqwest(last_error.unwrap()).into())
+    }
+}
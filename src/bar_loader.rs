@@ -0,0 +1,456 @@
+use crate::market_data::MinuteData;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use rust_decimal::Decimal;
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Names of the OHLCV columns in a bar file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BarColumns {
+    pub timestamp: String,
+    pub open: String,
+    pub high: String,
+    pub low: String,
+    pub close: String,
+    pub volume: String,
+}
+
+impl Default for BarColumns {
+    fn default() -> Self {
+        Self {
+            timestamp: "timestamp".to_string(),
+            open: "open".to_string(),
+            high: "high".to_string(),
+            low: "low".to_string(),
+            close: "close".to_string(),
+            volume: "volume".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TimestampFormat {
+    /// `chrono` strftime pattern for a date-time, or a bare date for daily
+    /// bars, in the format's time zone.
+    Pattern(String),
+    /// RFC 3339 with its own offset; the time zone is ignored.
+    Rfc3339,
+    UnixSeconds,
+    UnixMillis,
+    UnixMicros,
+    UnixNanos,
+}
+
+impl TimestampFormat {
+    fn parse(&self, text: &str, time_zone: Tz) -> Option<DateTime<Utc>> {
+        let text = text.trim();
+        match self {
+            TimestampFormat::Pattern(pattern) => {
+                let local = NaiveDateTime::parse_from_str(text, pattern)
+                    .or_else(|_| {
+                        NaiveDate::parse_from_str(text, pattern)
+                            .map(|date| date.and_hms_opt(0, 0, 0).unwrap_or_default())
+                    })
+                    .ok()?;
+                time_zone
+                    .from_local_datetime(&local)
+                    .earliest()
+                    .map(|dt| dt.with_timezone(&Utc))
+            }
+            TimestampFormat::Rfc3339 => DateTime::parse_from_rfc3339(text)
+                .ok()
+                .map(|dt| dt.with_timezone(&Utc)),
+            _ => self.unix(text.parse().ok()?),
+        }
+    }
+
+    /// Reads an integer epoch timestamp in the format's unit.
+    fn unix(&self, value: i64) -> Option<DateTime<Utc>> {
+        match self {
+            TimestampFormat::UnixSeconds => DateTime::from_timestamp(value, 0),
+            TimestampFormat::UnixMillis => DateTime::from_timestamp_millis(value),
+            TimestampFormat::UnixMicros => DateTime::from_timestamp_micros(value),
+            TimestampFormat::UnixNanos => Some(DateTime::from_timestamp_nanos(value)),
+            TimestampFormat::Pattern(_) | TimestampFormat::Rfc3339 => None,
+        }
+    }
+}
+
+/// Layout of a bar file: which columns hold what, and how to read times.
+#[derive(Debug, Clone)]
+pub struct BarFormat {
+    pub columns: BarColumns,
+    pub timestamp_format: TimestampFormat,
+    /// Zone of local timestamps read with `TimestampFormat::Pattern`.
+    pub time_zone: Tz,
+    /// CSV field separator.
+    pub delimiter: u8,
+}
+
+impl Default for BarFormat {
+    fn default() -> Self {
+        Self {
+            columns: BarColumns::default(),
+            timestamp_format: TimestampFormat::Pattern("%Y-%m-%d %H:%M:%S".to_string()),
+            time_zone: Tz::UTC,
+            delimiter: b',',
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum BarLoadError {
+    Io(std::io::Error),
+    Csv(csv::Error),
+    #[cfg(feature = "parquet")]
+    Parquet(parquet::errors::ParquetError),
+    MissingColumn(String),
+    /// `row` is the line for CSV and the 1-based row for Parquet.
+    InvalidValue {
+        row: u64,
+        column: String,
+        value: String,
+    },
+}
+
+impl std::fmt::Display for BarLoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BarLoadError::Io(e) => write!(f, "IO error: {}", e),
+            BarLoadError::Csv(e) => write!(f, "CSV error: {}", e),
+            #[cfg(feature = "parquet")]
+            BarLoadError::Parquet(e) => write!(f, "Parquet error: {}", e),
+            BarLoadError::MissingColumn(column) => write!(f, "missing column `{}`", column),
+            BarLoadError::InvalidValue { row, column, value } => {
+                write!(f, "row {}: invalid {} `{}`", row, column, value)
+            }
+        }
+    }
+}
+
+impl std::error::Error for BarLoadError {}
+
+impl From<std::io::Error> for BarLoadError {
+    fn from(e: std::io::Error) -> Self {
+        BarLoadError::Io(e)
+    }
+}
+
+impl From<csv::Error> for BarLoadError {
+    fn from(e: csv::Error) -> Self {
+        BarLoadError::Csv(e)
+    }
+}
+
+#[cfg(feature = "parquet")]
+impl From<parquet::errors::ParquetError> for BarLoadError {
+    fn from(e: parquet::errors::ParquetError) -> Self {
+        BarLoadError::Parquet(e)
+    }
+}
+
+/// Reads OHLCV bars from a CSV file with a header row. Bars come back
+/// oldest first, ready for `OrderBook::load_historical_bars`.
+pub fn load_csv(
+    path: impl AsRef<Path>,
+    format: &BarFormat,
+) -> Result<Vec<(DateTime<Utc>, MinuteData)>, BarLoadError> {
+    read_csv(std::fs::File::open(path)?, format)
+}
+
+/// Same as `load_csv`, from any reader.
+pub fn read_csv(
+    reader: impl Read,
+    format: &BarFormat,
+) -> Result<Vec<(DateTime<Utc>, MinuteData)>, BarLoadError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(format.delimiter)
+        .trim(csv::Trim::All)
+        .from_reader(reader);
+
+    let headers = reader.headers()?.clone();
+    let columns = &format.columns;
+    let index = |name: &String| {
+        headers
+            .iter()
+            .position(|header| header == name)
+            .ok_or_else(|| BarLoadError::MissingColumn(name.clone()))
+    };
+    let timestamp_index = index(&columns.timestamp)?;
+    let value_indices = [
+        (&columns.open, index(&columns.open)?),
+        (&columns.high, index(&columns.high)?),
+        (&columns.low, index(&columns.low)?),
+        (&columns.close, index(&columns.close)?),
+        (&columns.volume, index(&columns.volume)?),
+    ];
+
+    let mut bars = Vec::new();
+    for record in reader.records() {
+        let record = record?;
+        let row = record.position().map_or(0, |position| position.line());
+        let invalid = |column: &String, value: &str| BarLoadError::InvalidValue {
+            row,
+            column: column.clone(),
+            value: value.to_string(),
+        };
+
+        let text = record.get(timestamp_index).unwrap_or_default();
+        let timestamp = format
+            .timestamp_format
+            .parse(text, format.time_zone)
+            .ok_or_else(|| invalid(&columns.timestamp, text))?;
+
+        let mut values = [Decimal::ZERO; 5];
+        for (value, (column, i)) in values.iter_mut().zip(value_indices) {
+            let text = record.get(i).unwrap_or_default();
+            *value = Decimal::from_str(text)
+                .or_else(|_| Decimal::from_scientific(text))
+                .map_err(|_| invalid(column, text))?;
+        }
+        bars.push((timestamp, minute_data(values)));
+    }
+
+    bars.sort_by_key(|(timestamp, _)| *timestamp);
+    Ok(bars)
+}
+
+fn minute_data([open, high, low, close, volume]: [Decimal; 5]) -> MinuteData {
+    MinuteData {
+        open,
+        high,
+        low,
+        close,
+        volume,
+    }
+}
+
+/// Reads OHLCV bars from a Parquet file, oldest first. Timestamps may be
+/// Parquet timestamps or dates, integers in the format's Unix unit, or
+/// strings; prices may be any numeric type or numeric strings.
+#[cfg(feature = "parquet")]
+pub fn load_parquet(
+    path: impl AsRef<Path>,
+    format: &BarFormat,
+) -> Result<Vec<(DateTime<Utc>, MinuteData)>, BarLoadError> {
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use parquet::record::Field;
+
+    let reader = SerializedFileReader::new(std::fs::File::open(path)?)?;
+    let schema = reader.metadata().file_metadata().schema_descr_ptr();
+    let columns = &format.columns;
+    let names = [
+        &columns.timestamp,
+        &columns.open,
+        &columns.high,
+        &columns.low,
+        &columns.close,
+        &columns.volume,
+    ];
+    for name in names {
+        if !schema.columns().iter().any(|column| column.name() == name) {
+            return Err(BarLoadError::MissingColumn(name.clone()));
+        }
+    }
+
+    let mut bars = Vec::new();
+    for (i, row) in reader.get_row_iter(None)?.enumerate() {
+        let row = row?;
+        let fields: std::collections::HashMap<&String, &Field> = row.get_column_iter().collect();
+        let invalid = |column: &String| BarLoadError::InvalidValue {
+            row: i as u64 + 1,
+            column: column.clone(),
+            value: fields
+                .get(column)
+                .map_or_else(String::new, |field| field.to_string()),
+        };
+
+        let timestamp = fields
+            .get(&columns.timestamp)
+            .and_then(|field| parquet_timestamp(field, format))
+            .ok_or_else(|| invalid(&columns.timestamp))?;
+
+        let mut values = [Decimal::ZERO; 5];
+        for (value, column) in values.iter_mut().zip(&names[1..]) {
+            *value = fields
+                .get(column)
+                .and_then(|field| parquet_decimal(field))
+                .ok_or_else(|| invalid(column))?;
+        }
+        bars.push((timestamp, minute_data(values)));
+    }
+
+    bars.sort_by_key(|(timestamp, _)| *timestamp);
+    Ok(bars)
+}
+
+#[cfg(feature = "parquet")]
+fn parquet_timestamp(field: &parquet::record::Field, format: &BarFormat) -> Option<DateTime<Utc>> {
+    use parquet::record::Field;
+
+    match field {
+        Field::TimestampMillis(millis) => DateTime::from_timestamp_millis(*millis),
+        Field::TimestampMicros(micros) => DateTime::from_timestamp_micros(*micros),
+        Field::Date(days) => {
+            let date = NaiveDate::from_ymd_opt(1970, 1, 1)?
+                .checked_add_signed(chrono::Duration::days(i64::from(*days)))?;
+            format
+                .time_zone
+                .from_local_datetime(&date.and_hms_opt(0, 0, 0)?)
+                .earliest()
+                .map(|dt| dt.with_timezone(&Utc))
+        }
+        Field::Int(value) => format.timestamp_format.unix(i64::from(*value)),
+        Field::Long(value) => format.timestamp_format.unix(*value),
+        Field::Str(text) => format.timestamp_format.parse(text, format.time_zone),
+        _ => None,
+    }
+}
+
+#[cfg(feature = "parquet")]
+fn parquet_decimal(field: &parquet::record::Field) -> Option<Decimal> {
+    use parquet::record::Field;
+    use rust_decimal::prelude::FromPrimitive;
+
+    match field {
+        Field::Byte(value) => Some(Decimal::from(*value)),
+        Field::Short(value) => Some(Decimal::from(*value)),
+        Field::Int(value) => Some(Decimal::from(*value)),
+        Field::Long(value) => Some(Decimal::from(*value)),
+        Field::UByte(value) => Some(Decimal::from(*value)),
+        Field::UShort(value) => Some(Decimal::from(*value)),
+        Field::UInt(value) => Some(Decimal::from(*value)),
+        Field::ULong(value) => Some(Decimal::from(*value)),
+        Field::Float(value) => Decimal::from_f32(*value),
+        Field::Double(value) => Decimal::from_f64(*value),
+        Field::Decimal(value) => {
+            // Big-endian two's complement unscaled value
+            let bytes = value.data();
+            if bytes.is_empty() || bytes.len() > 16 {
+                return None;
+            }
+            let fill = if bytes[0] & 0x80 != 0 { 0xff } else { 0 };
+            let mut buf = [fill; 16];
+            buf[16 - bytes.len()..].copy_from_slice(bytes);
+            Decimal::try_from_i128_with_scale(i128::from_be_bytes(buf), value.scale() as u32).ok()
+        }
+        Field::Str(text) => Decimal::from_str(text).ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn format(timestamp_format: TimestampFormat) -> BarFormat {
+        BarFormat {
+            timestamp_format,
+            ..BarFormat::default()
+        }
+    }
+
+    fn first_time(csv: &str, format: &BarFormat) -> DateTime<Utc> {
+        read_csv(csv.as_bytes(), format).unwrap()[0].0
+    }
+
+    fn utc(text: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(text)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    #[test]
+    fn custom_columns_and_local_times_are_read() {
+        let format = BarFormat {
+            columns: BarColumns {
+                timestamp: "Date".to_string(),
+                open: "Open".to_string(),
+                high: "High".to_string(),
+                low: "Low".to_string(),
+                close: "Adj Close".to_string(),
+                volume: "Volume".to_string(),
+            },
+            timestamp_format: TimestampFormat::Pattern("%d/%m/%Y %H:%M".to_string()),
+            time_zone: chrono_tz::America::New_York,
+            delimiter: b';',
+        };
+        let csv = "Volume;Adj Close;Low;High;Open;Date\n\
+                   1200;101.5;99;102;100;15/01/2024 09:30\n";
+        let bars = read_csv(csv.as_bytes(), &format).unwrap();
+
+        assert_eq!(bars.len(), 1);
+        let (timestamp, bar) = &bars[0];
+        assert_eq!(*timestamp, utc("2024-01-15T14:30:00Z"));
+        assert_eq!(
+            (bar.open, bar.high, bar.low, bar.close, bar.volume),
+            (dec!(100), dec!(102), dec!(99), dec!(101.5), dec!(1200))
+        );
+    }
+
+    #[test]
+    fn daily_patterns_start_at_local_midnight() {
+        let format = BarFormat {
+            time_zone: chrono_tz::Europe::London,
+            ..format(TimestampFormat::Pattern("%Y-%m-%d".to_string()))
+        };
+        let csv = "timestamp,open,high,low,close,volume\n2024-07-01,1,1,1,1,1\n";
+        assert_eq!(first_time(csv, &format), utc("2024-06-30T23:00:00Z"));
+    }
+
+    #[test]
+    fn rfc3339_and_unix_timestamps_are_read() {
+        let expected = utc("2024-01-15T14:30:00Z");
+        let cases = [
+            (TimestampFormat::Rfc3339, "2024-01-15T09:30:00-05:00"),
+            (TimestampFormat::UnixSeconds, "1705329000"),
+            (TimestampFormat::UnixMillis, "1705329000000"),
+            (TimestampFormat::UnixMicros, "1705329000000000"),
+            (TimestampFormat::UnixNanos, "1705329000000000000"),
+        ];
+        for (timestamp_format, text) in cases {
+            let csv = format!("timestamp,open,high,low,close,volume\n{text},1,1,1,1,1\n");
+            assert_eq!(
+                first_time(&csv, &format(timestamp_format.clone())),
+                expected,
+                "{timestamp_format:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn missing_columns_are_named() {
+        let csv = "timestamp,open,high,low,volume\n";
+        assert!(matches!(
+            read_csv(csv.as_bytes(), &BarFormat::default()),
+            Err(BarLoadError::MissingColumn(column)) if column == "close"
+        ));
+    }
+
+    #[test]
+    fn invalid_values_give_their_line() {
+        let csv = "timestamp,open,high,low,close,volume\n\
+                   2024-01-15 14:30:00,1,1,1,1,1\n\
+                   2024-01-15 14:31:00,1,1,n/a,1,1\n";
+        match read_csv(csv.as_bytes(), &BarFormat::default()) {
+            Err(BarLoadError::InvalidValue { row, column, value }) => {
+                assert_eq!((row, column.as_str(), value.as_str()), (3, "low", "n/a"));
+            }
+            other => panic!("expected an invalid value, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn bars_come_back_oldest_first() {
+        let csv = "timestamp,open,high,low,close,volume\n\
+                   2024-01-15 14:32:00,3,3,3,3,1\n\
+                   2024-01-15 14:30:00,1,1,1,1,1\n\
+                   2024-01-15 14:31:00,2,2,2,2,1\n";
+        let bars = read_csv(csv.as_bytes(), &BarFormat::default()).unwrap();
+        let closes: Vec<Decimal> = bars.iter().map(|(_, bar)| bar.close).collect();
+        assert_eq!(closes, vec![dec!(1), dec!(2), dec!(3)]);
+    }
+}
@@ -0,0 +1,178 @@
+use crate::order_book::{Order, OrderSide};
+use crate::risk_management::RiskRejection;
+use crate::throttle::ThrottleRejection;
+use chrono::Utc;
+use rust_decimal::Decimal;
+
+/// Why the engine refused an order or a request against one.
+#[allow(dead_code)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum RejectReason {
+    InvalidQuantity,
+    InvalidPrice,
+    InvalidStopPrice,
+    InvalidDisplayQuantity,
+    /// GTD expiry time already passed on arrival.
+    ExpiryInPast,
+    /// Post-only order would have locked or crossed the spread.
+    WouldCrossSpread,
+    /// Pegged order arrived with no book price to peg to.
+    NoReferencePrice,
+    InvalidAmend(&'static str),
+    /// Cancel or amend of an order that is not working.
+    UnknownOrder,
+    /// Client order ID already taken by a working order of the same account.
+    DuplicateClientOrderId,
+    /// Failed a pre-trade risk check.
+    Risk(RiskRejection),
+    /// Account is over one of its message rate limits.
+    Throttled(ThrottleRejection),
+}
+
+impl std::fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RejectReason::InvalidQuantity => write!(f, "quantity must be positive"),
+            RejectReason::InvalidPrice => write!(f, "price must be positive"),
+            RejectReason::InvalidStopPrice => write!(f, "stop price must be positive"),
+            RejectReason::InvalidDisplayQuantity => {
+                write!(f, "display quantity must be positive")
+            }
+            RejectReason::ExpiryInPast => write!(f, "GTD expiry already passed"),
+            RejectReason::WouldCrossSpread => write!(f, "post-only order would cross the spread"),
+            RejectReason::NoReferencePrice => write!(f, "no reference price to peg to"),
+            RejectReason::InvalidAmend(reason) => write!(f, "invalid amend: {}", reason),
+            RejectReason::UnknownOrder => write!(f, "order is not working"),
+            RejectReason::DuplicateClientOrderId => {
+                write!(f, "client order ID is already in use")
+            }
+            RejectReason::Risk(rejection) => write!(f, "risk: {}", rejection),
+            RejectReason::Throttled(rejection) => write!(f, "throttled: {}", rejection),
+        }
+    }
+}
+
+/// Lifecycle event carried by an [`ExecutionReport`].
+#[allow(dead_code)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExecType {
+    /// Accepted and working.
+    New,
+    Rejected(RejectReason),
+    /// Stop order fired and released into the book.
+    Triggered,
+    PartialFill,
+    Fill,
+    /// Cancelled on request, or the unfilled rest of an IOC, FOK or market order.
+    Cancelled,
+    /// Amend applied; `leaves_quantity` and `price` carry the new terms.
+    Replaced,
+    Expired,
+    CancelRejected(RejectReason),
+    ReplaceRejected(RejectReason),
+}
+
+/// What happened to an order, published by the matching engine on every
+/// state change and keyed by the submitter's `account_id` and `client_order_id`.
+///
+/// The order's identity and side are `None` only on rejects of requests for
+/// an order the engine has no record of, for whatever the request left out.
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct ExecutionReport {
+    pub client_order_id: Option<u64>,
+    pub account_id: Option<u64>,
+    pub order_id: Option<u64>,
+    pub symbol: Option<String>,
+    pub side: Option<OrderSide>,
+    pub exec_type: ExecType,
+    /// Working price of the order.
+    pub price: Decimal,
+    /// Price and size of the fill for `PartialFill` and `Fill`, zero otherwise.
+    pub last_price: Decimal,
+    pub last_quantity: Decimal,
+    /// Quantity still open, hidden reserve included.
+    pub leaves_quantity: Decimal,
+    /// Engine-wide sequence number, stamped on publication.
+    pub sequence: u64,
+    pub timestamp: i64,
+}
+
+impl ExecutionReport {
+    pub fn new(order: &Order, exec_type: ExecType, leaves_quantity: Decimal) -> Self {
+        ExecutionReport {
+            client_order_id: Some(order.client_order_id),
+            account_id: Some(order.account_id),
+            order_id: Some(order.id),
+            symbol: Some(order.symbol.clone()),
+            side: Some(order.side.clone()),
+            exec_type,
+            price: order.price,
+            last_price: Decimal::ZERO,
+            last_quantity: Decimal::ZERO,
+            leaves_quantity,
+            sequence: 0,
+            timestamp: Utc::now().timestamp_nanos_opt().unwrap_or_default(),
+        }
+    }
+
+    /// Reject for a request naming by engine ID an order the engine has no
+    /// record of. Only the ID and symbol the request carried are filled in.
+    pub fn unknown_order(order_id: u64, symbol: &str, exec_type: ExecType) -> Self {
+        ExecutionReport {
+            order_id: Some(order_id),
+            symbol: Some(symbol.to_string()),
+            ..Self::unknown(exec_type)
+        }
+    }
+
+    /// Reject for a request naming by client order ID an order that is not
+    /// working. Only the account and client order ID are filled in.
+    pub fn unknown_client_order(
+        account_id: u64,
+        client_order_id: u64,
+        exec_type: ExecType,
+    ) -> Self {
+        ExecutionReport {
+            account_id: Some(account_id),
+            client_order_id: Some(client_order_id),
+            ..Self::unknown(exec_type)
+        }
+    }
+
+    fn unknown(exec_type: ExecType) -> Self {
+        ExecutionReport {
+            client_order_id: None,
+            account_id: None,
+            order_id: None,
+            symbol: None,
+            side: None,
+            exec_type,
+            price: Decimal::ZERO,
+            last_price: Decimal::ZERO,
+            last_quantity: Decimal::ZERO,
+            leaves_quantity: Decimal::ZERO,
+            sequence: 0,
+            timestamp: Utc::now().timestamp_nanos_opt().unwrap_or_default(),
+        }
+    }
+
+    /// Fill report; a fill that leaves no more than dust open is a full fill.
+    pub fn fill(
+        order: &Order,
+        price: Decimal,
+        quantity: Decimal,
+        leaves_quantity: Decimal,
+    ) -> Self {
+        let exec_type = if leaves_quantity > Decimal::new(1, 3) {
+            ExecType::PartialFill
+        } else {
+            ExecType::Fill
+        };
+        ExecutionReport {
+            last_price: price,
+            last_quantity: quantity,
+            ..Self::new(order, exec_type, leaves_quantity)
+        }
+    }
+}
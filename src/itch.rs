@@ -0,0 +1,892 @@
+use crate::market_data::MARKET_DATA_ACCOUNT_ID;
+use crate::order_book::{Order, OrderBook, OrderSide, OrderType, TimeInForce};
+use rust_decimal::Decimal;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+/// ITCH prices carry four implied decimal places.
+const PRICE_SCALE: u32 = 4;
+
+/// Fields every ITCH 5.0 message starts with, after the message type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ItchHeader {
+    pub stock_locate: u16,
+    pub tracking_number: u16,
+    /// Nanoseconds since midnight.
+    pub timestamp_ns: u64,
+}
+
+/// One ITCH 5.0 message, borrowing its text fields from the input buffer.
+/// Prices are raw wire values; `itch_price` converts them.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ItchMessage<'a> {
+    /// `S`. `event_code` is `O` start of messages, `S` start of system
+    /// hours, `Q` start of market hours, `M` end of market hours, `E` end
+    /// of system hours or `C` end of messages.
+    SystemEvent { header: ItchHeader, event_code: u8 },
+    /// `A`, or `F` when `mpid` carries the market participant attribution.
+    AddOrder {
+        header: ItchHeader,
+        order_ref: u64,
+        side: OrderSide,
+        shares: u32,
+        stock: &'a str,
+        price: u32,
+        mpid: Option<&'a str>,
+    },
+    /// `E`, at the resting order's price.
+    OrderExecuted {
+        header: ItchHeader,
+        order_ref: u64,
+        executed_shares: u32,
+        match_number: u64,
+    },
+    /// `C`, at a price other than the order's own. Non-printable executions
+    /// don't count towards the last sale.
+    OrderExecutedWithPrice {
+        header: ItchHeader,
+        order_ref: u64,
+        executed_shares: u32,
+        match_number: u64,
+        printable: bool,
+        price: u32,
+    },
+    /// `X`, a partial cancel.
+    OrderCancel {
+        header: ItchHeader,
+        order_ref: u64,
+        cancelled_shares: u32,
+    },
+    /// `D`
+    OrderDelete { header: ItchHeader, order_ref: u64 },
+    /// `U`. The original order is gone and the new one joins the back of
+    /// the queue, on the same side and symbol.
+    OrderReplace {
+        header: ItchHeader,
+        original_order_ref: u64,
+        new_order_ref: u64,
+        shares: u32,
+        price: u32,
+    },
+    /// `P`, an execution against a non-displayed order.
+    Trade {
+        header: ItchHeader,
+        order_ref: u64,
+        side: OrderSide,
+        shares: u32,
+        stock: &'a str,
+        price: u32,
+        match_number: u64,
+    },
+    /// Any other message type, left undecoded.
+    Other { message_type: u8, body: &'a [u8] },
+}
+
+impl ItchMessage<'_> {
+    pub fn header(&self) -> Option<&ItchHeader> {
+        match self {
+            ItchMessage::SystemEvent { header, .. }
+            | ItchMessage::AddOrder { header, .. }
+            | ItchMessage::OrderExecuted { header, .. }
+            | ItchMessage::OrderExecutedWithPrice { header, .. }
+            | ItchMessage::OrderCancel { header, .. }
+            | ItchMessage::OrderDelete { header, .. }
+            | ItchMessage::OrderReplace { header, .. }
+            | ItchMessage::Trade { header, .. } => Some(header),
+            ItchMessage::Other { .. } => None,
+        }
+    }
+}
+
+pub fn itch_price(raw: u32) -> Decimal {
+    Decimal::new(i64::from(raw), PRICE_SCALE)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ItchError {
+    /// The buffer ends partway through the message whose length prefix
+    /// starts at `offset`.
+    Truncated { offset: usize },
+    /// Shorter than the message type's fixed layout.
+    TooShort { message_type: u8, length: usize },
+    InvalidField {
+        message_type: u8,
+        field: &'static str,
+    },
+}
+
+impl std::fmt::Display for ItchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ItchError::Truncated { offset } => {
+                write!(f, "truncated message at byte {}", offset)
+            }
+            ItchError::TooShort {
+                message_type,
+                length,
+            } => write!(
+                f,
+                "message type {:?} too short: {} bytes",
+                *message_type as char, length
+            ),
+            ItchError::InvalidField {
+                message_type,
+                field,
+            } => write!(
+                f,
+                "message type {:?}: invalid {}",
+                *message_type as char, field
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ItchError {}
+
+/// Fixed length of each decoded message type, type byte included.
+fn message_length(message_type: u8) -> Option<usize> {
+    match message_type {
+        b'S' => Some(12),
+        b'A' => Some(36),
+        b'F' => Some(40),
+        b'E' => Some(31),
+        b'C' => Some(36),
+        b'X' => Some(23),
+        b'D' => Some(19),
+        b'U' => Some(35),
+        b'P' => Some(44),
+        _ => None,
+    }
+}
+
+fn u16_at(bytes: &[u8], at: usize) -> u16 {
+    u16::from_be_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+fn u48_at(bytes: &[u8], at: usize) -> u64 {
+    let mut buf = [0; 8];
+    buf[2..].copy_from_slice(&bytes[at..at + 6]);
+    u64::from_be_bytes(buf)
+}
+
+fn u64_at(bytes: &[u8], at: usize) -> u64 {
+    let mut buf = [0; 8];
+    buf.copy_from_slice(&bytes[at..at + 8]);
+    u64::from_be_bytes(buf)
+}
+
+/// Decodes one message, without its length prefix.
+pub fn parse_message(bytes: &[u8]) -> Result<ItchMessage<'_>, ItchError> {
+    let Some(&message_type) = bytes.first() else {
+        return Err(ItchError::TooShort {
+            message_type: 0,
+            length: 0,
+        });
+    };
+    let Some(length) = message_length(message_type) else {
+        return Ok(ItchMessage::Other {
+            message_type,
+            body: &bytes[1..],
+        });
+    };
+    if bytes.len() < length {
+        return Err(ItchError::TooShort {
+            message_type,
+            length: bytes.len(),
+        });
+    }
+
+    let invalid = |field| ItchError::InvalidField {
+        message_type,
+        field,
+    };
+    // Space-padded ASCII
+    let text = |at: usize, len: usize, field| {
+        std::str::from_utf8(&bytes[at..at + len])
+            .map(str::trim_end)
+            .map_err(|_| invalid(field))
+    };
+    let side = |at: usize| match bytes[at] {
+        b'B' => Ok(OrderSide::Buy),
+        b'S' => Ok(OrderSide::Sell),
+        _ => Err(invalid("side")),
+    };
+
+    let header = ItchHeader {
+        stock_locate: u16_at(bytes, 1),
+        tracking_number: u16_at(bytes, 3),
+        timestamp_ns: u48_at(bytes, 5),
+    };
+    let message = match message_type {
+        b'S' => ItchMessage::SystemEvent {
+            header,
+            event_code: bytes[11],
+        },
+        b'A' | b'F' => ItchMessage::AddOrder {
+            header,
+            order_ref: u64_at(bytes, 11),
+            side: side(19)?,
+            shares: u32_at(bytes, 20),
+            stock: text(24, 8, "stock")?,
+            price: u32_at(bytes, 32),
+            mpid: match message_type {
+                b'F' => Some(text(36, 4, "attribution")?),
+                _ => None,
+            },
+        },
+        b'E' => ItchMessage::OrderExecuted {
+            header,
+            order_ref: u64_at(bytes, 11),
+            executed_shares: u32_at(bytes, 19),
+            match_number: u64_at(bytes, 23),
+        },
+        b'C' => ItchMessage::OrderExecutedWithPrice {
+            header,
+            order_ref: u64_at(bytes, 11),
+            executed_shares: u32_at(bytes, 19),
+            match_number: u64_at(bytes, 23),
+            printable: match bytes[31] {
+                b'Y' => true,
+                b'N' => false,
+                _ => return Err(invalid("printable")),
+            },
+            price: u32_at(bytes, 32),
+        },
+        b'X' => ItchMessage::OrderCancel {
+            header,
+            order_ref: u64_at(bytes, 11),
+            cancelled_shares: u32_at(bytes, 19),
+        },
+        b'D' => ItchMessage::OrderDelete {
+            header,
+            order_ref: u64_at(bytes, 11),
+        },
+        b'U' => ItchMessage::OrderReplace {
+            header,
+            original_order_ref: u64_at(bytes, 11),
+            new_order_ref: u64_at(bytes, 19),
+            shares: u32_at(bytes, 27),
+            price: u32_at(bytes, 31),
+        },
+        b'P' => ItchMessage::Trade {
+            header,
+            order_ref: u64_at(bytes, 11),
+            side: side(19)?,
+            shares: u32_at(bytes, 20),
+            stock: text(24, 8, "stock")?,
+            price: u32_at(bytes, 32),
+            match_number: u64_at(bytes, 36),
+        },
+        _ => unreachable!("message_length covers every decoded type"),
+    };
+    Ok(message)
+}
+
+/// Messages from a buffer in NASDAQ's `BinaryFILE` layout, where each
+/// message follows its two-byte big-endian length. Stops after the first
+/// error.
+pub struct ItchReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+    failed: bool,
+}
+
+impl<'a> ItchReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            offset: 0,
+            failed: false,
+        }
+    }
+
+    /// Start of the next unread message.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+impl<'a> Iterator for ItchReader<'a> {
+    type Item = Result<ItchMessage<'a>, ItchError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = &self.data[self.offset..];
+        if self.failed || rest.is_empty() {
+            return None;
+        }
+
+        let length = match rest {
+            [high, low, ..] => u16::from_be_bytes([*high, *low]) as usize,
+            _ => 0,
+        };
+        let Some(bytes) = rest.get(2..2 + length) else {
+            self.failed = true;
+            return Some(Err(ItchError::Truncated {
+                offset: self.offset,
+            }));
+        };
+        self.offset += 2 + length;
+
+        let message = parse_message(bytes);
+        self.failed = message.is_err();
+        Some(message)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ItchBookStats {
+    pub messages: u64,
+    pub adds: u64,
+    pub executions: u64,
+    pub cancels: u64,
+    pub deletes: u64,
+    pub replaces: u64,
+    pub trades: u64,
+    pub system_events: u64,
+    /// Message types the builder doesn't decode.
+    pub skipped: u64,
+    /// Executions, cancels, deletes and replaces on a built symbol that name
+    /// an order the builder never saw added.
+    pub unknown_orders: u64,
+}
+
+/// Resting order as the feed last described it.
+#[derive(Debug, Clone)]
+struct ItchOrder {
+    stock_locate: u16,
+    side: OrderSide,
+    price: u32,
+    shares: u32,
+}
+
+/// Builds per-symbol books in an `OrderBook` from ITCH messages, keeping
+/// the feed's order-level state alongside. Book orders use ITCH order
+/// reference numbers as their IDs, so the book should not be shared with
+/// the matching engine.
+pub struct ItchBookBuilder {
+    book: Arc<OrderBook>,
+    // Symbols to build; empty builds every symbol
+    symbols: HashSet<String>,
+    locates: HashMap<u16, String>,
+    orders: HashMap<u64, ItchOrder>,
+    last_system_event: Option<u8>,
+    stats: ItchBookStats,
+}
+
+impl ItchBookBuilder {
+    pub fn new(book: Arc<OrderBook>, symbols: Vec<String>) -> Self {
+        Self {
+            book,
+            symbols: symbols.into_iter().collect(),
+            locates: HashMap::new(),
+            orders: HashMap::new(),
+            last_system_event: None,
+            stats: ItchBookStats::default(),
+        }
+    }
+
+    pub fn book(&self) -> &Arc<OrderBook> {
+        &self.book
+    }
+
+    pub fn stats(&self) -> ItchBookStats {
+        self.stats
+    }
+
+    /// Orders resting in the built books.
+    pub fn order_count(&self) -> usize {
+        self.orders.len()
+    }
+
+    pub fn last_system_event(&self) -> Option<u8> {
+        self.last_system_event
+    }
+
+    /// Applies every message in a `BinaryFILE` buffer, stopping at the
+    /// first malformed one.
+    pub fn apply_all(&mut self, data: &[u8]) -> Result<ItchBookStats, ItchError> {
+        for message in ItchReader::new(data) {
+            self.apply(&message?);
+        }
+        Ok(self.stats)
+    }
+
+    pub fn apply(&mut self, message: &ItchMessage) {
+        self.stats.messages += 1;
+        match message {
+            ItchMessage::SystemEvent { header, event_code } => {
+                self.stats.system_events += 1;
+                self.last_system_event = Some(*event_code);
+                log::info!(
+                    "ITCH system event {:?} at {}ns",
+                    *event_code as char,
+                    header.timestamp_ns
+                );
+            }
+            ItchMessage::AddOrder {
+                header,
+                order_ref,
+                side,
+                shares,
+                stock,
+                price,
+                ..
+            } => {
+                self.stats.adds += 1;
+                self.locates
+                    .entry(header.stock_locate)
+                    .or_insert_with(|| stock.to_string());
+                if self.is_built(header.stock_locate) {
+                    let order = ItchOrder {
+                        stock_locate: header.stock_locate,
+                        side: side.clone(),
+                        price: *price,
+                        shares: *shares,
+                    };
+                    self.add(*order_ref, order, header.timestamp_ns);
+                }
+            }
+            ItchMessage::OrderExecuted {
+                header,
+                order_ref,
+                executed_shares,
+                ..
+            } => {
+                self.stats.executions += 1;
+                if let Some(order) = self.reduce(header, *order_ref, *executed_shares) {
+                    self.record_trade(order.stock_locate, order.price);
+                }
+            }
+            ItchMessage::OrderExecutedWithPrice {
+                header,
+                order_ref,
+                executed_shares,
+                printable,
+                price,
+                ..
+            } => {
+                self.stats.executions += 1;
+                let order = self.reduce(header, *order_ref, *executed_shares);
+                if let Some(order) = order.filter(|_| *printable) {
+                    self.record_trade(order.stock_locate, *price);
+                }
+            }
+            ItchMessage::OrderCancel {
+                header,
+                order_ref,
+                cancelled_shares,
+            } => {
+                self.stats.cancels += 1;
+                self.reduce(header, *order_ref, *cancelled_shares);
+            }
+            ItchMessage::OrderDelete { header, order_ref } => {
+                self.stats.deletes += 1;
+                self.remove(header, *order_ref);
+            }
+            ItchMessage::OrderReplace {
+                header,
+                original_order_ref,
+                new_order_ref,
+                shares,
+                price,
+            } => {
+                self.stats.replaces += 1;
+                if let Some(original) = self.remove(header, *original_order_ref) {
+                    let order = ItchOrder {
+                        shares: *shares,
+                        price: *price,
+                        ..original
+                    };
+                    self.add(*new_order_ref, order, header.timestamp_ns);
+                }
+            }
+            ItchMessage::Trade { header, price, .. } => {
+                self.stats.trades += 1;
+                if self.is_built(header.stock_locate) {
+                    self.record_trade(header.stock_locate, *price);
+                }
+            }
+            ItchMessage::Other { .. } => self.stats.skipped += 1,
+        }
+    }
+
+    /// Whether the symbol behind a locate code is being built. Until an
+    /// add names the symbol, only an unfiltered builder claims it.
+    fn is_built(&self, stock_locate: u16) -> bool {
+        match self.locates.get(&stock_locate) {
+            Some(symbol) => self.symbols.is_empty() || self.symbols.contains(symbol),
+            None => self.symbols.is_empty(),
+        }
+    }
+
+    fn add(&mut self, order_ref: u64, order: ItchOrder, timestamp_ns: u64) {
+        let Some(symbol) = self.locates.get(&order.stock_locate) else {
+            return;
+        };
+        if order.shares == 0 || order.price == 0 {
+            return;
+        }
+        self.book.add_order(Order {
+            id: order_ref,
+            client_order_id: order_ref,
+            account_id: MARKET_DATA_ACCOUNT_ID,
+            symbol: symbol.clone(),
+            price: itch_price(order.price),
+            quantity: Decimal::from(order.shares),
+            order_type: OrderType::Limit,
+            side: order.side.clone(),
+            timestamp: timestamp_ns as i64,
+            time_in_force: TimeInForce::GoodTillCancel,
+            display_quantity: None,
+            hidden_quantity: Decimal::ZERO,
+        });
+        self.orders.insert(order_ref, order);
+    }
+
+    /// Takes shares off an order in place, keeping its priority, or removes
+    /// it once none are left. Returns the order as it was.
+    fn reduce(&mut self, header: &ItchHeader, order_ref: u64, shares: u32) -> Option<ItchOrder> {
+        let Some(order) = self.orders.get_mut(&order_ref) else {
+            self.note_unknown(header);
+            return None;
+        };
+        if shares >= order.shares {
+            return self.remove(header, order_ref);
+        }
+
+        let before = order.clone();
+        order.shares -= shares;
+        self.book
+            .amend_order(order_ref, None, Some(Decimal::from(order.shares)));
+        Some(before)
+    }
+
+    fn remove(&mut self, header: &ItchHeader, order_ref: u64) -> Option<ItchOrder> {
+        let Some(order) = self.orders.remove(&order_ref) else {
+            self.note_unknown(header);
+            return None;
+        };
+        self.book.take_order(order_ref);
+        Some(order)
+    }
+
+    fn note_unknown(&mut self, header: &ItchHeader) {
+        if self.is_built(header.stock_locate) {
+            self.stats.unknown_orders += 1;
+        }
+    }
+
+    fn record_trade(&self, stock_locate: u16, price: u32) {
+        if let Some(symbol) = self.locates.get(&stock_locate) {
+            self.book.record_trade_price(symbol, itch_price(price));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn header(stock_locate: u16, timestamp_ns: u64) -> ItchHeader {
+        ItchHeader {
+            stock_locate,
+            tracking_number: 7,
+            timestamp_ns,
+        }
+    }
+
+    fn text(bytes: &mut Vec<u8>, value: &str, len: usize) {
+        bytes.extend_from_slice(format!("{value:<len$}").as_bytes());
+    }
+
+    fn side(side: &OrderSide) -> u8 {
+        match side {
+            OrderSide::Buy => b'B',
+            OrderSide::Sell => b'S',
+        }
+    }
+
+    /// Wire form of a decoded message, without its length prefix.
+    fn encode(message: &ItchMessage) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let mut put_header = |kind: u8, header: &ItchHeader| {
+            bytes.push(kind);
+            bytes.extend_from_slice(&header.stock_locate.to_be_bytes());
+            bytes.extend_from_slice(&header.tracking_number.to_be_bytes());
+            bytes.extend_from_slice(&header.timestamp_ns.to_be_bytes()[2..]);
+        };
+        match message {
+            ItchMessage::SystemEvent { header, event_code } => {
+                put_header(b'S', header);
+                bytes.push(*event_code);
+            }
+            ItchMessage::AddOrder {
+                header,
+                order_ref,
+                side: order_side,
+                shares,
+                stock,
+                price,
+                mpid,
+            } => {
+                put_header(if mpid.is_some() { b'F' } else { b'A' }, header);
+                bytes.extend_from_slice(&order_ref.to_be_bytes());
+                bytes.push(side(order_side));
+                bytes.extend_from_slice(&shares.to_be_bytes());
+                text(&mut bytes, stock, 8);
+                bytes.extend_from_slice(&price.to_be_bytes());
+                if let Some(mpid) = mpid {
+                    text(&mut bytes, mpid, 4);
+                }
+            }
+            ItchMessage::OrderExecuted {
+                header,
+                order_ref,
+                executed_shares,
+                match_number,
+            } => {
+                put_header(b'E', header);
+                bytes.extend_from_slice(&order_ref.to_be_bytes());
+                bytes.extend_from_slice(&executed_shares.to_be_bytes());
+                bytes.extend_from_slice(&match_number.to_be_bytes());
+            }
+            ItchMessage::OrderExecutedWithPrice {
+                header,
+                order_ref,
+                executed_shares,
+                match_number,
+                printable,
+                price,
+            } => {
+                put_header(b'C', header);
+                bytes.extend_from_slice(&order_ref.to_be_bytes());
+                bytes.extend_from_slice(&executed_shares.to_be_bytes());
+                bytes.extend_from_slice(&match_number.to_be_bytes());
+                bytes.push(if *printable { b'Y' } else { b'N' });
+                bytes.extend_from_slice(&price.to_be_bytes());
+            }
+            ItchMessage::OrderCancel {
+                header,
+                order_ref,
+                cancelled_shares,
+            } => {
+                put_header(b'X', header);
+                bytes.extend_from_slice(&order_ref.to_be_bytes());
+                bytes.extend_from_slice(&cancelled_shares.to_be_bytes());
+            }
+            ItchMessage::OrderDelete { header, order_ref } => {
+                put_header(b'D', header);
+                bytes.extend_from_slice(&order_ref.to_be_bytes());
+            }
+            ItchMessage::OrderReplace {
+                header,
+                original_order_ref,
+                new_order_ref,
+                shares,
+                price,
+            } => {
+                put_header(b'U', header);
+                bytes.extend_from_slice(&original_order_ref.to_be_bytes());
+                bytes.extend_from_slice(&new_order_ref.to_be_bytes());
+                bytes.extend_from_slice(&shares.to_be_bytes());
+                bytes.extend_from_slice(&price.to_be_bytes());
+            }
+            ItchMessage::Trade {
+                header,
+                order_ref,
+                side: order_side,
+                shares,
+                stock,
+                price,
+                match_number,
+            } => {
+                put_header(b'P', header);
+                bytes.extend_from_slice(&order_ref.to_be_bytes());
+                bytes.push(side(order_side));
+                bytes.extend_from_slice(&shares.to_be_bytes());
+                text(&mut bytes, stock, 8);
+                bytes.extend_from_slice(&price.to_be_bytes());
+                bytes.extend_from_slice(&match_number.to_be_bytes());
+            }
+            ItchMessage::Other { message_type, body } => {
+                bytes.push(*message_type);
+                bytes.extend_from_slice(body);
+            }
+        }
+        bytes
+    }
+
+    /// `messages` in the `BinaryFILE` layout.
+    fn binary_file(messages: &[ItchMessage]) -> Vec<u8> {
+        let mut file = Vec::new();
+        for message in messages {
+            let bytes = encode(message);
+            file.extend_from_slice(&(bytes.len() as u16).to_be_bytes());
+            file.extend_from_slice(&bytes);
+        }
+        file
+    }
+
+    fn add(
+        locate: u16,
+        order_ref: u64,
+        side: OrderSide,
+        shares: u32,
+        stock: &str,
+        price: u32,
+    ) -> ItchMessage<'_> {
+        ItchMessage::AddOrder {
+            header: header(locate, order_ref),
+            order_ref,
+            side,
+            shares,
+            stock,
+            price,
+            mpid: None,
+        }
+    }
+
+    #[test]
+    fn every_decoded_message_type_round_trips() {
+        let messages = vec![
+            ItchMessage::SystemEvent {
+                header: header(0, 1),
+                event_code: b'O',
+            },
+            add(1, 2, OrderSide::Buy, 100, "AAPL", 1_899_900),
+            ItchMessage::AddOrder {
+                header: header(2, 3),
+                order_ref: 3,
+                side: OrderSide::Sell,
+                shares: 200,
+                stock: "BRK.A",
+                price: 4_000_000_000,
+                mpid: Some("GSCO"),
+            },
+            ItchMessage::OrderExecuted {
+                header: header(1, 4),
+                order_ref: 2,
+                executed_shares: 40,
+                match_number: 900,
+            },
+            ItchMessage::OrderExecutedWithPrice {
+                header: header(1, 5),
+                order_ref: 2,
+                executed_shares: 10,
+                match_number: 901,
+                printable: false,
+                price: 1_899_800,
+            },
+            ItchMessage::OrderCancel {
+                header: header(1, 6),
+                order_ref: 2,
+                cancelled_shares: 5,
+            },
+            ItchMessage::OrderReplace {
+                header: header(1, 7),
+                original_order_ref: 2,
+                new_order_ref: 8,
+                shares: 300,
+                price: 1_900_000,
+            },
+            ItchMessage::OrderDelete {
+                header: header(1, 8),
+                order_ref: 8,
+            },
+            ItchMessage::Trade {
+                header: header(1, 9),
+                order_ref: 0,
+                side: OrderSide::Buy,
+                shares: 100,
+                stock: "AAPL",
+                price: 1_899_950,
+                match_number: 902,
+            },
+            ItchMessage::Other {
+                message_type: b'R',
+                body: b"directory",
+            },
+        ];
+
+        let file = binary_file(&messages);
+        let read: Vec<ItchMessage> = ItchReader::new(&file).collect::<Result<_, _>>().unwrap();
+        assert_eq!(read, messages);
+        for message in &messages[..9] {
+            let bytes = encode(message);
+            assert_eq!(message_length(bytes[0]), Some(bytes.len()));
+        }
+    }
+
+    #[test]
+    fn malformed_messages_stop_the_reader() {
+        let mut file = binary_file(&[add(1, 1, OrderSide::Buy, 100, "AAPL", 10_000)]);
+        let first = file.len();
+        file.extend_from_slice(&[0, 36, b'A']);
+        let mut reader = ItchReader::new(&file);
+        assert!(reader.next().unwrap().is_ok());
+        assert_eq!(
+            reader.next(),
+            Some(Err(ItchError::Truncated { offset: first }))
+        );
+        assert_eq!(reader.next(), None);
+
+        let mut bytes = encode(&add(1, 1, OrderSide::Buy, 100, "AAPL", 10_000));
+        bytes[19] = b'Z';
+        assert_eq!(
+            parse_message(&bytes),
+            Err(ItchError::InvalidField {
+                message_type: b'A',
+                field: "side"
+            })
+        );
+        assert_eq!(
+            parse_message(&bytes[..20]),
+            Err(ItchError::TooShort {
+                message_type: b'A',
+                length: 20
+            })
+        );
+    }
+
+    #[test]
+    fn builder_tracks_orders_for_the_chosen_symbols() {
+        let file = binary_file(&[
+            add(1, 1, OrderSide::Buy, 100, "AAPL", 1_000_000),
+            add(1, 2, OrderSide::Buy, 100, "AAPL", 1_000_100),
+            add(2, 3, OrderSide::Sell, 100, "MSFT", 3_000_000),
+            ItchMessage::OrderExecuted {
+                header: header(1, 4),
+                order_ref: 2,
+                executed_shares: 30,
+                match_number: 1,
+            },
+            ItchMessage::OrderReplace {
+                header: header(1, 5),
+                original_order_ref: 1,
+                new_order_ref: 4,
+                shares: 50,
+                price: 1_000_200,
+            },
+            ItchMessage::OrderDelete {
+                header: header(1, 6),
+                order_ref: 99,
+            },
+        ]);
+        let mut builder = ItchBookBuilder::new(Arc::new(OrderBook::new()), vec!["AAPL".into()]);
+        let stats = builder.apply_all(&file).unwrap();
+
+        assert_eq!((stats.adds, stats.executions, stats.replaces), (3, 1, 1));
+        assert_eq!(stats.unknown_orders, 1);
+        assert_eq!(builder.order_count(), 2);
+        let book = builder.book();
+        assert_eq!(book.get_best_bid("AAPL"), Some(dec!(100.02)));
+        assert_eq!(book.get_order(2).map(|o| o.quantity), Some(dec!(70)));
+        assert_eq!(book.get_last_trade_price("AAPL"), Some(dec!(100.01)));
+        assert_eq!(book.get_best_ask("MSFT"), None);
+    }
+}
@@ -0,0 +1,17 @@
+pub mod alpha_vantage;
+pub mod bar_loader;
+pub mod execution_report;
+pub mod itch;
+pub mod lobster;
+pub mod margin;
+pub mod market_data;
+pub mod matching_engine;
+pub mod order_book;
+pub mod position_ledger;
+pub mod risk_analytics;
+pub mod risk_config;
+pub mod risk_management;
+pub mod short_sale;
+pub mod throttle;
+pub mod tick_replay;
+pub mod ticks;
@@ -0,0 +1,659 @@
+use crate::market_data::MARKET_DATA_ACCOUNT_ID;
+use crate::order_book::{BookSnapshot, Order, OrderBook, OrderSide, OrderType, TimeInForce};
+use rust_decimal::Decimal;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+
+/// LOBSTER prices are dollars times 10,000.
+const PRICE_SCALE: u32 = 4;
+
+/// Dummy prices marking empty levels in the orderbook file.
+const EMPTY_ASK_PRICE: i64 = 9_999_999_999;
+const EMPTY_BID_PRICE: i64 = -9_999_999_999;
+
+/// IDs of the orders standing in for liquidity that was already resting
+/// when the file starts; LOBSTER's own IDs stay well below this.
+const SEED_ORDER_ID_BASE: u64 = 1 << 63;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HaltState {
+    Halted,
+    /// Quoting has resumed but trading has not.
+    Quoting,
+    Trading,
+}
+
+/// Event type column of a LOBSTER message file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LobsterEvent {
+    Submit,
+    PartialCancel,
+    Delete,
+    /// Execution against a visible limit order.
+    Execute,
+    /// Execution against a hidden order; the visible book is unchanged.
+    HiddenExecute,
+    /// Auction cross; the visible book is unchanged.
+    CrossTrade,
+    Halt(HaltState),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LobsterMessage {
+    /// Seconds after midnight.
+    pub time: Decimal,
+    pub event: LobsterEvent,
+    pub order_id: u64,
+    pub size: Decimal,
+    pub price: Decimal,
+    /// Side of the limit order the message refers to, including the
+    /// resting side of an execution.
+    pub side: OrderSide,
+}
+
+/// First level at which the rebuilt book and the orderbook file disagree
+/// after a message. `None` is an empty level.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    /// 1-based line in the message file.
+    pub message: u64,
+    pub time: Decimal,
+    pub side: OrderSide,
+    /// 1-based level.
+    pub level: usize,
+    pub expected: Option<(Decimal, Decimal)>,
+    pub actual: Option<(Decimal, Decimal)>,
+}
+
+impl std::fmt::Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let show = |level: &Option<(Decimal, Decimal)>| match level {
+            Some((price, size)) => format!("{} @ {}", size, price),
+            None => "empty".to_string(),
+        };
+        write!(
+            f,
+            "message {} at {}s: {:?} level {} expected {}, rebuilt {}",
+            self.message,
+            self.time,
+            self.side,
+            self.level,
+            show(&self.expected),
+            show(&self.actual)
+        )
+    }
+}
+
+/// Execution of an order that the rebuilt book has queued behind others at
+/// its price, so time priority there differs from the exchange's.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueueDivergence {
+    /// 1-based line in the message file.
+    pub message: u64,
+    pub time: Decimal,
+    pub order_id: u64,
+    pub price: Decimal,
+    /// Orders ahead of it in the rebuilt queue.
+    pub ahead: usize,
+}
+
+impl std::fmt::Display for QueueDivergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "message {} at {}s: executed order {} has {} orders ahead of it at {}",
+            self.message, self.time, self.order_id, self.ahead, self.price
+        )
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LobsterReport {
+    pub messages: u64,
+    pub submits: u64,
+    pub cancels: u64,
+    pub deletes: u64,
+    pub executions: u64,
+    pub hidden_executions: u64,
+    pub cross_trades: u64,
+    pub halts: u64,
+    /// Cancels and executions of orders the rebuilt book has no trace of.
+    pub unknown_orders: u64,
+    pub divergences: Vec<Divergence>,
+    pub queue_divergences: Vec<QueueDivergence>,
+}
+
+#[derive(Debug)]
+pub enum LobsterError {
+    Io(std::io::Error),
+    Csv(csv::Error),
+    /// `file` is `message` or `orderbook`.
+    Malformed {
+        file: &'static str,
+        line: u64,
+        message: String,
+    },
+    /// The two files must have one line per message.
+    LengthMismatch,
+}
+
+impl std::fmt::Display for LobsterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LobsterError::Io(e) => write!(f, "IO error: {}", e),
+            LobsterError::Csv(e) => write!(f, "CSV error: {}", e),
+            LobsterError::Malformed {
+                file,
+                line,
+                message,
+            } => write!(f, "{} file line {}: {}", file, line, message),
+            LobsterError::LengthMismatch => {
+                write!(f, "message and orderbook files differ in length")
+            }
+        }
+    }
+}
+
+impl std::error::Error for LobsterError {}
+
+impl From<std::io::Error> for LobsterError {
+    fn from(e: std::io::Error) -> Self {
+        LobsterError::Io(e)
+    }
+}
+
+impl From<csv::Error> for LobsterError {
+    fn from(e: csv::Error) -> Self {
+        LobsterError::Csv(e)
+    }
+}
+
+fn csv_reader(reader: impl Read) -> csv::Reader<impl Read> {
+    csv::ReaderBuilder::new()
+        .has_headers(false)
+        .trim(csv::Trim::All)
+        .from_reader(reader)
+}
+
+fn parse_message(record: &csv::StringRecord) -> Result<LobsterMessage, LobsterError> {
+    let line = record.position().map_or(0, |position| position.line());
+    let malformed = |message: String| LobsterError::Malformed {
+        file: "message",
+        line,
+        message,
+    };
+    if record.len() < 6 {
+        return Err(malformed(format!(
+            "expected 6 fields, got {}",
+            record.len()
+        )));
+    }
+    let integer = |i: usize| {
+        record[i]
+            .parse::<i64>()
+            .map_err(|_| malformed(format!("invalid integer `{}`", &record[i])))
+    };
+
+    let raw_price = integer(4)?;
+    let event = match integer(1)? {
+        1 => LobsterEvent::Submit,
+        2 => LobsterEvent::PartialCancel,
+        3 => LobsterEvent::Delete,
+        4 => LobsterEvent::Execute,
+        5 => LobsterEvent::HiddenExecute,
+        6 => LobsterEvent::CrossTrade,
+        7 => LobsterEvent::Halt(match raw_price {
+            -1 => HaltState::Halted,
+            0 => HaltState::Quoting,
+            _ => HaltState::Trading,
+        }),
+        kind => return Err(malformed(format!("unknown event type {}", kind))),
+    };
+
+    Ok(LobsterMessage {
+        time: Decimal::from_str(&record[0])
+            .map_err(|_| malformed(format!("invalid time `{}`", &record[0])))?,
+        event,
+        order_id: integer(2)?.max(0) as u64,
+        size: Decimal::from(integer(3)?),
+        price: Decimal::new(raw_price, PRICE_SCALE),
+        side: if integer(5)? > 0 {
+            OrderSide::Buy
+        } else {
+            OrderSide::Sell
+        },
+    })
+}
+
+/// Reads one orderbook row: ask price, ask size, bid price, bid size for
+/// each level, best first. Empty levels are dropped.
+fn parse_levels(record: &csv::StringRecord) -> Result<BookSnapshot, LobsterError> {
+    let line = record.position().map_or(0, |position| position.line());
+    let malformed = |message: String| LobsterError::Malformed {
+        file: "orderbook",
+        line,
+        message,
+    };
+    if record.is_empty() || !record.len().is_multiple_of(4) {
+        return Err(malformed(format!(
+            "expected 4 fields per level, got {}",
+            record.len()
+        )));
+    }
+    let values = record
+        .iter()
+        .map(|field| {
+            field
+                .parse::<i64>()
+                .map_err(|_| malformed(format!("invalid integer `{}`", field)))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut snapshot = BookSnapshot::default();
+    for level in values.chunks(4) {
+        let [ask, ask_size, bid, bid_size] = [level[0], level[1], level[2], level[3]];
+        if ask != EMPTY_ASK_PRICE && ask_size > 0 {
+            snapshot
+                .asks
+                .push((Decimal::new(ask, PRICE_SCALE), Decimal::from(ask_size)));
+        }
+        if bid != EMPTY_BID_PRICE && bid_size > 0 {
+            snapshot
+                .bids
+                .push((Decimal::new(bid, PRICE_SCALE), Decimal::from(bid_size)));
+        }
+    }
+    Ok(snapshot)
+}
+
+/// Order book state rebuilt from a LOBSTER message file.
+struct Rebuild<'a> {
+    book: &'a OrderBook,
+    symbol: &'a str,
+    // One order per level resting before the first message
+    seeds: HashMap<(bool, Decimal), u64>,
+    report: LobsterReport,
+}
+
+impl Rebuild<'_> {
+    /// Loads the book as it stood before `first`, given the state after it.
+    fn seed(&mut self, first: &LobsterMessage, after: &BookSnapshot) {
+        let mut before = after.clone();
+        let delta = match first.event {
+            LobsterEvent::Submit => -first.size,
+            LobsterEvent::PartialCancel | LobsterEvent::Delete | LobsterEvent::Execute => {
+                first.size
+            }
+            _ => Decimal::ZERO,
+        };
+        if !delta.is_zero() {
+            let levels = match first.side {
+                OrderSide::Buy => &mut before.bids,
+                OrderSide::Sell => &mut before.asks,
+            };
+            match levels.iter_mut().find(|(price, _)| *price == first.price) {
+                Some(level) => level.1 += delta,
+                None if delta > Decimal::ZERO => levels.push((first.price, delta)),
+                None => {}
+            }
+        }
+
+        let sides = [
+            (OrderSide::Buy, before.bids),
+            (OrderSide::Sell, before.asks),
+        ];
+        for (side, levels) in sides {
+            for (price, size) in levels {
+                if size <= Decimal::ZERO {
+                    continue;
+                }
+                let id = SEED_ORDER_ID_BASE + self.seeds.len() as u64;
+                self.seeds.insert((side == OrderSide::Buy, price), id);
+                self.add(id, side.clone(), price, size, 0);
+            }
+        }
+    }
+
+    fn add(&self, id: u64, side: OrderSide, price: Decimal, quantity: Decimal, timestamp: i64) {
+        self.book.add_order(Order {
+            id,
+            client_order_id: id,
+            account_id: MARKET_DATA_ACCOUNT_ID,
+            symbol: self.symbol.to_string(),
+            price,
+            quantity,
+            order_type: OrderType::Limit,
+            side,
+            timestamp,
+            time_in_force: TimeInForce::GoodTillCancel,
+            display_quantity: None,
+            hidden_quantity: Decimal::ZERO,
+        });
+    }
+
+    /// The order a message refers to, or the seed order at its price if it
+    /// was resting before the file starts.
+    fn find(&self, message: &LobsterMessage) -> Option<Order> {
+        self.book.get_order(message.order_id).or_else(|| {
+            self.seeds
+                .get(&(message.side == OrderSide::Buy, message.price))
+                .and_then(|id| self.book.get_order(*id))
+        })
+    }
+
+    /// Orders queued ahead of `order` at its price.
+    fn queued_ahead(&self, order: &Order) -> usize {
+        let position = match order.side {
+            OrderSide::Buy => self.book.bids.get(self.symbol).and_then(|levels| {
+                let queue = levels.get(&Reverse(order.price))?;
+                queue.iter().position(|resting| resting.id == order.id)
+            }),
+            OrderSide::Sell => self.book.asks.get(self.symbol).and_then(|levels| {
+                let queue = levels.get(&order.price)?;
+                queue.iter().position(|resting| resting.id == order.id)
+            }),
+        };
+        position.unwrap_or(0)
+    }
+
+    /// Takes `size` off the order a message refers to.
+    fn reduce(&mut self, message: &LobsterMessage) {
+        let Some(order) = self.find(message) else {
+            self.report.unknown_orders += 1;
+            return;
+        };
+
+        let remaining = order.quantity - message.size;
+        if remaining > Decimal::ZERO {
+            self.book.amend_order(order.id, None, Some(remaining));
+        } else {
+            self.book.take_order(order.id);
+        }
+    }
+
+    fn apply(&mut self, line: u64, message: &LobsterMessage) {
+        self.report.messages += 1;
+        match message.event {
+            LobsterEvent::Submit => {
+                self.report.submits += 1;
+                let timestamp = (message.time * Decimal::from(1_000_000_000))
+                    .trunc()
+                    .try_into()
+                    .unwrap_or_default();
+                self.add(
+                    message.order_id,
+                    message.side.clone(),
+                    message.price,
+                    message.size,
+                    timestamp,
+                );
+            }
+            LobsterEvent::PartialCancel => {
+                self.report.cancels += 1;
+                self.reduce(message);
+            }
+            LobsterEvent::Delete => {
+                self.report.deletes += 1;
+                self.reduce(message);
+            }
+            LobsterEvent::Execute => {
+                self.report.executions += 1;
+                // Executions hit the head of the queue, so anything ahead of
+                // the order means the rebuilt priority is off
+                if let Some(order) = self.find(message) {
+                    let ahead = self.queued_ahead(&order);
+                    if ahead > 0 {
+                        self.report.queue_divergences.push(QueueDivergence {
+                            message: line,
+                            time: message.time,
+                            order_id: message.order_id,
+                            price: message.price,
+                            ahead,
+                        });
+                    }
+                }
+                self.reduce(message);
+                self.book.record_trade_price(self.symbol, message.price);
+            }
+            LobsterEvent::HiddenExecute => {
+                self.report.hidden_executions += 1;
+                self.book.record_trade_price(self.symbol, message.price);
+            }
+            LobsterEvent::CrossTrade => {
+                self.report.cross_trades += 1;
+                self.book.record_trade_price(self.symbol, message.price);
+            }
+            LobsterEvent::Halt(state) => {
+                self.report.halts += 1;
+                log::info!(
+                    "{} trading state at {}s: {:?}",
+                    self.symbol,
+                    message.time,
+                    state
+                );
+            }
+        }
+    }
+
+    /// Compares the top of the rebuilt book with the orderbook file row.
+    fn check(
+        &mut self,
+        line: u64,
+        message: &LobsterMessage,
+        expected: &BookSnapshot,
+        levels: usize,
+    ) {
+        let actual = self.book.get_book_snapshot(self.symbol, levels);
+        let sides = [
+            (OrderSide::Sell, &expected.asks, &actual.asks),
+            (OrderSide::Buy, &expected.bids, &actual.bids),
+        ];
+        for (side, expected, actual) in sides {
+            let level = (0..levels).find(|i| expected.get(*i) != actual.get(*i));
+            if let Some(level) = level {
+                self.report.divergences.push(Divergence {
+                    message: line,
+                    time: message.time,
+                    side,
+                    level: level + 1,
+                    expected: expected.get(level).copied(),
+                    actual: actual.get(level).copied(),
+                });
+            }
+        }
+    }
+}
+
+/// Rebuilds `symbol` in `book` from a LOBSTER message file, checking it
+/// against the matching orderbook file after every message. Liquidity
+/// resting before the first message is seeded from the first orderbook row,
+/// one order per level. Executions of orders not at the head of their queue
+/// are reported too. Divergences are reported rather than corrected, so
+/// one early mismatch will usually repeat on later messages.
+pub fn import(
+    book: &OrderBook,
+    symbol: &str,
+    messages: impl Read,
+    orderbook: impl Read,
+) -> Result<LobsterReport, LobsterError> {
+    let mut messages = csv_reader(messages).into_records();
+    let mut rows = csv_reader(orderbook).into_records();
+    let mut rebuild = Rebuild {
+        book,
+        symbol,
+        seeds: HashMap::new(),
+        report: LobsterReport::default(),
+    };
+
+    loop {
+        let (record, row) = match (messages.next(), rows.next()) {
+            (Some(record), Some(row)) => (record?, row?),
+            (None, None) => break,
+            _ => return Err(LobsterError::LengthMismatch),
+        };
+        let message = parse_message(&record)?;
+        let expected = parse_levels(&row)?;
+        let line = record.position().map_or(0, |position| position.line());
+
+        if rebuild.report.messages == 0 {
+            rebuild.seed(&message, &expected);
+        }
+        rebuild.apply(line, &message);
+        rebuild.check(line, &message, &expected, row.len() / 4);
+    }
+
+    for divergence in rebuild.report.divergences.iter().take(1) {
+        log::warn!("LOBSTER rebuild of {} diverged: {}", symbol, divergence);
+    }
+    for divergence in rebuild.report.queue_divergences.iter().take(1) {
+        log::warn!(
+            "LOBSTER rebuild of {} out of priority: {}",
+            symbol,
+            divergence
+        );
+    }
+    Ok(rebuild.report)
+}
+
+pub fn import_files(
+    book: &OrderBook,
+    symbol: &str,
+    message_path: impl AsRef<Path>,
+    orderbook_path: impl AsRef<Path>,
+) -> Result<LobsterReport, LobsterError> {
+    import(
+        book,
+        symbol,
+        std::fs::File::open(message_path)?,
+        std::fs::File::open(orderbook_path)?,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn run(messages: &[&str], rows: &[&str]) -> (OrderBook, LobsterReport) {
+        let book = OrderBook::new();
+        let report = import(
+            &book,
+            "AAPL",
+            messages.join("\n").as_bytes(),
+            rows.join("\n").as_bytes(),
+        )
+        .unwrap();
+        (book, report)
+    }
+
+    #[test]
+    fn rebuilt_book_matches_the_orderbook_file() {
+        let messages = [
+            "34200.1,1,1,100,1000000,1",
+            "34200.2,1,2,50,1010000,-1",
+            "34200.3,2,1,30,1000000,1",
+            "34200.4,1,3,20,1000000,1",
+            "34200.5,4,1,70,1000000,1",
+            "34200.6,3,2,50,1010000,-1",
+        ];
+        let rows = [
+            "9999999999,0,1000000,100",
+            "1010000,50,1000000,100",
+            "1010000,50,1000000,70",
+            "1010000,50,1000000,90",
+            "1010000,50,1000000,20",
+            "9999999999,0,1000000,20",
+        ];
+        let (book, report) = run(&messages, &rows);
+
+        assert_eq!(report.messages, 6);
+        assert_eq!(
+            (
+                report.submits,
+                report.cancels,
+                report.executions,
+                report.deletes
+            ),
+            (3, 1, 1, 1)
+        );
+        assert_eq!(report.divergences, vec![]);
+        assert_eq!(report.queue_divergences, vec![]);
+        assert_eq!(book.get_best_bid("AAPL"), Some(dec!(100)));
+        assert_eq!(
+            book.get_order(3).map(|order| order.quantity),
+            Some(dec!(20))
+        );
+        assert_eq!(book.get_last_trade_price("AAPL"), Some(dec!(100)));
+    }
+
+    #[test]
+    fn liquidity_resting_before_the_file_is_seeded() {
+        let messages = ["34200.1,4,7,40,1010000,-1", "34200.2,3,8,60,1010000,-1"];
+        let rows = ["1010000,60,1000000,10", "9999999999,0,1000000,10"];
+        let (book, report) = run(&messages, &rows);
+
+        assert_eq!(report.divergences, vec![]);
+        assert_eq!(report.queue_divergences, vec![]);
+        assert_eq!(report.unknown_orders, 0);
+        assert_eq!(book.get_best_ask("AAPL"), None);
+        assert_eq!(book.get_best_bid("AAPL"), Some(dec!(100)));
+    }
+
+    #[test]
+    fn execution_behind_the_head_of_the_queue_is_reported() {
+        let messages = [
+            "34200.1,1,1,100,1000000,1",
+            "34200.2,1,2,50,1000000,1",
+            "34200.3,4,2,50,1000000,1",
+        ];
+        let rows = [
+            "9999999999,0,1000000,100",
+            "9999999999,0,1000000,150",
+            "9999999999,0,1000000,100",
+        ];
+        let (_, report) = run(&messages, &rows);
+
+        assert_eq!(report.divergences, vec![]);
+        assert_eq!(
+            report.queue_divergences,
+            vec![QueueDivergence {
+                message: 3,
+                time: dec!(34200.3),
+                order_id: 2,
+                price: dec!(100),
+                ahead: 1,
+            }]
+        );
+    }
+
+    #[test]
+    fn level_mismatch_is_reported() {
+        let (_, report) = run(&["34200.1,1,1,100,1000000,1"], &["9999999999,0,1000000,90"]);
+        assert_eq!(
+            report.divergences,
+            vec![Divergence {
+                message: 1,
+                time: dec!(34200.1),
+                side: OrderSide::Buy,
+                level: 1,
+                expected: Some((dec!(100), dec!(90))),
+                actual: Some((dec!(100), dec!(100))),
+            }]
+        );
+    }
+
+    #[test]
+    fn files_of_different_lengths_are_refused() {
+        let book = OrderBook::new();
+        let result = import(
+            &book,
+            "AAPL",
+            "34200.1,1,1,100,1000000,1".as_bytes(),
+            "".as_bytes(),
+        );
+        assert!(matches!(result, Err(LobsterError::LengthMismatch)));
+    }
+}
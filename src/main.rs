@@ -1,18 +1,16 @@
-mod market_data;
-mod matching_engine;
-mod order_book;
-mod risk_management;
-
-use crate::{
-    market_data::{fetch_market_data, EfficientMarketDataBuffer, MarketDataManager},
+use hft_simulator::{
+    alpha_vantage::AlphaVantageProvider,
+    market_data::{self, EfficientMarketDataBuffer, MarketDataManager, MarketDataProvider},
     matching_engine::{EngineMessage, MatchingEngine},
-    order_book::{Order, OrderBook, OrderSide, OrderType},
+    order_book::{Order, OrderBook, OrderSide, OrderType, TimeInForce},
+    risk_config::{self, RiskConfig},
     risk_management::RiskManager,
 };
 use rand::Rng;
 use reqwest::Client;
 use rust_decimal::{prelude::FromPrimitive, Decimal};
 use std::{
+    path::PathBuf,
     sync::{
         atomic::{AtomicU64, Ordering},
         Arc,
@@ -21,8 +19,14 @@ use std::{
 };
 use tokio::{signal, sync::Mutex};
 
-// Define a static atomic counter for unique order IDs
-static ORDER_ID_COUNTER: AtomicU64 = AtomicU64::new(0);
+// Client order IDs for the random order loop; the engine assigns order IDs
+static CLIENT_ORDER_ID_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+// Random orders are spread over this many accounts, numbered from 1
+const SIMULATED_ACCOUNTS: u64 = 8;
+
+// Risk limits and accounts, unless RISK_CONFIG names another file
+const DEFAULT_RISK_CONFIG: &str = "risk.toml";
 
 #[tokio::main]
 async fn main() {
@@ -34,18 +38,27 @@ async fn main() {
     // Load .env file
     dotenv::dotenv().ok();
 
-    // Shared HTTP client for market data
-    let http_client = Arc::new(Client::new());
+    // Market data source shared by the feed task and the data manager
+    let market_data: Arc<dyn MarketDataProvider> =
+        Arc::new(AlphaVantageProvider::from_env(Client::new()));
 
     // Shared order book
     let order_book = Arc::new(OrderBook::new());
 
-    // Initialize risk manager with position limits
-    let risk_manager = Arc::new({
-        let rm = RiskManager::new(Decimal::from(1_000_000));
-        rm.set_position_limit("AAPL", Decimal::from(10_000));
-        rm
-    });
+    // Load risk limits and accounts
+    let risk_config_path = PathBuf::from(
+        std::env::var("RISK_CONFIG").unwrap_or_else(|_| DEFAULT_RISK_CONFIG.to_string()),
+    );
+    let risk_config = match RiskConfig::load(&risk_config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            log::error!("Invalid risk config: {}", e);
+            return;
+        }
+    };
+
+    // Initialize risk manager
+    let risk_manager = Arc::new(RiskManager::new(risk_config.max_order_size));
 
     // Initialize Efficient Market Data Buffer
     let market_data_buffer = Arc::new(EfficientMarketDataBuffer::new(100));
@@ -55,17 +68,31 @@ async fn main() {
         MatchingEngine::new(order_book.clone(), risk_manager.clone());
     let matching_engine = Arc::new(matching_engine);
 
+    // Apply the risk config and reload it when the file changes
+    risk_config.apply(None, &matching_engine);
+    // The bar feed quotes from one account, so AAPL's position limit would
+    // soon stop it
+    market_data::exempt_market_data_account(&matching_engine);
+    risk_config::watch(
+        risk_config_path,
+        risk_config,
+        matching_engine.clone(),
+        Duration::from_secs(5),
+    );
+
     // Market data task: Convert market data to orders and send to matching engine
     tokio::spawn({
         let engine_tx_clone = engine_tx.clone();
-        let http_client = http_client.clone();
+        let market_data = market_data.clone();
+        let market_data_buffer = market_data_buffer.clone();
         async move {
             let mut interval = tokio::time::interval(Duration::from_secs(60));
             loop {
                 interval.tick().await;
-                match fetch_market_data(&http_client, "AAPL").await {
+                match market_data.bars("AAPL").await {
                     Ok(data) => {
                         log::info!("Received {} market data points", data.len());
+                        market_data_buffer.add_data(data.clone());
                         for (_, md) in data.iter() {
                             let orders = md.to_orders("AAPL", Decimal::new(1, 2));
                             for order in orders {
@@ -89,7 +116,7 @@ async fn main() {
         let message_rx = message_rx.clone();
         async move {
             let mut lock = message_rx.lock().await;
-            engine.run(&mut *lock).await;
+            engine.run(&mut lock).await;
         }
     });
 
@@ -124,7 +151,8 @@ async fn main() {
     });
 
     // Market Data Manager task: Periodically update market data
-    let mut market_data_manager = MarketDataManager::new(&["AAPL".to_string()]);
+    let mut market_data_manager =
+        MarketDataManager::new(market_data.clone(), &["AAPL".to_string()]);
     tokio::spawn(async move {
         let mut interval = tokio::time::interval(Duration::from_secs(60));
         loop {
@@ -138,11 +166,15 @@ async fn main() {
     // Market Data Buffer Analysis Task: Periodically analyze buffered data
     tokio::spawn({
         let market_data_buffer = market_data_buffer.clone();
+        let risk_manager = risk_manager.clone();
         async move {
             let mut interval = tokio::time::interval(Duration::from_secs(30));
             loop {
                 interval.tick().await;
                 let recent_data = market_data_buffer.get_recent_data();
+                risk_manager
+                    .analytics()
+                    .load_minute_data("AAPL", &recent_data);
 
                 if !recent_data.is_empty() {
                     // Simple analysis: calculate average close price
@@ -158,6 +190,9 @@ async fn main() {
         }
     });
 
+    let order_engine = matching_engine.clone();
+    matching_engine.clone().start_expiry_scheduler(100).await;
+    matching_engine.clone().start_risk_monitor(1000).await;
     matching_engine.start_reporting(10).await;
 
     // Shutdown listener
@@ -174,7 +209,9 @@ async fn main() {
             let quantity = Decimal::from(rng.gen_range(10..1001));
 
             let order = Order {
-                id: ORDER_ID_COUNTER.fetch_add(1, Ordering::Relaxed),
+                id: 0,
+                client_order_id: CLIENT_ORDER_ID_COUNTER.fetch_add(1, Ordering::Relaxed),
+                account_id: rng.gen_range(1..=SIMULATED_ACCOUNTS),
                 symbol: "AAPL".into(),
                 price,
                 quantity,
@@ -187,25 +224,33 @@ async fn main() {
                 timestamp: chrono::Utc::now()
                     .timestamp_nanos_opt()
                     .expect("Failed to get nanosecond timestamp"),
+                time_in_force: TimeInForce::Day,
+                display_quantity: None,
+                hidden_quantity: Decimal::ZERO,
             };
 
-            if risk_manager.validate_order(&order) {
-                if let Err(e) = engine_tx.send(EngineMessage::NewOrder(order.clone())) {
-                    log::error!("Failed to send order: {:?}", e);
-                }
+            if let Err(e) = engine_tx.send(EngineMessage::NewOrder(order.clone())) {
+                log::error!("Failed to send order: {:?}", e);
+            }
 
-                // 25% chance to cancel the order after 1 second
-                if rng.gen::<f64>() < 0.25 {
-                    let tx = engine_tx.clone();
-                    let symbol = order.symbol.clone();
-                    let order_id = order.id;
-                    tokio::spawn(async move {
-                        tokio::time::sleep(Duration::from_secs(1)).await;
-                        if let Err(e) = tx.send(EngineMessage::CancelOrder { symbol, order_id }) {
-                            log::error!("Failed to cancel order: {:?}", e);
-                        }
-                    });
-                }
+            // 25% chance to cancel the order after 1 second
+            if rng.gen::<f64>() < 0.25 {
+                let tx = engine_tx.clone();
+                let engine = order_engine.clone();
+                let symbol = order.symbol.clone();
+                let account_id = order.account_id;
+                let client_order_id = order.client_order_id;
+                tokio::spawn(async move {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    // Nothing to cancel once the order has filled
+                    let Some(order_id) = engine.order_id_for_client(account_id, client_order_id)
+                    else {
+                        return;
+                    };
+                    if let Err(e) = tx.send(EngineMessage::CancelOrder { symbol, order_id }) {
+                        log::error!("Failed to cancel order: {:?}", e);
+                    }
+                });
             }
 
             tokio::time::sleep(Duration::from_millis(100)).await;
@@ -0,0 +1,160 @@
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// Share of the maintenance requirement equity has to cover before an
+/// account is flagged for forced liquidation rather than a margin call.
+const LIQUIDATION_FRACTION: Decimal = Decimal::from_parts(5, 0, 0, false, 1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccountType {
+    /// Pays in full and cannot sell short.
+    Cash,
+    /// Borrows against its positions at the symbol's margin rates.
+    Margin,
+}
+
+/// Shares of a position's value the account must fund itself, when opening
+/// it and while holding it.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MarginRates {
+    pub initial: Decimal,
+    pub maintenance: Decimal,
+}
+
+impl Default for MarginRates {
+    /// Reg-T: 50% initial, 25% maintenance.
+    fn default() -> Self {
+        Self {
+            initial: Decimal::new(5, 1),
+            maintenance: Decimal::new(25, 2),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MarginStatus {
+    Healthy,
+    /// Equity is below the maintenance requirement.
+    MarginCall {
+        equity: Decimal,
+        requirement: Decimal,
+    },
+    /// Equity no longer covers half the maintenance requirement.
+    Liquidation {
+        equity: Decimal,
+        requirement: Decimal,
+    },
+}
+
+impl MarginStatus {
+    pub fn from_equity(equity: Decimal, requirement: Decimal) -> Self {
+        if equity >= requirement {
+            MarginStatus::Healthy
+        } else if equity >= requirement * LIQUIDATION_FRACTION {
+            MarginStatus::MarginCall {
+                equity,
+                requirement,
+            }
+        } else {
+            MarginStatus::Liquidation {
+                equity,
+                requirement,
+            }
+        }
+    }
+}
+
+/// Capital of one account. Positions live in `RiskManager`; this holds the
+/// cash they were bought with and the margin held back for working orders.
+#[derive(Debug, Clone)]
+pub struct MarginAccount {
+    account_type: AccountType,
+    cash: Decimal,
+    // Order ID to initial margin held for its unfilled quantity
+    reservations: HashMap<u64, Decimal>,
+    status: MarginStatus,
+}
+
+impl MarginAccount {
+    pub fn new(account_type: AccountType, cash: Decimal) -> Self {
+        Self {
+            account_type,
+            cash,
+            reservations: HashMap::new(),
+            status: MarginStatus::Healthy,
+        }
+    }
+
+    pub fn account_type(&self) -> AccountType {
+        self.account_type
+    }
+
+    pub fn cash(&self) -> Decimal {
+        self.cash
+    }
+
+    pub fn reserved(&self) -> Decimal {
+        self.reservations.values().sum()
+    }
+
+    pub fn status(&self) -> MarginStatus {
+        self.status
+    }
+
+    /// Rates that apply to this account in a symbol with `symbol_rates`.
+    /// Cash accounts fund everything and so never face a maintenance call.
+    pub fn rates(&self, symbol_rates: MarginRates) -> MarginRates {
+        match self.account_type {
+            AccountType::Cash => MarginRates {
+                initial: Decimal::ONE,
+                maintenance: Decimal::ZERO,
+            },
+            AccountType::Margin => symbol_rates,
+        }
+    }
+
+    /// Settles a fill; `quantity` is negative for sells.
+    pub fn apply_fill(&mut self, quantity: Decimal, price: Decimal) {
+        self.cash -= quantity * price;
+    }
+
+    pub fn reserve(&mut self, order_id: u64, amount: Decimal) {
+        if amount.is_zero() {
+            self.reservations.remove(&order_id);
+        } else {
+            self.reservations.insert(order_id, amount);
+        }
+    }
+
+    pub fn release(&mut self, order_id: u64) {
+        self.reservations.remove(&order_id);
+    }
+
+    /// Records the latest status and returns it if it changed.
+    pub fn update_status(&mut self, status: MarginStatus) -> Option<MarginStatus> {
+        let changed = std::mem::discriminant(&status) != std::mem::discriminant(&self.status);
+        self.status = status;
+        changed.then_some(status)
+    }
+}
+
+/// Margin position of an account at current marks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MarginSummary {
+    /// Cash plus the market value of all positions.
+    pub equity: Decimal,
+    pub initial_requirement: Decimal,
+    pub maintenance_requirement: Decimal,
+    /// Initial margin held back for working orders.
+    pub reserved: Decimal,
+}
+
+impl MarginSummary {
+    /// Initial margin still free to open new positions with.
+    pub fn excess(&self) -> Decimal {
+        self.equity - self.initial_requirement - self.reserved
+    }
+}
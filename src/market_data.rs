@@ -1,13 +1,13 @@
-use crate::order_book::{Order, OrderSide, OrderType};
+use crate::matching_engine::MatchingEngine;
+use crate::order_book::{Order, OrderSide, OrderType, TimeInForce};
+use async_trait::async_trait;
 use chrono::{DateTime, Utc};
-use reqwest::Client;
 use rust_decimal::Decimal;
 use serde::{Deserialize, Deserializer};
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 use std::{
     collections::{HashMap, VecDeque},
     str::FromStr,
-    time::Duration,
 };
 
 #[allow(dead_code)]
@@ -25,7 +25,6 @@ impl EfficientMarketDataBuffer {
         }
     }
 
-    #[allow(dead_code)]
     pub fn add_data(&self, data: Vec<(DateTime<Utc>, MinuteData)>) {
         let mut buffer = self.buffer.lock().unwrap();
 
@@ -50,36 +49,83 @@ impl EfficientMarketDataBuffer {
 /// Custom error type for market data fetching.
 #[derive(Debug)]
 pub enum MarketDataError {
-    MissingApiKey,
-    Reqwest(reqwest::Error),
     ApiError(String),
+    /// A failure specific to one provider, such as its transport or
+    /// credentials.
+    Provider(Box<dyn std::error::Error + Send + Sync>),
+    /// The provider has no such feed.
+    Unsupported {
+        provider: String,
+        feed: &'static str,
+    },
 }
 
 impl std::fmt::Display for MarketDataError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            MarketDataError::MissingApiKey => {
-                write!(f, "ALPHA_VANTAGE_API_KEY environment variable not set")
-            }
-            MarketDataError::Reqwest(e) => write!(f, "Reqwest error: {}", e),
             MarketDataError::ApiError(msg) => write!(f, "API error: {}", msg),
+            MarketDataError::Provider(e) => write!(f, "{}", e),
+            MarketDataError::Unsupported { provider, feed } => {
+                write!(f, "{} does not provide {}", provider, feed)
+            }
         }
     }
 }
 
-impl std::error::Error for MarketDataError {}
-
-impl From<reqwest::Error> for MarketDataError {
-    fn from(e: reqwest::Error) -> Self {
-        MarketDataError::Reqwest(e)
+impl std::error::Error for MarketDataError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MarketDataError::Provider(e) => Some(e.as_ref()),
+            _ => None,
+        }
     }
 }
 
-/// Structure representing the Alpha Vantage API response.
-#[derive(Debug, Deserialize)]
-struct AlphaVantageResponse {
-    #[serde(rename = "Time Series (1min)")]
-    time_series: HashMap<String, MinuteData>,
+/// Best bid and offer at one instant.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quote {
+    pub timestamp: DateTime<Utc>,
+    pub bid: Decimal,
+    pub bid_size: Decimal,
+    pub ask: Decimal,
+    pub ask_size: Decimal,
+}
+
+/// One print on the consolidated tape.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MarketTrade {
+    pub timestamp: DateTime<Utc>,
+    pub price: Decimal,
+    pub size: Decimal,
+}
+
+/// A source of market data: a vendor API, a file replay, a synthetic or
+/// mock feed. Providers that lack quotes or trades keep the default
+/// methods, which return `MarketDataError::Unsupported`.
+#[async_trait]
+pub trait MarketDataProvider: Send + Sync {
+    /// Shown in logs and errors.
+    fn name(&self) -> &str;
+
+    /// Recent one-minute bars, newest first.
+    async fn bars(&self, symbol: &str)
+        -> Result<Vec<(DateTime<Utc>, MinuteData)>, MarketDataError>;
+
+    /// Latest top of book.
+    async fn quote(&self, _symbol: &str) -> Result<Quote, MarketDataError> {
+        Err(MarketDataError::Unsupported {
+            provider: self.name().to_string(),
+            feed: "quotes",
+        })
+    }
+
+    /// Recent trades, oldest first.
+    async fn trades(&self, _symbol: &str) -> Result<Vec<MarketTrade>, MarketDataError> {
+        Err(MarketDataError::Unsupported {
+            provider: self.name().to_string(),
+            feed: "trades",
+        })
+    }
 }
 
 /// Structure representing one minute of market data.
@@ -107,55 +153,14 @@ where
     Decimal::from_str(&s).map_err(serde::de::Error::custom)
 }
 
-/// Fetch market data from Alpha Vantage using a shared `reqwest::Client` for efficiency.
-pub async fn fetch_market_data(
-    client: &Client,
-    symbol: &str,
-) -> Result<Vec<(DateTime<Utc>, MinuteData)>, MarketDataError> {
-    let api_key =
-        std::env::var("ALPHA_VANTAGE_API_KEY").map_err(|_| MarketDataError::MissingApiKey)?;
-    let url = format!(
-        "https://www.alphavantage.co/query?function=TIME_SERIES_INTRADAY&symbol={}&interval=1min&apikey={}",
-        symbol, api_key
-    );
-
-    let mut attempts = 3;
-    let mut last_error: Option<reqwest::Error> = None;
-
-    while attempts > 0 {
-        match client.get(&url).send().await {
-            Ok(response) => {
-                let status = response.status();
-                if !status.is_success() {
-                    let body = response.text().await.unwrap_or_default();
-                    return Err(MarketDataError::ApiError(format!(
-                        "Status {}: {}",
-                        status, body
-                    )));
-                }
+/// Account that owns the liquidity generated from market data bars.
+pub const MARKET_DATA_ACCOUNT_ID: u64 = 0;
 
-                let json_response = response.json::<AlphaVantageResponse>().await?;
-                let mut data = json_response
-                    .time_series
-                    .into_iter()
-                    .filter_map(|(timestamp, values)| {
-                        DateTime::parse_from_str(&timestamp, "%Y-%m-%d %H:%M:%S")
-                            .ok()
-                            .map(|dt| (dt.with_timezone(&Utc), values))
-                    })
-                    .collect::<Vec<_>>();
-
-                data.sort_by(|a, b| b.0.cmp(&a.0));
-                return Ok(data);
-            }
-            Err(e) => {
-                last_error = Some(e);
-                tokio::time::sleep(Duration::from_secs(2)).await;
-            }
-        }
-        attempts -= 1;
-    }
-    Err(MarketDataError::Reqwest(last_error.unwrap()))
+/// Lets the market data account hold whatever position the feed builds up
+/// and quote at the feed's rate. Its orders are still checked for size,
+/// price collars and liquidity.
+pub fn exempt_market_data_account(engine: &MatchingEngine) {
+    engine.exempt_account(MARKET_DATA_ACCOUNT_ID);
 }
 
 impl MinuteData {
@@ -167,22 +172,32 @@ impl MinuteData {
 
         vec![
             Order {
-                id: ts,
+                id: 0,
+                client_order_id: ts,
+                account_id: MARKET_DATA_ACCOUNT_ID,
                 symbol: symbol.into(),
                 price: round_to_tick(self.close - spread, tick_size),
                 quantity: base_quantity,
                 order_type: OrderType::Limit,
                 side: OrderSide::Buy,
                 timestamp: ts as i64,
+                time_in_force: TimeInForce::GoodTillCancel,
+                display_quantity: None,
+                hidden_quantity: Decimal::ZERO,
             },
             Order {
-                id: ts + 1,
+                id: 0,
+                client_order_id: ts + 1,
+                account_id: MARKET_DATA_ACCOUNT_ID,
                 symbol: symbol.into(),
                 price: round_to_tick(self.close + spread, tick_size),
                 quantity: base_quantity,
                 order_type: OrderType::Limit,
                 side: OrderSide::Sell,
                 timestamp: ts as i64,
+                time_in_force: TimeInForce::GoodTillCancel,
+                display_quantity: None,
+                hidden_quantity: Decimal::ZERO,
             },
         ]
     }
@@ -193,19 +208,22 @@ fn round_to_tick(price: Decimal, tick_size: Decimal) -> Decimal {
     (price / tick_size).round() * tick_size
 }
 
-/// Market data manager that caches and updates market data.
+/// Market data manager that caches and updates bars, and quotes where the
+/// provider has them, from any provider.
 #[allow(dead_code)]
 pub struct MarketDataManager {
-    client: Client,
+    provider: Arc<dyn MarketDataProvider>,
     cache: HashMap<String, Vec<(DateTime<Utc>, MinuteData)>>,
+    quotes: HashMap<String, Quote>,
     last_update: HashMap<String, DateTime<Utc>>,
 }
 
 impl MarketDataManager {
-    pub fn new(symbols: &[String]) -> Self {
+    pub fn new(provider: Arc<dyn MarketDataProvider>, symbols: &[String]) -> Self {
         MarketDataManager {
-            client: Client::new(),
+            provider,
             cache: symbols.iter().map(|s| (s.clone(), Vec::new())).collect(),
+            quotes: HashMap::new(),
             last_update: HashMap::new(),
         }
     }
@@ -216,12 +234,13 @@ impl MarketDataManager {
         let mut tasks = Vec::new();
 
         for symbol in symbols {
-            let client = self.client.clone();
+            let provider = self.provider.clone();
             tasks.push(tokio::spawn(async move {
-                tokio::time::sleep(Duration::from_secs(12)).await;
-                fetch_market_data(&client, &symbol)
+                let quote = provider.quote(&symbol).await;
+                provider
+                    .bars(&symbol)
                     .await
-                    .map(|data| (symbol, data))
+                    .map(|data| (symbol, data, quote))
             }));
         }
 
@@ -230,7 +249,14 @@ impl MarketDataManager {
                 .await
                 .unwrap_or_else(|_| Err(MarketDataError::ApiError("Task panicked".into())))
             {
-                Ok((symbol, mut data)) => {
+                Ok((symbol, mut data, quote)) => {
+                    match quote {
+                        Ok(quote) => {
+                            self.quotes.insert(symbol.clone(), quote);
+                        }
+                        Err(MarketDataError::Unsupported { .. }) => {}
+                        Err(e) => log::error!("Failed to update quote for {}: {:?}", symbol, e),
+                    }
                     data.truncate(100);
                     if let Some(latest) = data.first() {
                         self.last_update.insert(symbol.clone(), latest.0);
@@ -248,8 +274,152 @@ impl MarketDataManager {
         self.cache.get(symbol).map(|v| v.as_slice())
     }
 
+    /// Latest quote, if the provider has quotes.
+    #[allow(dead_code)]
+    pub fn quote(&self, symbol: &str) -> Option<&Quote> {
+        self.quotes.get(symbol)
+    }
+
+    #[allow(dead_code)]
+    pub fn provider(&self) -> &Arc<dyn MarketDataProvider> {
+        &self.provider
+    }
+
     #[allow(dead_code)]
     pub fn last_update(&self, symbol: &str) -> Option<DateTime<Utc>> {
         self.last_update.get(symbol).copied()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    /// Serves a fixed bar and quote per symbol and counts requests.
+    struct MockProvider {
+        quotes: bool,
+        requests: Mutex<Vec<String>>,
+    }
+
+    impl MockProvider {
+        fn new(quotes: bool) -> Arc<Self> {
+            Arc::new(Self {
+                quotes,
+                requests: Mutex::new(Vec::new()),
+            })
+        }
+
+        fn bar(close: Decimal) -> MinuteData {
+            MinuteData {
+                open: close,
+                high: close,
+                low: close,
+                close,
+                volume: dec!(1_000),
+            }
+        }
+
+        fn time(minute: i64) -> DateTime<Utc> {
+            DateTime::from_timestamp(1_700_000_000 + minute * 60, 0).unwrap()
+        }
+    }
+
+    #[async_trait]
+    impl MarketDataProvider for MockProvider {
+        fn name(&self) -> &str {
+            "mock"
+        }
+
+        async fn bars(
+            &self,
+            symbol: &str,
+        ) -> Result<Vec<(DateTime<Utc>, MinuteData)>, MarketDataError> {
+            self.requests.lock().unwrap().push(format!("bars {symbol}"));
+            Ok(vec![
+                (Self::time(1), Self::bar(dec!(101))),
+                (Self::time(0), Self::bar(dec!(100))),
+            ])
+        }
+
+        async fn quote(&self, symbol: &str) -> Result<Quote, MarketDataError> {
+            if !self.quotes {
+                return Err(MarketDataError::Unsupported {
+                    provider: self.name().to_string(),
+                    feed: "quotes",
+                });
+            }
+            self.requests
+                .lock()
+                .unwrap()
+                .push(format!("quote {symbol}"));
+            Ok(Quote {
+                timestamp: Self::time(1),
+                bid: dec!(100.99),
+                bid_size: dec!(5),
+                ask: dec!(101.01),
+                ask_size: dec!(7),
+            })
+        }
+    }
+
+    /// Bars only, keeping the default `quote` and `trades`.
+    struct BarsOnly;
+
+    #[async_trait]
+    impl MarketDataProvider for BarsOnly {
+        fn name(&self) -> &str {
+            "bars only"
+        }
+
+        async fn bars(
+            &self,
+            _symbol: &str,
+        ) -> Result<Vec<(DateTime<Utc>, MinuteData)>, MarketDataError> {
+            Ok(Vec::new())
+        }
+    }
+
+    #[tokio::test]
+    async fn update_data_pulls_bars_and_quotes_from_the_provider() {
+        let provider = MockProvider::new(true);
+        let symbols = ["AAPL".to_string(), "MSFT".to_string()];
+        let mut manager = MarketDataManager::new(provider.clone(), &symbols);
+
+        manager.update_data().await.unwrap();
+
+        let mut requests = provider.requests.lock().unwrap().clone();
+        requests.sort();
+        assert_eq!(
+            requests,
+            vec!["bars AAPL", "bars MSFT", "quote AAPL", "quote MSFT"]
+        );
+        for symbol in &symbols {
+            let bars = manager.get_data(symbol).unwrap();
+            assert_eq!(bars.len(), 2);
+            assert_eq!(bars[0].1.close, dec!(101));
+            assert_eq!(manager.last_update(symbol), Some(MockProvider::time(1)));
+            assert_eq!(manager.quote(symbol).map(|q| q.ask), Some(dec!(101.01)));
+        }
+    }
+
+    #[tokio::test]
+    async fn providers_without_quotes_still_update_bars() {
+        let mut manager = MarketDataManager::new(MockProvider::new(false), &["AAPL".to_string()]);
+        manager.update_data().await.unwrap();
+        assert_eq!(manager.get_data("AAPL").map(|bars| bars.len()), Some(2));
+        assert_eq!(manager.quote("AAPL"), None);
+    }
+
+    #[tokio::test]
+    async fn quotes_and_trades_are_unsupported_by_default() {
+        assert!(matches!(
+            BarsOnly.quote("AAPL").await,
+            Err(MarketDataError::Unsupported { provider, feed: "quotes" }) if provider == "bars only"
+        ));
+        assert!(matches!(
+            BarsOnly.trades("AAPL").await,
+            Err(MarketDataError::Unsupported { feed: "trades", .. })
+        ));
+    }
+}
@@ -1,29 +1,127 @@
-use crate::order_book::{Order, OrderBook, OrderSide, OrderType};
-use crate::risk_management::RiskManager;
+use crate::execution_report::{ExecType, ExecutionReport, RejectReason};
+use crate::order_book::{AmendResult, Order, OrderBook, OrderSide, OrderType, TimeInForce};
+use crate::risk_management::{AtomicDecimal, HaltReason, RiskManager};
+use crate::throttle::{Throttle, ThrottleLimits};
+use chrono::{DateTime, Duration, NaiveTime, Utc};
+use dashmap::DashMap;
 use log::{info, warn};
+use parking_lot::{Mutex, RwLock};
 use rust_decimal::Decimal;
 use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::Instant;
-use tokio::sync::mpsc;
+use tokio::sync::{broadcast, mpsc};
 
 #[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub struct Trade {
     pub id: u64,
+    pub sequence: u64,
     pub symbol: String,
     pub price: Decimal,
     pub quantity: Decimal,
     pub buyer_id: u64,
     pub seller_id: u64,
+    pub buyer_account: u64,
+    pub seller_account: u64,
     pub timestamp: i64,
 }
 
 #[allow(dead_code)]
 pub enum EngineMessage {
     NewOrder(Order),
-    CancelOrder { symbol: String, order_id: u64 },
+    CancelOrder {
+        symbol: String,
+        order_id: u64,
+    },
+    /// Changes the price and/or total quantity of a resting order. A quantity
+    /// cut keeps time priority; a price change or quantity increase loses it.
+    ModifyOrder {
+        symbol: String,
+        order_id: u64,
+        new_price: Option<Decimal>,
+        new_quantity: Option<Decimal>,
+    },
     BatchOrders(Vec<Order>),
+    /// Cancels a working order by the submitter's own ID. Does nothing if
+    /// the order has already filled or been cancelled.
+    CancelClientOrder {
+        account_id: u64,
+        client_order_id: u64,
+    },
+}
+
+/// What happens when an incoming order meets a resting order from the same
+/// account.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SelfTradePrevention {
+    /// Cancel the rest of the incoming order.
+    #[default]
+    CancelNewest,
+    /// Cancel the resting order and keep matching.
+    CancelOldest,
+    CancelBoth,
+    /// Reduce both orders by the smaller quantity and cancel whichever is
+    /// left with nothing.
+    DecrementAndCancel,
+}
+
+/// Trades and leftover quantity from matching one incoming order.
+struct MatchOutcome {
+    trades: Vec<Trade>,
+    remaining_qty: Decimal,
+    /// Self-trade prevention cancelled whatever was left of the incoming order.
+    self_trade_cancelled: bool,
+}
+
+impl MatchOutcome {
+    fn new(quantity: Decimal) -> Self {
+        Self {
+            trades: Vec::new(),
+            remaining_qty: quantity,
+            self_trade_cancelled: false,
+        }
+    }
+}
+
+/// Source of engine-owned identifiers. Order and trade IDs each count up from
+/// one; the sequence number is shared by trades and execution reports so
+/// consumers can put every engine event in a single order.
+pub struct Sequencer {
+    next_order_id: AtomicU64,
+    next_trade_id: AtomicU64,
+    next_sequence: AtomicU64,
+}
+
+impl Default for Sequencer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sequencer {
+    pub fn new() -> Self {
+        Self {
+            next_order_id: AtomicU64::new(1),
+            next_trade_id: AtomicU64::new(1),
+            next_sequence: AtomicU64::new(1),
+        }
+    }
+
+    pub fn next_order_id(&self) -> u64 {
+        self.next_order_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub fn next_trade_id(&self) -> u64 {
+        self.next_trade_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub fn next_sequence(&self) -> u64 {
+        self.next_sequence.fetch_add(1, Ordering::Relaxed)
+    }
 }
 
 #[derive(Clone)]
@@ -31,11 +129,91 @@ pub struct MatchingEngine {
     order_book: Arc<OrderBook>,
     risk_manager: Arc<RiskManager>,
     metrics: Arc<EngineMetrics>,
+    expiries: Arc<ExpiryScheduler>,
+    reports: broadcast::Sender<ExecutionReport>,
+    sequencer: Arc<Sequencer>,
+    // (account, client order ID) to engine order ID for orders still working
+    client_orders: Arc<DashMap<(u64, u64), u64>>,
+    self_trade_prevention: Arc<RwLock<SelfTradePrevention>>,
+    throttle: Arc<Throttle>,
+}
+
+/// Reports buffered per subscriber before the slowest starts losing them.
+const REPORT_CHANNEL_CAPACITY: usize = 65_536;
+
+/// Deadlines for resting Day and GTD orders, drained by the expiry task.
+struct ExpiryScheduler {
+    deadlines: Mutex<BTreeMap<DateTime<Utc>, Vec<u64>>>,
+    session_close: RwLock<NaiveTime>,
+    // The close the session rolls over at, set on the first check
+    next_rollover: Mutex<Option<DateTime<Utc>>>,
+}
+
+impl ExpiryScheduler {
+    fn new() -> Self {
+        Self {
+            deadlines: Mutex::new(BTreeMap::new()),
+            // 16:00 New York during daylight saving time
+            session_close: RwLock::new(NaiveTime::from_hms_opt(20, 0, 0).unwrap()),
+            next_rollover: Mutex::new(None),
+        }
+    }
+
+    /// When an order placed at `now` with `time_in_force` expires, if ever.
+    fn deadline(&self, time_in_force: TimeInForce, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match time_in_force {
+            TimeInForce::Day => {
+                let close = now
+                    .date_naive()
+                    .and_time(*self.session_close.read())
+                    .and_utc();
+                Some(if close > now {
+                    close
+                } else {
+                    close + Duration::days(1)
+                })
+            }
+            TimeInForce::GoodTillDate(expire_at) => Some(expire_at),
+            _ => None,
+        }
+    }
+
+    fn schedule(&self, expire_at: DateTime<Utc>, order_id: u64) {
+        self.deadlines
+            .lock()
+            .entry(expire_at)
+            .or_default()
+            .push(order_id);
+    }
+
+    /// Whether `now` has passed the session close since the last call.
+    fn session_closed(&self, now: DateTime<Utc>) -> bool {
+        let mut next_rollover = self.next_rollover.lock();
+        let due = next_rollover.is_some_and(|close| now >= close);
+        if due || next_rollover.is_none() {
+            *next_rollover = self.deadline(TimeInForce::Day, now);
+        }
+        due
+    }
+
+    /// Removes and returns every order ID due at or before `now`.
+    fn take_due(&self, now: DateTime<Utc>) -> Vec<u64> {
+        let mut deadlines = self.deadlines.lock();
+        let pending = deadlines.split_off(&(now + Duration::nanoseconds(1)));
+        std::mem::replace(&mut *deadlines, pending)
+            .into_values()
+            .flatten()
+            .collect()
+    }
 }
 
 pub struct EngineMetrics {
     orders_processed: std::sync::atomic::AtomicU64,
     trades_executed: std::sync::atomic::AtomicU64,
+    orders_expired: std::sync::atomic::AtomicU64,
+    rejects: DashMap<RejectReason, u64>,
+    orders_throttled: std::sync::atomic::AtomicU64,
+    cancels_throttled: std::sync::atomic::AtomicU64,
     last_processing_time: std::sync::Mutex<Option<std::time::Duration>>,
 }
 
@@ -44,6 +222,10 @@ impl EngineMetrics {
         Self {
             orders_processed: std::sync::atomic::AtomicU64::new(0),
             trades_executed: std::sync::atomic::AtomicU64::new(0),
+            orders_expired: std::sync::atomic::AtomicU64::new(0),
+            rejects: DashMap::new(),
+            orders_throttled: std::sync::atomic::AtomicU64::new(0),
+            cancels_throttled: std::sync::atomic::AtomicU64::new(0),
             last_processing_time: std::sync::Mutex::new(None),
         }
     }
@@ -58,6 +240,25 @@ impl EngineMetrics {
             .fetch_add(count, std::sync::atomic::Ordering::Relaxed);
     }
 
+    fn inc_orders_expired(&self, count: u64) {
+        self.orders_expired
+            .fetch_add(count, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    fn inc_rejects(&self, reason: &RejectReason) {
+        *self.rejects.entry(reason.clone()).or_default() += 1;
+    }
+
+    fn inc_orders_throttled(&self) {
+        self.orders_throttled
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    fn inc_cancels_throttled(&self) {
+        self.cancels_throttled
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    }
+
     fn set_processing_time(&self, duration: std::time::Duration) {
         let mut guard = self.last_processing_time.lock().unwrap();
         *guard = Some(duration);
@@ -70,6 +271,16 @@ impl EngineMetrics {
         let trades = self
             .trades_executed
             .load(std::sync::atomic::Ordering::Relaxed);
+        let expired = self
+            .orders_expired
+            .load(std::sync::atomic::Ordering::Relaxed);
+        let rejected: u64 = self.rejects.iter().map(|entry| *entry.value()).sum();
+        let orders_throttled = self
+            .orders_throttled
+            .load(std::sync::atomic::Ordering::Relaxed);
+        let cancels_throttled = self
+            .cancels_throttled
+            .load(std::sync::atomic::Ordering::Relaxed);
         let time = self.last_processing_time.lock().unwrap();
 
         let last_order_time = match *time {
@@ -78,8 +289,8 @@ impl EngineMetrics {
         };
 
         info!(
-            "Engine metrics | Orders: {} | Trades: {} | Last order processing time: {}",
-            orders, trades, last_order_time
+            "Engine metrics | Orders: {} | Trades: {} | Expired: {} | Rejected: {} | Throttled orders: {} | Throttled cancels: {} | Last order processing time: {}",
+            orders, trades, expired, rejected, orders_throttled, cancels_throttled, last_order_time
         );
     }
 }
@@ -100,17 +311,68 @@ impl MatchingEngine {
         mpsc::UnboundedReceiver<EngineMessage>,
     ) {
         let (message_tx, message_rx) = mpsc::unbounded_channel();
+        let (reports, _) = broadcast::channel(REPORT_CHANNEL_CAPACITY);
         (
             Self {
                 order_book,
                 risk_manager,
                 metrics: Arc::new(EngineMetrics::new()),
+                expiries: Arc::new(ExpiryScheduler::new()),
+                reports,
+                sequencer: Arc::new(Sequencer::new()),
+                client_orders: Arc::new(DashMap::new()),
+                self_trade_prevention: Arc::new(RwLock::new(SelfTradePrevention::default())),
+                throttle: Arc::new(Throttle::new()),
             },
             message_tx,
             message_rx,
         )
     }
 
+    pub fn risk_manager(&self) -> &Arc<RiskManager> {
+        &self.risk_manager
+    }
+
+    #[allow(dead_code)]
+    pub fn throttle(&self) -> &Arc<Throttle> {
+        &self.throttle
+    }
+
+    /// Sets the UTC time of day at which Day orders expire.
+    #[allow(dead_code)]
+    pub fn set_session_close(&self, close: NaiveTime) {
+        *self.expiries.session_close.write() = close;
+        *self.expiries.next_rollover.lock() = None;
+    }
+
+    /// Per-account message limits applied to every order and cancel.
+    #[allow(dead_code)]
+    pub fn set_throttle_limits(&self, limits: ThrottleLimits) {
+        self.throttle.set_limits(limits);
+    }
+
+    /// Message limits for one account in place of the shared ones.
+    pub fn set_account_throttle_limits(&self, account_id: u64, limits: ThrottleLimits) {
+        self.throttle.set_account_limits(account_id, limits);
+    }
+
+    /// Puts the account back on the shared message limits.
+    pub fn clear_account_throttle_limits(&self, account_id: u64) {
+        self.throttle.clear_account_limits(account_id);
+    }
+
+    /// Exempts an account from the throttle and from the risk manager's
+    /// position and loss limits; see `RiskManager::exempt_account`.
+    pub fn exempt_account(&self, account_id: u64) {
+        self.throttle.exempt(account_id);
+        self.risk_manager.exempt_account(account_id);
+    }
+
+    #[allow(dead_code)]
+    pub fn set_self_trade_prevention(&self, mode: SelfTradePrevention) {
+        *self.self_trade_prevention.write() = mode;
+    }
+
     pub async fn run(&self, message_rx: &mut mpsc::UnboundedReceiver<EngineMessage>) {
         while let Some(msg) = message_rx.recv().await {
             match msg {
@@ -120,94 +382,674 @@ impl MatchingEngine {
                 EngineMessage::CancelOrder { symbol, order_id } => {
                     self.process_cancellation(&symbol, order_id).await;
                 }
+                EngineMessage::ModifyOrder {
+                    symbol,
+                    order_id,
+                    new_price,
+                    new_quantity,
+                } => {
+                    self.process_modification(&symbol, order_id, new_price, new_quantity)
+                        .await;
+                }
                 EngineMessage::BatchOrders(orders) => {
                     for order in orders {
                         self.process_order(order).await;
                     }
                 }
+                EngineMessage::CancelClientOrder {
+                    account_id,
+                    client_order_id,
+                } => {
+                    let order = self
+                        .order_id_for_client(account_id, client_order_id)
+                        .and_then(|order_id| self.order_book.get_order(order_id));
+                    match order {
+                        Some(order) => self.process_cancellation(&order.symbol, order.id).await,
+                        None => {
+                            warn!(
+                                "Failed to cancel client order {} of account {}: not working",
+                                client_order_id, account_id
+                            );
+                            self.publish(ExecutionReport::unknown_client_order(
+                                account_id,
+                                client_order_id,
+                                ExecType::CancelRejected(RejectReason::UnknownOrder),
+                            ));
+                        }
+                    }
+                }
             }
         }
     }
 
-    async fn process_order(&self, order: Order) {
+    /// Shared ID source, for callers that load orders into the book directly.
+    #[allow(dead_code)]
+    pub fn sequencer(&self) -> Arc<Sequencer> {
+        self.sequencer.clone()
+    }
+
+    /// Orders rejected so far, by reason.
+    #[allow(dead_code)]
+    pub fn reject_counts(&self) -> HashMap<RejectReason, u64> {
+        self.metrics
+            .rejects
+            .iter()
+            .map(|entry| (entry.key().clone(), *entry.value()))
+            .collect()
+    }
+
+    /// Engine order ID of a working order, looked up by the owning account
+    /// and its client order ID.
+    pub fn order_id_for_client(&self, account_id: u64, client_order_id: u64) -> Option<u64> {
+        self.client_orders
+            .get(&(account_id, client_order_id))
+            .map(|id| *id)
+    }
+
+    async fn process_order(&self, mut order: Order) {
         let start_time = Instant::now();
         self.metrics.inc_orders_processed();
 
-        let _symbol = order.symbol.clone();
+        // Whatever ID the submitter filled in is replaced by the engine's own
+        order.id = self.sequencer.next_order_id();
+
+        if let Err(throttled) = self.throttle.check_order(order.account_id, start_time) {
+            self.metrics.inc_orders_throttled();
+            self.reject(&order, RejectReason::Throttled(throttled));
+            self.metrics.set_processing_time(start_time.elapsed());
+            return;
+        }
+
+        // A client order ID names one working order; it can be reused once that is done
+        let client_key = (order.account_id, order.client_order_id);
+        if self.client_orders.contains_key(&client_key) {
+            self.reject(&order, RejectReason::DuplicateClientOrderId);
+            self.metrics.set_processing_time(start_time.elapsed());
+            return;
+        }
+
+        // Risk is checked once, on arrival, whichever channel the order came in on
+        match self.risk_manager.validate_order(&order, &self.order_book) {
+            Ok(()) => {
+                self.client_orders.insert(client_key, order.id);
+                self.execute_with_triggers(order, ExecType::New);
+            }
+            Err(rejection) => self.reject(&order, RejectReason::Risk(rejection)),
+        }
+
+        let duration = start_time.elapsed();
+        self.metrics.set_processing_time(duration);
+    }
+
+    /// Executes an order, then any stops its trades fire. `ack` is the report
+    /// published once the order passes its checks.
+    fn execute_with_triggers(&self, order: Order, ack: ExecType) {
+        let symbol = order.symbol.clone();
+
+        // Stops fired by a trade are queued behind the order that fired them, so
+        // a cascade of triggers runs in trigger order without recursing.
+        let mut pending = VecDeque::from([(order, ack)]);
+        while let Some((order, ack)) = pending.pop_front() {
+            // A stop queued behind the trade that halted its account never fires
+            if ack == ExecType::Triggered && self.risk_manager.is_halted(order.account_id) {
+                self.risk_manager.release_margin(order.account_id, order.id);
+                self.publish(ExecutionReport::new(
+                    &order,
+                    ExecType::Cancelled,
+                    Decimal::ZERO,
+                ));
+                continue;
+            }
+            let (account_id, order_id) = (order.account_id, order.id);
+            let trades = self.execute_order(order, ack);
+
+            // Positions are up to date now, so margin is held against them
+            self.refresh_margin(account_id, order_id);
+            for trade in &trades {
+                match trade.buyer_id == order_id {
+                    true => self.refresh_margin(trade.seller_account, trade.seller_id),
+                    false => self.refresh_margin(trade.buyer_account, trade.buyer_id),
+                }
+            }
+
+            let mut accounts = Vec::new();
+            for trade in &trades {
+                accounts.extend([trade.buyer_account, trade.seller_account]);
+                let now = Instant::now();
+                self.throttle.record_trade(trade.buyer_account, now);
+                self.throttle.record_trade(trade.seller_account, now);
+                self.order_book
+                    .record_trade_price(&trade.symbol, trade.price);
+                for stop in self
+                    .order_book
+                    .take_triggered_stops(&trade.symbol, trade.price)
+                {
+                    info!("Stop order {} triggered at {}", stop.id, trade.price);
+                    pending.push_back((stop.into_triggered(), ExecType::Triggered));
+                }
+            }
+
+            // Checked before the next queued stop runs, so it sees any halt
+            if !accounts.is_empty() {
+                accounts.sort_unstable();
+                accounts.dedup();
+                self.enforce_loss_limits(&accounts);
+            }
+        }
+        self.reprice_pegged_orders(&symbol);
+    }
+
+    /// Holds initial margin for what is left of `order_id` in the book, on
+    /// the part that would open a position given the account's position now,
+    /// and releases it once the order is gone.
+    fn refresh_margin(&self, account_id: u64, order_id: u64) {
+        match self.order_book.get_order(order_id) {
+            Some(order) => self.risk_manager.reserve_margin(&order),
+            None => self.risk_manager.release_margin(account_id, order_id),
+        }
+    }
+
+    /// Marks `accounts` to the book, pulls the orders of any account whose
+    /// kill switch trips and flags margin calls.
+    fn enforce_loss_limits(&self, accounts: &[u64]) {
+        let mark = |symbol: &str| {
+            self.order_book
+                .get_mid_price(symbol)
+                .or_else(|| self.order_book.get_last_trade_price(symbol))
+        };
+        for account_id in self.risk_manager.check_loss_limits(accounts, mark) {
+            self.cancel_account_orders(account_id);
+        }
+        self.risk_manager.check_margin_calls(accounts, mark);
+    }
+
+    /// Halts an account by hand: its resting orders are cancelled and new
+    /// ones rejected until `RiskManager::reset_kill_switch`.
+    #[allow(dead_code)]
+    pub fn halt_account(&self, account_id: u64) {
+        self.risk_manager
+            .trip_kill_switch(account_id, HaltReason::Manual);
+        self.cancel_account_orders(account_id);
+    }
+
+    fn cancel_account_orders(&self, account_id: u64) {
+        let cancelled = self.order_book.take_account_orders(account_id);
+        info!(
+            "Cancelled {} orders of halted account {}",
+            cancelled.len(),
+            account_id
+        );
+
+        let mut symbols = Vec::new();
+        for order in &cancelled {
+            self.risk_manager.release_margin(order.account_id, order.id);
+            self.publish(ExecutionReport::new(
+                order,
+                ExecType::Cancelled,
+                Decimal::ZERO,
+            ));
+            if !symbols.contains(&order.symbol) {
+                symbols.push(order.symbol.clone());
+            }
+        }
+        for symbol in symbols {
+            self.reprice_pegged_orders(&symbol);
+        }
+    }
+
+    /// Field checks every order has to pass before it is acknowledged.
+    fn check_order(order: &Order) -> Result<(), RejectReason> {
+        // Positions are mirrored into `AtomicDecimal`s, so no finer quantities
+        if order.quantity <= Decimal::ZERO || !AtomicDecimal::fits(order.quantity) {
+            return Err(RejectReason::InvalidQuantity);
+        }
+
+        let priced = matches!(
+            order.order_type,
+            OrderType::Limit | OrderType::StopLimit { .. } | OrderType::PostOnly { .. }
+        );
+        if priced && order.price <= Decimal::ZERO {
+            return Err(RejectReason::InvalidPrice);
+        }
+        if order.stop_price().is_some_and(|stop| stop <= Decimal::ZERO) {
+            return Err(RejectReason::InvalidStopPrice);
+        }
+        if order
+            .display_quantity
+            .is_some_and(|peak| peak <= Decimal::ZERO)
+        {
+            return Err(RejectReason::InvalidDisplayQuantity);
+        }
+        if let TimeInForce::GoodTillDate(expire_at) = order.time_in_force {
+            if expire_at <= Utc::now() {
+                return Err(RejectReason::ExpiryInPast);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn reject(&self, order: &Order, reason: RejectReason) {
+        warn!("Rejected order {}: {}", order.id, reason);
+        self.metrics.inc_rejects(&reason);
+        self.publish(ExecutionReport::new(
+            order,
+            ExecType::Rejected(reason),
+            Decimal::ZERO,
+        ));
+    }
+
+    fn execute_order(&self, mut order: Order, ack: ExecType) -> Vec<Trade> {
         info!("Processing order {}: {:?}", order.id, order);
 
-        let trades = match order.order_type {
-            OrderType::Limit if order.price > Decimal::ZERO => self.match_limit_order(&order),
+        if let Err(reason) = Self::check_order(&order) {
+            self.reject(&order, reason);
+            return Vec::new();
+        }
+
+        // Post-only orders never match: they rest, slide or are rejected
+        if let OrderType::PostOnly { reprice } = order.order_type {
+            match self.order_book.post_only_price(&order, reprice) {
+                Some(price) if price != order.price => {
+                    info!(
+                        "Repriced post-only order {} from {} to {}",
+                        order.id, order.price, price
+                    );
+                    order.price = price;
+                }
+                Some(_) => {}
+                None => {
+                    self.reject(&order, RejectReason::WouldCrossSpread);
+                    return Vec::new();
+                }
+            }
+        }
+
+        // Pegs are priced off the book on arrival and then treated as limits;
+        // they can only meet other pegs since they stay inside the reference quotes
+        if let OrderType::Pegged { .. } = order.order_type {
+            match self.order_book.peg_price(&order) {
+                Some(price) => order.price = price,
+                None => {
+                    self.reject(&order, RejectReason::NoReferencePrice);
+                    return Vec::new();
+                }
+            }
+        }
+
+        self.publish(ExecutionReport::new(&order, ack, order.total_quantity()));
+
+        if order.stop_price().is_some() {
+            let triggered = self
+                .order_book
+                .get_last_trade_price(&order.symbol)
+                .is_some_and(|last_price| order.is_stop_triggered(last_price));
+
+            // A stop already through the last trade price fires on arrival
+            if triggered {
+                return self.execute_order(order.into_triggered(), ExecType::Triggered);
+            }
+            self.rest_order(order);
+            return Vec::new();
+        }
+
+        if let OrderType::PostOnly { .. } = order.order_type {
+            self.rest_order(order);
+            return Vec::new();
+        }
+
+        if order.time_in_force == TimeInForce::FillOrKill {
+            let available = self.fillable_quantity(&order);
+            if available < order.quantity {
+                info!(
+                    "Killed FOK order {}: {} available of {}",
+                    order.id, available, order.quantity
+                );
+                self.publish(ExecutionReport::new(
+                    &order,
+                    ExecType::Cancelled,
+                    Decimal::ZERO,
+                ));
+                return Vec::new();
+            }
+        }
+
+        let outcome = match order.order_type {
+            OrderType::Limit | OrderType::Pegged { .. } if order.price > Decimal::ZERO => {
+                self.match_limit_order(&order)
+            }
             OrderType::Market => self.match_market_order(&order),
             _ => {
                 warn!("Invalid order type/price");
-                Vec::new()
+                MatchOutcome::new(order.quantity)
             }
         };
+        let trades = outcome.trades;
 
-        // Record trades for risk management
+        // Record both sides of every trade for risk management
         for trade in &trades {
-            let trade_side = if trade.buyer_id == order.id {
-                OrderSide::Buy
-            } else {
-                OrderSide::Sell
-            };
-
-            self.risk_manager.record_transaction(
-                &trade.symbol,
-                trade.price,
-                trade.quantity,
-                trade_side,
-            );
+            self.risk_manager.record_trade(trade);
         }
 
         self.metrics.inc_trades_executed(trades.len() as u64);
 
         // Add remaining order to order book if it's a limit order
-        let remaining_qty = order.quantity - trades.iter().map(|t| t.quantity).sum::<Decimal>();
+        let remaining_qty = outcome.remaining_qty;
 
-        if remaining_qty > Decimal::new(1, 3) && order.order_type == OrderType::Limit {
-            let mut new_order = order.clone();
-            new_order.quantity = remaining_qty;
-            self.order_book.add_order(new_order);
+        if !trades.is_empty() {
+            info!("Executed {} trades for order {}", trades.len(), order.id);
         }
 
-        let duration = start_time.elapsed();
-        self.metrics.set_processing_time(duration);
+        if outcome.self_trade_cancelled {
+            self.publish(ExecutionReport::new(
+                &order,
+                ExecType::Cancelled,
+                Decimal::ZERO,
+            ));
+        } else if remaining_qty > Decimal::new(1, 3) {
+            if order.order_type != OrderType::Market && order.time_in_force.can_rest() {
+                let mut new_order = order;
+                new_order.quantity = remaining_qty;
+                self.rest_order(new_order);
+            } else {
+                info!("Cancelled unfilled {} of order {}", remaining_qty, order.id);
+                self.publish(ExecutionReport::new(
+                    &order,
+                    ExecType::Cancelled,
+                    Decimal::ZERO,
+                ));
+            }
+        }
 
-        if !trades.is_empty() {
-            info!("Executed {} trades for order {}", trades.len(), order.id);
+        trades
+    }
+
+    /// Adds an order to the book or trigger book, holds margin for it and
+    /// schedules its expiry.
+    fn rest_order(&self, order: Order) {
+        if let Some(expire_at) = self.expiries.deadline(order.time_in_force, Utc::now()) {
+            self.expiries.schedule(expire_at, order.id);
         }
+        self.risk_manager.reserve_margin(&order);
+        self.order_book.add_order(order);
     }
 
-    async fn process_cancellation(&self, _symbol: &str, order_id: u64) {
-        if self.order_book.cancel_order(order_id) {
+    /// Cancels every Day and GTD order due at or before `now` and returns the
+    /// IDs that were still resting.
+    pub fn expire_orders(&self, now: DateTime<Utc>) -> Vec<u64> {
+        let expired: Vec<Order> = self
+            .expiries
+            .take_due(now)
+            .into_iter()
+            .filter_map(|order_id| self.order_book.take_order(order_id))
+            .collect();
+
+        for order in &expired {
+            info!("Expired order {}", order.id);
+            self.risk_manager.release_margin(order.account_id, order.id);
+            self.publish(ExecutionReport::new(
+                order,
+                ExecType::Expired,
+                Decimal::ZERO,
+            ));
+        }
+        if !expired.is_empty() {
+            for symbol in self.order_book.pegged_symbols() {
+                self.reprice_pegged_orders(&symbol);
+            }
+        }
+        self.metrics.inc_orders_expired(expired.len() as u64);
+
+        expired.iter().map(|order| order.id).collect()
+    }
+
+    /// Rolls over to the next session once `now` passes the session close:
+    /// each symbol's last trade becomes its previous close for the short
+    /// sale price test, and intraday loss limits restart. Returns whether it
+    /// rolled over.
+    pub fn roll_session(&self, now: DateTime<Utc>) -> bool {
+        if !self.expiries.session_closed(now) {
+            return false;
+        }
+        let short_sales = self.risk_manager.short_sales();
+        for (symbol, price) in self.order_book.last_trade_prices() {
+            short_sales.set_previous_close(&symbol, price);
+        }
+        self.risk_manager.begin_session();
+        info!("Session closed at {}", now);
+        true
+    }
+
+    async fn process_cancellation(&self, symbol: &str, order_id: u64) {
+        // Cancels carry no account, so the throttle goes by the order's owner
+        if let Some(order) = self.order_book.get_order(order_id) {
+            if let Err(throttled) = self.throttle.check_cancel(order.account_id, Instant::now()) {
+                self.metrics.inc_cancels_throttled();
+                warn!("Throttled cancel of order {}: {}", order_id, throttled);
+                self.publish(ExecutionReport::new(
+                    &order,
+                    ExecType::CancelRejected(RejectReason::Throttled(throttled)),
+                    order.total_quantity(),
+                ));
+                return;
+            }
+        }
+
+        if let Some(order) = self.order_book.take_order(order_id) {
             log::info!("Cancelled order {}", order_id);
+            self.risk_manager.release_margin(order.account_id, order.id);
+            self.publish(ExecutionReport::new(
+                &order,
+                ExecType::Cancelled,
+                Decimal::ZERO,
+            ));
+            self.reprice_pegged_orders(symbol);
         } else {
-            log::warn!("Failed to cancel order {}", order_id);
+            log::warn!("Failed to cancel order {}: not working", order_id);
+            self.publish(ExecutionReport::unknown_order(
+                order_id,
+                symbol,
+                ExecType::CancelRejected(RejectReason::UnknownOrder),
+            ));
+        }
+    }
+
+    async fn process_modification(
+        &self,
+        symbol: &str,
+        order_id: u64,
+        new_price: Option<Decimal>,
+        new_quantity: Option<Decimal>,
+    ) {
+        // Kept so a replacement that fails risk can go back where it was
+        let original = self.order_book.get_order(order_id);
+
+        // A cut counts against the throttle as a cancel, any other amend as
+        // a new order
+        if let Some(order) = &original {
+            let is_cut = new_price.is_none_or(|price| price == order.price)
+                && new_quantity.is_none_or(|quantity| quantity <= order.total_quantity());
+            let now = Instant::now();
+            let admitted = if is_cut {
+                self.throttle
+                    .check_cancel(order.account_id, now)
+                    .inspect_err(|_| self.metrics.inc_cancels_throttled())
+            } else {
+                self.throttle
+                    .check_order(order.account_id, now)
+                    .inspect_err(|_| self.metrics.inc_orders_throttled())
+            };
+            if let Err(throttled) = admitted {
+                warn!("Throttled modify of order {}: {}", order_id, throttled);
+                self.publish(ExecutionReport::new(
+                    order,
+                    ExecType::ReplaceRejected(RejectReason::Throttled(throttled)),
+                    order.total_quantity(),
+                ));
+                return;
+            }
+        }
+
+        match self
+            .order_book
+            .amend_order(order_id, new_price, new_quantity)
+        {
+            AmendResult::Amended => {
+                info!("Amended order {} in place", order_id);
+                if let Some(order) = self.order_book.get_order(order_id) {
+                    self.risk_manager.reserve_margin(&order);
+                    self.publish(ExecutionReport::new(
+                        &order,
+                        ExecType::Replaced,
+                        order.total_quantity(),
+                    ));
+                }
+                self.reprice_pegged_orders(symbol);
+            }
+            AmendResult::Replaced(order) => {
+                // The replacement is checked like a new order, without the
+                // margin held for the one it replaces
+                self.risk_manager.release_margin(order.account_id, order.id);
+                match self.risk_manager.validate_order(&order, &self.order_book) {
+                    Ok(()) => {
+                        info!("Replaced order {}: {:?}", order_id, order);
+                        self.execute_with_triggers(order, ExecType::Replaced);
+                    }
+                    Err(rejection) => {
+                        warn!("Rejected modify for order {}: {}", order_id, rejection);
+                        // Whatever `amend_order` replaced was resting a moment ago
+                        if let Some(original) = original {
+                            let report = ExecutionReport::new(
+                                &original,
+                                ExecType::ReplaceRejected(RejectReason::Risk(rejection)),
+                                original.total_quantity(),
+                            );
+                            self.risk_manager.reserve_margin(&original);
+                            self.order_book.restore_order(original);
+                            self.publish(report);
+                        }
+                    }
+                }
+            }
+            AmendResult::NotFound => {
+                warn!("Failed to modify order {}: not working", order_id);
+                self.publish(ExecutionReport::unknown_order(
+                    order_id,
+                    symbol,
+                    ExecType::ReplaceRejected(RejectReason::UnknownOrder),
+                ));
+            }
+            AmendResult::Rejected(reason) => {
+                warn!("Rejected modify for order {}: {}", order_id, reason);
+                if let Some(order) = self.order_book.get_order(order_id) {
+                    self.publish(ExecutionReport::new(
+                        &order,
+                        ExecType::ReplaceRejected(RejectReason::InvalidAmend(reason)),
+                        order.total_quantity(),
+                    ));
+                }
+            }
         }
     }
 
-    fn match_limit_order(&self, order: &Order) -> Vec<Trade> {
+    /// Subscribes to execution reports for every order the engine handles.
+    /// Receivers that fall more than the channel capacity behind lose the
+    /// oldest reports.
+    #[allow(dead_code)]
+    pub fn subscribe_reports(&self) -> broadcast::Receiver<ExecutionReport> {
+        self.reports.subscribe()
+    }
+
+    fn publish(&self, mut report: ExecutionReport) {
+        report.sequence = self.sequencer.next_sequence();
+
+        let finished = match report.exec_type {
+            ExecType::Rejected(_) | ExecType::Cancelled | ExecType::Expired => true,
+            ExecType::Fill => report.leaves_quantity <= Decimal::new(1, 3),
+            _ => false,
+        };
+        if finished {
+            if let (Some(account_id), Some(client_order_id)) =
+                (report.account_id, report.client_order_id)
+            {
+                self.client_orders
+                    .remove_if(&(account_id, client_order_id), |_, id| {
+                        Some(*id) == report.order_id
+                    });
+            }
+        }
+
+        // Sending only fails when nobody is subscribed
+        let _ = self.reports.send(report);
+    }
+
+    /// Quantity `order` would fill if it swept the book now, in the same
+    /// queue order as `match_price_level`. The sweep stops at the first
+    /// resting order from the same account unless self-trade prevention
+    /// cancels only the resting side; every other mode cancels or shrinks the
+    /// aggressor there without a fill.
+    fn fillable_quantity(&self, order: &Order) -> Decimal {
+        let skip_own = *self.self_trade_prevention.read() == SelfTradePrevention::CancelOldest;
+        let within_limit = |price: Decimal| match (&order.order_type, &order.side) {
+            (OrderType::Market, _) => true,
+            (_, OrderSide::Buy) => price <= order.price,
+            (_, OrderSide::Sell) => price >= order.price,
+        };
+
+        let mut remaining = order.quantity;
+        match order.side {
+            OrderSide::Buy => {
+                if let Some(asks) = self.order_book.asks.get(&order.symbol) {
+                    for (price, level) in asks.iter() {
+                        if !within_limit(*price)
+                            || !sweep_level(order, level, skip_own, &mut remaining)
+                        {
+                            break;
+                        }
+                    }
+                }
+            }
+            OrderSide::Sell => {
+                if let Some(bids) = self.order_book.bids.get(&order.symbol) {
+                    for (price, level) in bids.iter() {
+                        if !within_limit(price.0)
+                            || !sweep_level(order, level, skip_own, &mut remaining)
+                        {
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        order.quantity - remaining
+    }
+
+    fn reprice_pegged_orders(&self, symbol: &str) {
+        let moved = self.order_book.reprice_pegged_orders(symbol);
+        if moved > 0 {
+            info!("Repriced {} pegged orders on {}", moved, symbol);
+        }
+    }
+
+    fn match_limit_order(&self, order: &Order) -> MatchOutcome {
         match order.side {
             OrderSide::Buy => self.match_buy_order(order, |ask_price| ask_price <= order.price),
             OrderSide::Sell => self.match_sell_order(order, |bid_price| bid_price >= order.price),
         }
     }
 
-    fn match_market_order(&self, order: &Order) -> Vec<Trade> {
+    fn match_market_order(&self, order: &Order) -> MatchOutcome {
         match order.side {
             OrderSide::Buy => self.match_buy_order(order, |_| true),
             OrderSide::Sell => self.match_sell_order(order, |_| true),
         }
     }
 
-    fn match_buy_order<F>(&self, order: &Order, price_check: F) -> Vec<Trade>
+    fn match_buy_order<F>(&self, order: &Order, price_check: F) -> MatchOutcome
     where
         F: Fn(Decimal) -> bool,
     {
-        let mut trades = Vec::new();
-        let mut remaining_qty = order.quantity;
+        let mut outcome = MatchOutcome::new(order.quantity);
         let symbol = &order.symbol;
 
         if let Some(mut asks) = self.order_book.asks.get_mut(symbol) {
@@ -220,60 +1062,27 @@ impl MatchingEngine {
                 }
 
                 if let Some(orders_at_price) = asks.get_mut(&price) {
-                    let mut filled_indices = Vec::new();
-                    let mut _filled_qty = Decimal::ZERO;
-
-                    for (idx, resting_order) in orders_at_price.iter_mut().enumerate() {
-                        if remaining_qty <= Decimal::ZERO {
-                            break;
-                        }
-
-                        let trade_qty = remaining_qty.min(resting_order.quantity);
-
-                        trades.push(Trade {
-                            id: chrono::Utc::now().timestamp_nanos_opt().unwrap() as u64,
-                            symbol: symbol.clone(),
-                            price,
-                            quantity: trade_qty,
-                            buyer_id: order.id,
-                            seller_id: resting_order.id,
-                            timestamp: chrono::Utc::now().timestamp(),
-                        });
-
-                        remaining_qty -= trade_qty;
-                        resting_order.quantity -= trade_qty;
-                        _filled_qty += trade_qty;
-
-                        if resting_order.quantity <= Decimal::new(1, 3) {
-                            filled_indices.push(idx);
-                            self.order_book.order_index.remove(&resting_order.id);
-                        }
-                    }
-
-                    for idx in filled_indices.iter().rev() {
-                        orders_at_price.remove(*idx);
-                    }
+                    self.match_price_level(order, price, orders_at_price, &mut outcome);
 
                     if orders_at_price.is_empty() {
                         asks.remove(&price);
                     }
                 }
 
-                if remaining_qty <= Decimal::new(1, 3) {
+                if outcome.remaining_qty <= Decimal::new(1, 3) || outcome.self_trade_cancelled {
                     break;
                 }
             }
         }
 
-        trades
+        outcome
     }
 
-    fn match_sell_order<F>(&self, order: &Order, price_check: F) -> Vec<Trade>
+    fn match_sell_order<F>(&self, order: &Order, price_check: F) -> MatchOutcome
     where
         F: Fn(Decimal) -> bool,
     {
-        let mut trades = Vec::new();
-        let mut remaining_qty = order.quantity;
+        let mut outcome = MatchOutcome::new(order.quantity);
         let symbol = &order.symbol;
 
         if let Some(mut bids) = self.order_book.bids.get_mut(symbol) {
@@ -287,52 +1096,182 @@ impl MatchingEngine {
 
                 let price_key = Reverse(price);
                 if let Some(orders_at_price) = bids.get_mut(&price_key) {
-                    let mut filled_indices = Vec::new();
-                    let mut _filled_qty = Decimal::ZERO;
-
-                    for (idx, resting_order) in orders_at_price.iter_mut().enumerate() {
-                        if remaining_qty <= Decimal::ZERO {
-                            break;
-                        }
-
-                        let trade_qty = remaining_qty.min(resting_order.quantity);
-
-                        trades.push(Trade {
-                            id: chrono::Utc::now().timestamp_nanos_opt().unwrap() as u64,
-                            symbol: symbol.clone(),
-                            price,
-                            quantity: trade_qty,
-                            buyer_id: resting_order.id,
-                            seller_id: order.id,
-                            timestamp: chrono::Utc::now().timestamp(),
-                        });
-
-                        remaining_qty -= trade_qty;
-                        resting_order.quantity -= trade_qty;
-                        _filled_qty += trade_qty;
-
-                        if resting_order.quantity <= Decimal::new(1, 3) {
-                            filled_indices.push(idx);
-                            self.order_book.order_index.remove(&resting_order.id);
-                        }
-                    }
-
-                    for idx in filled_indices.iter().rev() {
-                        orders_at_price.remove(*idx);
-                    }
+                    self.match_price_level(order, price, orders_at_price, &mut outcome);
 
                     if orders_at_price.is_empty() {
                         bids.remove(&price_key);
                     }
                 }
 
-                if remaining_qty <= Decimal::new(1, 3) {
+                if outcome.remaining_qty <= Decimal::new(1, 3) || outcome.self_trade_cancelled {
                     break;
                 }
             }
         }
 
-        trades
+        outcome
+    }
+
+    /// Fills against one price level in time priority. An iceberg whose
+    /// displayed slice is used up is refilled from its reserve and moved to the
+    /// back of the level, so the aggressor may meet it again further down.
+    /// Resting orders from the aggressor's own account are handled by the
+    /// engine's self-trade prevention mode instead of trading.
+    fn match_price_level(
+        &self,
+        order: &Order,
+        price: Decimal,
+        orders_at_price: &mut Vec<Order>,
+        outcome: &mut MatchOutcome,
+    ) {
+        let stp_mode = *self.self_trade_prevention.read();
+
+        let mut idx = 0;
+        while idx < orders_at_price.len() && outcome.remaining_qty > Decimal::ZERO {
+            if orders_at_price[idx].account_id == order.account_id {
+                if self.prevent_self_trade(stp_mode, order, orders_at_price, idx, outcome) {
+                    return;
+                }
+                continue;
+            }
+
+            let resting_order = &mut orders_at_price[idx];
+            let trade_qty = outcome.remaining_qty.min(resting_order.quantity);
+
+            let (buyer, seller) = match order.side {
+                OrderSide::Buy => (order, &*resting_order),
+                OrderSide::Sell => (&*resting_order, order),
+            };
+            outcome.trades.push(Trade {
+                id: self.sequencer.next_trade_id(),
+                sequence: self.sequencer.next_sequence(),
+                symbol: order.symbol.clone(),
+                price,
+                quantity: trade_qty,
+                buyer_id: buyer.id,
+                seller_id: seller.id,
+                buyer_account: buyer.account_id,
+                seller_account: seller.account_id,
+                timestamp: Utc::now().timestamp(),
+            });
+
+            outcome.remaining_qty -= trade_qty;
+            resting_order.quantity -= trade_qty;
+
+            self.publish(ExecutionReport::fill(
+                order,
+                price,
+                trade_qty,
+                outcome.remaining_qty,
+            ));
+            self.publish(ExecutionReport::fill(
+                resting_order,
+                price,
+                trade_qty,
+                resting_order.total_quantity(),
+            ));
+
+            if resting_order.quantity > Decimal::new(1, 3) {
+                idx += 1;
+            } else if resting_order.refill() {
+                let refilled = orders_at_price.remove(idx);
+                orders_at_price.push(refilled);
+            } else {
+                self.order_book.order_index.remove(&resting_order.id);
+                orders_at_price.remove(idx);
+            }
+        }
+    }
+
+    /// Applies `mode` to the resting order at `idx`, which shares the
+    /// aggressor's account. Returns true once the aggressor has been cancelled.
+    fn prevent_self_trade(
+        &self,
+        mode: SelfTradePrevention,
+        order: &Order,
+        orders_at_price: &mut Vec<Order>,
+        idx: usize,
+        outcome: &mut MatchOutcome,
+    ) -> bool {
+        let resting_id = orders_at_price[idx].id;
+        let resting_qty = orders_at_price[idx].total_quantity();
+        let (cancel_resting, cancel_incoming) = match mode {
+            SelfTradePrevention::CancelNewest => (false, true),
+            SelfTradePrevention::CancelOldest => (true, false),
+            SelfTradePrevention::CancelBoth => (true, true),
+            SelfTradePrevention::DecrementAndCancel => {
+                let decrement = outcome.remaining_qty.min(resting_qty);
+                outcome.remaining_qty -= decrement;
+                if resting_qty > decrement {
+                    let resting_order = &mut orders_at_price[idx];
+                    resting_order.reduce_quantity(decrement);
+                    self.risk_manager.reserve_margin(resting_order);
+                    self.publish(ExecutionReport::new(
+                        resting_order,
+                        ExecType::Replaced,
+                        resting_order.total_quantity(),
+                    ));
+                }
+                (
+                    resting_qty <= decrement,
+                    outcome.remaining_qty <= Decimal::new(1, 3),
+                )
+            }
+        };
+
+        if cancel_resting {
+            let resting_order = orders_at_price.remove(idx);
+            self.order_book.order_index.remove(&resting_order.id);
+            self.risk_manager
+                .release_margin(resting_order.account_id, resting_order.id);
+            info!(
+                "Self-trade prevention cancelled resting order {} against order {}",
+                resting_order.id, order.id
+            );
+            self.publish(ExecutionReport::new(
+                &resting_order,
+                ExecType::Cancelled,
+                Decimal::ZERO,
+            ));
+        }
+
+        if cancel_incoming {
+            info!(
+                "Self-trade prevention cancelled order {} against resting order {}",
+                order.id, resting_id
+            );
+            outcome.self_trade_cancelled = true;
+        }
+        cancel_incoming
+    }
+
+    /// Expires due orders and rolls the session over every `interval_ms`.
+    pub async fn start_expiry_scheduler(self: Arc<Self>, interval_ms: u64) {
+        let engine = Arc::clone(&self);
+        tokio::spawn(async move {
+            let mut interval =
+                tokio::time::interval(tokio::time::Duration::from_millis(interval_ms));
+            loop {
+                interval.tick().await;
+                let now = Utc::now();
+                engine.expire_orders(now);
+                engine.roll_session(now);
+            }
+        });
+    }
+
+    /// Marks every account that has traded each `interval_ms`, so loss
+    /// limits and margin calls also catch the book moving between its fills.
+    pub async fn start_risk_monitor(self: Arc<Self>, interval_ms: u64) {
+        let engine = Arc::clone(&self);
+        tokio::spawn(async move {
+            let mut interval =
+                tokio::time::interval(tokio::time::Duration::from_millis(interval_ms));
+            loop {
+                interval.tick().await;
+                engine.enforce_loss_limits(&engine.risk_manager.position_accounts());
+            }
+        });
     }
 
     #[allow(dead_code)]
@@ -355,14 +1294,46 @@ impl MatchingEngine {
                     bid_depth,
                     ask_depth
                 );
+                log::info!("Resting Stop Orders: {}", order_book.get_stop_depth("AAPL"));
+                let snapshot = order_book.get_book_snapshot("AAPL", 1);
+                if let (Some((bid, bid_size)), Some((ask, ask_size))) =
+                    (snapshot.bids.first(), snapshot.asks.first())
+                {
+                    log::info!(
+                        "Top of Book - {} x {} / {} x {}",
+                        bid_size,
+                        bid,
+                        ask,
+                        ask_size
+                    );
+                }
                 log::info!(
                     "Total Order Operations: {}",
                     order_book.get_operation_count()
                 );
 
                 // Potential risk analysis
-                let risk_analysis = engine.risk_manager.analyze_portfolio_risk();
-                for (symbol, metrics) in risk_analysis {
+                let risk_analysis = engine
+                    .risk_manager
+                    .analyze_portfolio_risk(|symbol| order_book.get_mid_price(symbol));
+                log::info!(
+                    "Portfolio Exposure | Gross: {:.2} | Net: {:.2} | Long: {:.2} | Short: {:.2}",
+                    risk_analysis.exposure.gross(),
+                    risk_analysis.exposure.net(),
+                    risk_analysis.exposure.long,
+                    risk_analysis.exposure.short
+                );
+                if let Some(var) = risk_analysis.var.parametric {
+                    log::info!(
+                        "Portfolio VaR ({:.0}%) | Intraday: {:.2} (ES {:.2}) | One day: {:.2} (ES {:.2})",
+                        risk_analysis.var.confidence * 100.0,
+                        var.intraday.var,
+                        var.intraday.expected_shortfall,
+                        var.one_day.var,
+                        var.one_day.expected_shortfall
+                    );
+                }
+                for (symbol, metrics) in risk_analysis.symbols {
                     log::info!(
                         "Risk Analysis for {}: Position: {}, Realized PnL: {}, Limit Utilization: {}%",
                         symbol,
@@ -375,3 +1346,830 @@ impl MatchingEngine {
         });
     }
 }
+
+/// Walks one price level the way `match_price_level` would, on a copy, and
+/// takes what `order` would fill off `remaining`. Returns false once the
+/// sweep is over: filled, or stopped by a same-account order.
+fn sweep_level(order: &Order, level: &[Order], skip_own: bool, remaining: &mut Decimal) -> bool {
+    let mut queue: VecDeque<Order> = level.iter().cloned().collect();
+    while *remaining > Decimal::ZERO {
+        let Some(mut resting) = queue.pop_front() else {
+            return true;
+        };
+        if resting.account_id == order.account_id {
+            if skip_own {
+                continue;
+            }
+            return false;
+        }
+
+        let fill = (*remaining).min(resting.quantity);
+        *remaining -= fill;
+        resting.quantity -= fill;
+        if resting.quantity <= Decimal::new(1, 3) && resting.refill() {
+            queue.push_back(resting);
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::margin::AccountType;
+    use crate::order_book::PegType;
+    use crate::risk_management::{LossLimits, RiskRejection};
+    use crate::throttle::ThrottleRejection;
+    use rust_decimal_macros::dec;
+
+    fn engine() -> MatchingEngine {
+        let (engine, _, _) = MatchingEngine::new(
+            Arc::new(OrderBook::new()),
+            Arc::new(RiskManager::new(dec!(1_000_000))),
+        );
+        engine
+    }
+
+    fn order(account_id: u64, side: OrderSide, price: Decimal, quantity: Decimal) -> Order {
+        static NEXT_CLIENT_ORDER_ID: AtomicU64 = AtomicU64::new(1_000);
+        Order {
+            id: 0,
+            client_order_id: NEXT_CLIENT_ORDER_ID.fetch_add(1, Ordering::Relaxed),
+            account_id,
+            symbol: "AAPL".to_string(),
+            price,
+            quantity,
+            order_type: OrderType::Limit,
+            side,
+            timestamp: Utc::now().timestamp_nanos_opt().unwrap_or_default(),
+            time_in_force: TimeInForce::GoodTillCancel,
+            display_quantity: None,
+            hidden_quantity: Decimal::ZERO,
+        }
+    }
+
+    fn with_tif(mut order: Order, time_in_force: TimeInForce) -> Order {
+        order.time_in_force = time_in_force;
+        order
+    }
+
+    fn drain(reports: &mut broadcast::Receiver<ExecutionReport>) -> Vec<ExecutionReport> {
+        std::iter::from_fn(|| reports.try_recv().ok()).collect()
+    }
+
+    fn exec_types(reports: &[ExecutionReport]) -> Vec<ExecType> {
+        reports.iter().map(|r| r.exec_type.clone()).collect()
+    }
+
+    #[tokio::test]
+    async fn fok_sell_counts_bids_from_the_best_price() {
+        let engine = engine();
+        engine
+            .process_order(order(1, OrderSide::Buy, dec!(100), dec!(5)))
+            .await;
+        engine
+            .process_order(order(1, OrderSide::Buy, dec!(98), dec!(5)))
+            .await;
+        let mut reports = engine.subscribe_reports();
+
+        let fok = with_tif(
+            order(2, OrderSide::Sell, dec!(99), dec!(5)),
+            TimeInForce::FillOrKill,
+        );
+        engine.process_order(fok).await;
+
+        let reports = drain(&mut reports);
+        let fills: Vec<_> = reports
+            .iter()
+            .filter(|r| r.account_id == Some(2) && r.exec_type == ExecType::Fill)
+            .collect();
+        assert_eq!(fills.len(), 1);
+        assert_eq!(fills[0].last_price, dec!(100));
+        assert_eq!(engine.order_book.get_best_bid("AAPL"), Some(dec!(98)));
+    }
+
+    #[tokio::test]
+    async fn fok_is_killed_without_enough_liquidity_inside_its_limit() {
+        let engine = engine();
+        engine
+            .process_order(order(1, OrderSide::Sell, dec!(100), dec!(5)))
+            .await;
+        engine
+            .process_order(order(1, OrderSide::Sell, dec!(102), dec!(5)))
+            .await;
+        let mut reports = engine.subscribe_reports();
+
+        let fok = with_tif(
+            order(2, OrderSide::Buy, dec!(101), dec!(10)),
+            TimeInForce::FillOrKill,
+        );
+        engine.process_order(fok).await;
+
+        assert_eq!(
+            exec_types(&drain(&mut reports)),
+            vec![ExecType::New, ExecType::Cancelled]
+        );
+        assert_eq!(engine.order_book.get_order_book_depth("AAPL"), (0, 2));
+    }
+
+    #[tokio::test]
+    async fn fok_ignores_own_account_liquidity() {
+        let engine = engine();
+        engine
+            .process_order(order(1, OrderSide::Sell, dec!(100), dec!(5)))
+            .await;
+        engine
+            .process_order(order(2, OrderSide::Sell, dec!(100), dec!(5)))
+            .await;
+        let mut reports = engine.subscribe_reports();
+
+        let fok = with_tif(
+            order(2, OrderSide::Buy, dec!(100), dec!(10)),
+            TimeInForce::FillOrKill,
+        );
+        engine.process_order(fok).await;
+
+        assert_eq!(
+            exec_types(&drain(&mut reports)),
+            vec![ExecType::New, ExecType::Cancelled]
+        );
+    }
+
+    #[tokio::test]
+    async fn ioc_fills_what_it_can_and_cancels_the_rest() {
+        let engine = engine();
+        engine
+            .process_order(order(1, OrderSide::Sell, dec!(100), dec!(5)))
+            .await;
+        let mut reports = engine.subscribe_reports();
+
+        let ioc = with_tif(
+            order(2, OrderSide::Buy, dec!(100), dec!(8)),
+            TimeInForce::ImmediateOrCancel,
+        );
+        engine.process_order(ioc).await;
+
+        let reports = drain(&mut reports);
+        let own: Vec<_> = reports.iter().filter(|r| r.account_id == Some(2)).collect();
+        assert_eq!(own.len(), 3);
+        assert_eq!(own[1].exec_type, ExecType::PartialFill);
+        assert_eq!(own[1].last_quantity, dec!(5));
+        assert_eq!(own[2].exec_type, ExecType::Cancelled);
+        assert_eq!(engine.order_book.get_order_book_depth("AAPL"), (0, 0));
+    }
+
+    async fn stp_setup(
+        mode: SelfTradePrevention,
+    ) -> (MatchingEngine, broadcast::Receiver<ExecutionReport>) {
+        let engine = engine();
+        engine.set_self_trade_prevention(mode);
+        engine
+            .process_order(order(1, OrderSide::Sell, dec!(100), dec!(4)))
+            .await;
+        engine
+            .process_order(order(2, OrderSide::Sell, dec!(100), dec!(10)))
+            .await;
+        let reports = engine.subscribe_reports();
+        (engine, reports)
+    }
+
+    fn traded(reports: &[ExecutionReport], account_id: u64) -> Decimal {
+        reports
+            .iter()
+            .filter(|r| r.account_id == Some(account_id))
+            .map(|r| r.last_quantity)
+            .sum()
+    }
+
+    #[tokio::test]
+    async fn cancel_newest_cancels_the_aggressor() {
+        let (engine, mut reports) = stp_setup(SelfTradePrevention::CancelNewest).await;
+        engine
+            .process_order(order(1, OrderSide::Buy, dec!(100), dec!(6)))
+            .await;
+
+        let reports = drain(&mut reports);
+        assert_eq!(
+            exec_types(&reports),
+            vec![ExecType::New, ExecType::Cancelled]
+        );
+        assert_eq!(engine.order_book.get_order_book_depth("AAPL"), (0, 2));
+    }
+
+    #[tokio::test]
+    async fn cancel_oldest_cancels_the_resting_order_and_keeps_matching() {
+        let (engine, mut reports) = stp_setup(SelfTradePrevention::CancelOldest).await;
+        engine
+            .process_order(order(1, OrderSide::Buy, dec!(100), dec!(6)))
+            .await;
+
+        let reports = drain(&mut reports);
+        assert_eq!(reports[1].exec_type, ExecType::Cancelled);
+        assert_eq!(reports[1].order_id, Some(1));
+        assert_eq!(traded(&reports, 2), dec!(6));
+        assert_eq!(engine.order_book.get_order_book_depth("AAPL"), (0, 1));
+    }
+
+    #[tokio::test]
+    async fn cancel_both_cancels_both_orders() {
+        let (engine, mut reports) = stp_setup(SelfTradePrevention::CancelBoth).await;
+        engine
+            .process_order(order(1, OrderSide::Buy, dec!(100), dec!(6)))
+            .await;
+
+        let reports = drain(&mut reports);
+        assert_eq!(
+            exec_types(&reports),
+            vec![ExecType::New, ExecType::Cancelled, ExecType::Cancelled]
+        );
+        assert_eq!(traded(&reports, 2), Decimal::ZERO);
+        assert_eq!(engine.order_book.get_order_book_depth("AAPL"), (0, 1));
+    }
+
+    #[tokio::test]
+    async fn decrement_and_cancel_shrinks_the_larger_order() {
+        let (engine, mut reports) = stp_setup(SelfTradePrevention::DecrementAndCancel).await;
+        engine
+            .process_order(order(1, OrderSide::Buy, dec!(100), dec!(3)))
+            .await;
+
+        let reports = drain(&mut reports);
+        assert_eq!(
+            exec_types(&reports),
+            vec![ExecType::New, ExecType::Replaced, ExecType::Cancelled]
+        );
+        assert_eq!(reports[1].order_id, Some(1));
+        assert_eq!(reports[1].leaves_quantity, dec!(1));
+        assert_eq!(engine.order_book.get_order(1).unwrap().quantity, dec!(1));
+    }
+
+    #[tokio::test]
+    async fn decrement_and_cancel_lets_the_rest_of_the_aggressor_trade() {
+        let (engine, mut reports) = stp_setup(SelfTradePrevention::DecrementAndCancel).await;
+        engine
+            .process_order(order(1, OrderSide::Buy, dec!(100), dec!(10)))
+            .await;
+
+        let reports = drain(&mut reports);
+        assert_eq!(reports[1].exec_type, ExecType::Cancelled);
+        assert_eq!(reports[1].order_id, Some(1));
+        assert_eq!(traded(&reports, 2), dec!(6));
+        assert!(engine.order_book.get_order(1).is_none());
+    }
+
+    #[tokio::test]
+    async fn fok_is_killed_where_self_trade_prevention_would_stop_it() {
+        let engine = engine();
+        engine
+            .process_order(order(2, OrderSide::Sell, dec!(100), dec!(5)))
+            .await;
+        engine
+            .process_order(order(1, OrderSide::Sell, dec!(100), dec!(5)))
+            .await;
+        engine
+            .process_order(order(2, OrderSide::Sell, dec!(101), dec!(5)))
+            .await;
+        let mut reports = engine.subscribe_reports();
+
+        let fok = with_tif(
+            order(1, OrderSide::Buy, dec!(101), dec!(10)),
+            TimeInForce::FillOrKill,
+        );
+        engine.process_order(fok).await;
+
+        let reports = drain(&mut reports);
+        assert_eq!(
+            exec_types(&reports),
+            vec![ExecType::New, ExecType::Cancelled]
+        );
+        assert_eq!(engine.order_book.get_order_book_depth("AAPL"), (0, 3));
+    }
+
+    #[tokio::test]
+    async fn fok_sweeps_past_own_orders_under_cancel_oldest() {
+        let engine = engine();
+        engine.set_self_trade_prevention(SelfTradePrevention::CancelOldest);
+        engine
+            .process_order(order(2, OrderSide::Sell, dec!(100), dec!(5)))
+            .await;
+        engine
+            .process_order(order(1, OrderSide::Sell, dec!(100), dec!(5)))
+            .await;
+        engine
+            .process_order(order(2, OrderSide::Sell, dec!(101), dec!(5)))
+            .await;
+        let mut reports = engine.subscribe_reports();
+
+        let fok = with_tif(
+            order(1, OrderSide::Buy, dec!(101), dec!(10)),
+            TimeInForce::FillOrKill,
+        );
+        engine.process_order(fok).await;
+
+        let reports = drain(&mut reports);
+        assert_eq!(traded(&reports, 2), dec!(10));
+        assert_eq!(engine.order_book.get_order_book_depth("AAPL"), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn amend_failing_risk_puts_the_original_back_in_place() {
+        let engine = engine();
+        engine.risk_manager().set_position_limit("AAPL", dec!(10));
+        engine
+            .process_order(order(1, OrderSide::Buy, dec!(100), dec!(5)))
+            .await;
+        engine
+            .process_order(order(2, OrderSide::Buy, dec!(100), dec!(5)))
+            .await;
+        let mut reports = engine.subscribe_reports();
+
+        engine
+            .process_modification("AAPL", 1, None, Some(dec!(11)))
+            .await;
+
+        let reports = drain(&mut reports);
+        assert_eq!(
+            exec_types(&reports),
+            vec![ExecType::ReplaceRejected(RejectReason::Risk(
+                RiskRejection::PositionLimit
+            ))]
+        );
+        assert_eq!(reports[0].leaves_quantity, dec!(5));
+        let level = engine.order_book.bids.get("AAPL").unwrap()[&Reverse(dec!(100))]
+            .iter()
+            .map(|o| (o.id, o.quantity))
+            .collect::<Vec<_>>();
+        assert_eq!(level, vec![(1, dec!(5)), (2, dec!(5))]);
+    }
+
+    #[tokio::test]
+    async fn amend_passing_risk_is_replaced() {
+        let engine = engine();
+        engine.risk_manager().set_position_limit("AAPL", dec!(10));
+        engine
+            .process_order(order(1, OrderSide::Buy, dec!(100), dec!(5)))
+            .await;
+        let mut reports = engine.subscribe_reports();
+
+        engine
+            .process_modification("AAPL", 1, Some(dec!(99)), Some(dec!(10)))
+            .await;
+
+        let reports = drain(&mut reports);
+        assert_eq!(exec_types(&reports), vec![ExecType::Replaced]);
+        assert_eq!(
+            (reports[0].price, reports[0].leaves_quantity),
+            (dec!(99), dec!(10))
+        );
+    }
+
+    #[tokio::test]
+    async fn requests_for_unknown_orders_are_rejected() {
+        let engine = engine();
+        let mut reports = engine.subscribe_reports();
+
+        engine.process_cancellation("AAPL", 42).await;
+        engine
+            .process_modification("AAPL", 42, None, Some(dec!(1)))
+            .await;
+
+        let reports = drain(&mut reports);
+        assert_eq!(
+            exec_types(&reports),
+            vec![
+                ExecType::CancelRejected(RejectReason::UnknownOrder),
+                ExecType::ReplaceRejected(RejectReason::UnknownOrder),
+            ]
+        );
+        for report in &reports {
+            assert_eq!(report.order_id, Some(42));
+            assert_eq!(report.symbol.as_deref(), Some("AAPL"));
+            assert_eq!((report.account_id, report.client_order_id), (None, None));
+            assert_eq!(report.side, None);
+        }
+    }
+
+    #[tokio::test]
+    async fn client_cancels_for_unknown_orders_are_rejected() {
+        let (engine, engine_tx, mut message_rx) = MatchingEngine::new(
+            Arc::new(OrderBook::new()),
+            Arc::new(RiskManager::new(dec!(1_000_000))),
+        );
+        let mut reports = engine.subscribe_reports();
+
+        engine_tx
+            .send(EngineMessage::CancelClientOrder {
+                account_id: 3,
+                client_order_id: 9,
+            })
+            .unwrap();
+        drop(engine_tx);
+        engine.run(&mut message_rx).await;
+
+        let reports = drain(&mut reports);
+        assert_eq!(
+            exec_types(&reports),
+            vec![ExecType::CancelRejected(RejectReason::UnknownOrder)]
+        );
+        assert_eq!(
+            (reports[0].account_id, reports[0].client_order_id),
+            (Some(3), Some(9))
+        );
+        assert_eq!(
+            (reports[0].order_id, reports[0].symbol.as_deref()),
+            (None, None)
+        );
+        assert_eq!(reports[0].side, None);
+    }
+
+    fn long_ten_at_100_with_loss_limit(engine: &MatchingEngine) {
+        let risk = engine.risk_manager();
+        risk.record_transaction(1, "AAPL", dec!(100), dec!(10), OrderSide::Buy);
+        risk.set_loss_limits(
+            1,
+            LossLimits {
+                max_loss: Some(dec!(50)),
+                max_drawdown: None,
+            },
+        );
+    }
+
+    #[tokio::test]
+    async fn stop_queued_behind_a_halting_trade_is_cancelled() {
+        let engine = engine();
+        long_ten_at_100_with_loss_limit(&engine);
+        engine
+            .process_order(order(1, OrderSide::Buy, dec!(95), dec!(10)))
+            .await;
+        engine
+            .process_order(order(3, OrderSide::Buy, dec!(90), dec!(10)))
+            .await;
+        let stop = Order {
+            order_type: OrderType::Stop {
+                stop_price: dec!(96),
+            },
+            client_order_id: 7,
+            ..order(1, OrderSide::Sell, Decimal::ZERO, dec!(10))
+        };
+        engine.process_order(stop).await;
+        let stop_id = engine.order_id_for_client(1, 7);
+        let mut reports = engine.subscribe_reports();
+
+        engine
+            .process_order(order(2, OrderSide::Sell, dec!(95), dec!(10)))
+            .await;
+
+        assert!(engine.risk_manager().is_halted(1));
+        let reports = drain(&mut reports);
+        let stop_reports: Vec<_> = reports
+            .iter()
+            .filter(|r| r.order_id == stop_id)
+            .map(|r| r.exec_type.clone())
+            .collect();
+        assert_eq!(stop_reports, vec![ExecType::Cancelled]);
+        assert_eq!(engine.order_book.get_best_bid("AAPL"), Some(dec!(90)));
+    }
+
+    #[tokio::test]
+    async fn loss_limits_follow_the_book_between_fills() {
+        let engine = engine();
+        long_ten_at_100_with_loss_limit(&engine);
+        engine.order_book.add_order(Order {
+            id: 100,
+            ..order(2, OrderSide::Buy, dec!(94), dec!(1))
+        });
+        engine.order_book.add_order(Order {
+            id: 101,
+            ..order(2, OrderSide::Sell, dec!(96), dec!(1))
+        });
+
+        engine.enforce_loss_limits(&[2]);
+        assert!(!engine.risk_manager().is_halted(1));
+
+        engine.enforce_loss_limits(&engine.risk_manager().position_accounts());
+        assert!(engine.risk_manager().is_halted(1));
+    }
+
+    #[tokio::test]
+    async fn amends_are_throttled_as_orders_or_cancels() {
+        let engine = engine();
+        engine.set_throttle_limits(ThrottleLimits {
+            max_orders_per_sec: Some(1),
+            max_cancels_per_sec: Some(1),
+            ..ThrottleLimits::default()
+        });
+        engine
+            .process_order(order(1, OrderSide::Buy, dec!(100), dec!(10)))
+            .await;
+        let mut reports = engine.subscribe_reports();
+
+        engine
+            .process_modification("AAPL", 1, Some(dec!(99)), None)
+            .await;
+        engine
+            .process_modification("AAPL", 1, None, Some(dec!(8)))
+            .await;
+        engine
+            .process_modification("AAPL", 1, None, Some(dec!(6)))
+            .await;
+
+        assert_eq!(
+            exec_types(&drain(&mut reports)),
+            vec![
+                ExecType::ReplaceRejected(RejectReason::Throttled(ThrottleRejection::OrderRate)),
+                ExecType::Replaced,
+                ExecType::ReplaceRejected(RejectReason::Throttled(ThrottleRejection::CancelRate)),
+            ]
+        );
+        assert_eq!(engine.order_book.get_order(1).unwrap().quantity, dec!(8));
+    }
+
+    #[tokio::test]
+    async fn margin_is_held_against_the_position_after_each_fill() {
+        let engine = engine();
+        let risk = engine.risk_manager();
+        risk.open_account(1, AccountType::Margin, dec!(100_000));
+        risk.record_transaction(1, "AAPL", dec!(100), dec!(50), OrderSide::Buy);
+        engine
+            .process_order(order(1, OrderSide::Sell, dec!(100), dec!(100)))
+            .await;
+        assert_eq!(risk.margin_account(1).unwrap().reserved(), dec!(2500));
+
+        engine
+            .process_order(order(2, OrderSide::Buy, dec!(100), dec!(40)))
+            .await;
+        assert_eq!(risk.margin_account(1).unwrap().reserved(), dec!(2500));
+
+        engine
+            .process_order(order(2, OrderSide::Buy, dec!(100), dec!(60)))
+            .await;
+        assert_eq!(risk.margin_account(1).unwrap().reserved(), Decimal::ZERO);
+    }
+
+    #[tokio::test]
+    async fn session_close_sets_previous_closes_and_ages_restrictions() {
+        let engine = engine();
+        let short_sales = engine.risk_manager().short_sales();
+        engine.set_session_close(NaiveTime::from_hms_opt(20, 0, 0).unwrap());
+        let midnight = Utc::now().date_naive().and_hms_opt(0, 0, 0).unwrap();
+        let at = |hours| midnight.and_utc() + Duration::hours(hours);
+
+        assert!(!engine.roll_session(at(12)));
+        engine.order_book.record_trade_price("AAPL", dec!(100));
+        assert!(!engine.roll_session(at(19)));
+        assert!(engine.roll_session(at(20)));
+        assert!(!engine.roll_session(at(21)));
+
+        assert!(short_sales.on_trade("AAPL", dec!(90)));
+        assert!(short_sales.is_restricted("AAPL"));
+        assert!(engine.roll_session(at(44)));
+        assert!(short_sales.is_restricted("AAPL"));
+        assert!(engine.roll_session(at(68)));
+        assert!(!short_sales.is_restricted("AAPL"));
+    }
+
+    async fn quoted(engine: &MatchingEngine) {
+        engine
+            .process_order(order(2, OrderSide::Buy, dec!(99), dec!(10)))
+            .await;
+        engine
+            .process_order(order(2, OrderSide::Sell, dec!(101), dec!(10)))
+            .await;
+    }
+
+    fn post_only(side: OrderSide, price: Decimal, reprice: bool) -> Order {
+        Order {
+            order_type: OrderType::PostOnly { reprice },
+            ..order(1, side, price, dec!(5))
+        }
+    }
+
+    fn pegged(side: OrderSide, peg: PegType) -> Order {
+        Order {
+            order_type: OrderType::Pegged {
+                peg,
+                offset: Decimal::ZERO,
+            },
+            ..order(1, side, Decimal::ZERO, dec!(5))
+        }
+    }
+
+    #[tokio::test]
+    async fn crossing_post_only_is_rejected() {
+        let engine = engine();
+        quoted(&engine).await;
+        let mut reports = engine.subscribe_reports();
+
+        engine
+            .process_order(post_only(OrderSide::Buy, dec!(101), false))
+            .await;
+
+        assert_eq!(
+            exec_types(&drain(&mut reports)),
+            vec![ExecType::Rejected(RejectReason::WouldCrossSpread)]
+        );
+        assert_eq!(engine.order_book.get_order_book_depth("AAPL"), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn repricing_post_only_rests_one_tick_inside() {
+        let engine = engine();
+        quoted(&engine).await;
+        let mut reports = engine.subscribe_reports();
+
+        engine
+            .process_order(post_only(OrderSide::Buy, dec!(102), true))
+            .await;
+
+        let reports = drain(&mut reports);
+        assert_eq!(exec_types(&reports), vec![ExecType::New]);
+        assert_eq!(reports[0].price, dec!(100.99));
+        assert_eq!(engine.order_book.get_best_bid("AAPL"), Some(dec!(100.99)));
+        assert_eq!(engine.order_book.get_best_ask("AAPL"), Some(dec!(101)));
+    }
+
+    #[tokio::test]
+    async fn pegs_rest_at_their_reference_price() {
+        let cases = [
+            (PegType::Primary, OrderSide::Buy, dec!(99)),
+            (PegType::Primary, OrderSide::Sell, dec!(101)),
+            (PegType::Market, OrderSide::Buy, dec!(100.99)),
+            (PegType::Market, OrderSide::Sell, dec!(99.01)),
+            (PegType::Midpoint, OrderSide::Buy, dec!(100)),
+            (PegType::Midpoint, OrderSide::Sell, dec!(100)),
+        ];
+        for (peg, side, expected) in cases {
+            let engine = engine();
+            quoted(&engine).await;
+
+            engine.process_order(pegged(side.clone(), peg)).await;
+
+            let resting = engine.order_book.get_order(3).unwrap();
+            assert_eq!(resting.price, expected, "{:?} {:?}", peg, side);
+        }
+    }
+
+    fn bid_level(engine: &MatchingEngine, price: Decimal) -> Vec<u64> {
+        engine.order_book.bids.get("AAPL").unwrap()[&Reverse(price)]
+            .iter()
+            .map(|o| o.id)
+            .collect()
+    }
+
+    /// Bid 99 (order 1) over 98 (order 4), with a primary peg (order 3)
+    /// resting behind order 1 and older than order 4.
+    async fn peg_behind_the_best_bid(engine: &MatchingEngine) {
+        quoted(engine).await;
+        engine
+            .process_order(pegged(OrderSide::Buy, PegType::Primary))
+            .await;
+        engine
+            .process_order(order(3, OrderSide::Buy, dec!(98), dec!(10)))
+            .await;
+        assert_eq!(bid_level(engine, dec!(99)), vec![1, 3]);
+    }
+
+    #[tokio::test]
+    async fn peg_follows_the_book_after_a_cancel_and_keeps_its_priority() {
+        let engine = engine();
+        peg_behind_the_best_bid(&engine).await;
+
+        engine.process_cancellation("AAPL", 1).await;
+
+        assert_eq!(bid_level(&engine, dec!(98)), vec![3, 4]);
+    }
+
+    #[tokio::test]
+    async fn peg_follows_the_book_after_a_trade_and_keeps_its_priority() {
+        let engine = engine();
+        peg_behind_the_best_bid(&engine).await;
+
+        engine
+            .process_order(order(4, OrderSide::Sell, dec!(99), dec!(10)))
+            .await;
+
+        assert!(engine.order_book.get_order(1).is_none());
+        assert_eq!(bid_level(&engine, dec!(98)), vec![3, 4]);
+    }
+
+    #[tokio::test]
+    async fn refilled_iceberg_queues_behind_later_orders() {
+        let engine = engine();
+        engine
+            .process_order(Order {
+                display_quantity: Some(dec!(2)),
+                ..order(1, OrderSide::Buy, dec!(100), dec!(10))
+            })
+            .await;
+        engine
+            .process_order(order(2, OrderSide::Buy, dec!(100), dec!(5)))
+            .await;
+
+        engine
+            .process_order(order(3, OrderSide::Sell, dec!(100), dec!(2)))
+            .await;
+
+        let iceberg = engine.order_book.get_order(1).unwrap();
+        assert_eq!(
+            (iceberg.quantity, iceberg.hidden_quantity),
+            (dec!(2), dec!(6))
+        );
+        assert_eq!(bid_level(&engine, dec!(100)), vec![2, 1]);
+
+        let mut reports = engine.subscribe_reports();
+        engine
+            .process_order(order(3, OrderSide::Sell, dec!(100), dec!(5)))
+            .await;
+
+        assert_eq!(traded(&drain(&mut reports), 2), dec!(5));
+        assert_eq!(bid_level(&engine, dec!(100)), vec![1]);
+    }
+
+    #[test]
+    fn sequencer_ids_are_unique_and_increasing() {
+        let sequencer = Arc::new(Sequencer::new());
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let sequencer = sequencer.clone();
+                std::thread::spawn(move || {
+                    (0..1_000)
+                        .map(|_| sequencer.next_order_id())
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+
+        let mut all = Vec::new();
+        for handle in handles {
+            let ids = handle.join().unwrap();
+            assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+            all.extend(ids);
+        }
+        all.sort_unstable();
+        all.dedup();
+        assert_eq!(all.len(), 4_000);
+        assert_eq!(sequencer.next_order_id(), 4_001);
+    }
+
+    #[tokio::test]
+    async fn reports_are_sequenced_in_publish_order() {
+        let engine = engine();
+        let mut reports = engine.subscribe_reports();
+        engine
+            .process_order(order(1, OrderSide::Buy, dec!(100), dec!(10)))
+            .await;
+        engine
+            .process_order(order(2, OrderSide::Sell, dec!(100), dec!(4)))
+            .await;
+
+        let sequences: Vec<_> = drain(&mut reports).iter().map(|r| r.sequence).collect();
+        assert!(sequences.len() >= 4);
+        assert!(sequences.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[tokio::test]
+    async fn client_order_ids_are_unique_among_working_orders() {
+        let engine = engine();
+        engine.risk_manager().set_position_limit("AAPL", dec!(10));
+        let with_client_id = |price, quantity| Order {
+            client_order_id: 7,
+            ..order(1, OrderSide::Buy, price, quantity)
+        };
+        let mut reports = engine.subscribe_reports();
+
+        // Rejected on risk, so the ID is never taken
+        engine
+            .process_order(with_client_id(dec!(99), dec!(11)))
+            .await;
+        assert_eq!(engine.order_id_for_client(1, 7), None);
+
+        engine
+            .process_order(with_client_id(dec!(99), dec!(5)))
+            .await;
+        let working = engine.order_id_for_client(1, 7);
+        engine
+            .process_order(with_client_id(dec!(98), dec!(5)))
+            .await;
+
+        assert_eq!(
+            exec_types(&drain(&mut reports)),
+            vec![
+                ExecType::Rejected(RejectReason::Risk(RiskRejection::PositionLimit)),
+                ExecType::New,
+                ExecType::Rejected(RejectReason::DuplicateClientOrderId),
+            ]
+        );
+        assert_eq!(engine.order_id_for_client(1, 7), working);
+        assert_eq!(engine.order_book.get_order_book_depth("AAPL"), (1, 0));
+
+        // Free again once the order is done
+        engine.process_cancellation("AAPL", working.unwrap()).await;
+        engine
+            .process_order(with_client_id(dec!(98), dec!(5)))
+            .await;
+        assert_eq!(
+            exec_types(&drain(&mut reports)).last(),
+            Some(&ExecType::New)
+        );
+    }
+}
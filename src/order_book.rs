@@ -1,4 +1,6 @@
 use crate::market_data::MinuteData;
+use crate::matching_engine::Sequencer;
+use chrono::{DateTime, Utc};
 use dashmap::DashMap;
 use rust_decimal::prelude::ToPrimitive;
 use rust_decimal::Decimal;
@@ -12,6 +14,66 @@ use std::sync::Mutex;
 pub enum OrderType {
     Market,
     Limit,
+    /// Becomes a market order once the last trade price reaches `stop_price`.
+    Stop {
+        stop_price: Decimal,
+    },
+    /// Becomes a limit order at `price` once the last trade price reaches `stop_price`.
+    StopLimit {
+        stop_price: Decimal,
+    },
+    /// Limit order that may only add liquidity. If it would lock or cross the
+    /// spread it is rejected, or with `reprice` set, moved one tick inside.
+    PostOnly {
+        reprice: bool,
+    },
+    /// Rests at `offset` from a price derived from the book and follows that
+    /// price as the book moves.
+    Pegged {
+        peg: PegType,
+        offset: Decimal,
+    },
+}
+
+/// Reference price a pegged order tracks.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PegType {
+    /// Same-side best price: best bid for buys, best ask for sells.
+    Primary,
+    /// Opposite-side best price: best ask for buys, best bid for sells. Like
+    /// every peg it is held one tick inside that quote, so it rests passively
+    /// one tick off the far touch rather than taking it.
+    Market,
+    /// Midpoint of the best bid and ask.
+    Midpoint,
+}
+
+/// How long an order stays working before the unfilled remainder is cancelled.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TimeInForce {
+    /// Rests until filled or cancelled.
+    #[default]
+    GoodTillCancel,
+    /// Fills what it can on arrival and cancels the rest.
+    ImmediateOrCancel,
+    /// Fills in full on arrival or not at all.
+    FillOrKill,
+    /// Expires at the engine's session close.
+    Day,
+    /// Expires at the given time.
+    GoodTillDate(DateTime<Utc>),
+}
+
+impl TimeInForce {
+    /// Whether an unfilled remainder may rest in the book.
+    pub fn can_rest(&self) -> bool {
+        !matches!(
+            self,
+            TimeInForce::ImmediateOrCancel | TimeInForce::FillOrKill
+        )
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -67,31 +129,194 @@ impl AtomicOrderQueue {
 #[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub struct Order {
+    /// Engine-assigned ID; the matching engine overwrites it on arrival.
     pub id: u64,
+    /// Submitter's own reference, echoed on every execution report.
+    pub client_order_id: u64,
+    /// Participant that owns the order; orders from one account never trade
+    /// with each other.
+    pub account_id: u64,
     pub symbol: String,
     pub price: Decimal,
     pub quantity: Decimal,
     pub order_type: OrderType,
     pub side: OrderSide,
     pub timestamp: i64,
+    pub time_in_force: TimeInForce,
+    /// Peak size shown in the book for iceberg orders; `None` shows everything.
+    pub display_quantity: Option<Decimal>,
+    /// Reserve behind the displayed `quantity` of a resting iceberg order.
+    pub hidden_quantity: Decimal,
+}
+
+impl Order {
+    /// Copy of this order carrying an amended price and/or total quantity,
+    /// with the hidden reserve folded back in and a new timestamp.
+    fn amended(mut self, new_price: Option<Decimal>, new_quantity: Option<Decimal>) -> Order {
+        self.quantity = new_quantity.unwrap_or_else(|| self.total_quantity());
+        self.hidden_quantity = Decimal::ZERO;
+        if let Some(price) = new_price {
+            self.price = price;
+        }
+        self.timestamp = Utc::now().timestamp_nanos_opt().unwrap_or(self.timestamp);
+        self
+    }
+
+    /// Displayed plus hidden quantity.
+    pub fn total_quantity(&self) -> Decimal {
+        self.quantity + self.hidden_quantity
+    }
+
+    /// Takes `quantity` off the order, out of the hidden reserve first so the
+    /// displayed slice keeps its place.
+    pub fn reduce_quantity(&mut self, quantity: Decimal) {
+        let from_hidden = quantity.min(self.hidden_quantity);
+        self.hidden_quantity -= from_hidden;
+        self.quantity -= quantity - from_hidden;
+    }
+
+    /// Moves everything above the display peak into the hidden reserve.
+    fn split_display(&mut self) {
+        if let Some(peak) = self.display_quantity {
+            if self.quantity > peak {
+                self.hidden_quantity += self.quantity - peak;
+                self.quantity = peak;
+            }
+        }
+    }
+
+    /// Tops the displayed slice of an iceberg order back up to its peak from
+    /// the reserve, taking a new timestamp. Returns false once the reserve is
+    /// empty.
+    pub fn refill(&mut self) -> bool {
+        let peak = match self.display_quantity {
+            Some(peak) if self.hidden_quantity > Decimal::ZERO => peak,
+            _ => return false,
+        };
+        let slice = (peak - self.quantity).min(self.hidden_quantity);
+        self.quantity += slice;
+        self.hidden_quantity -= slice;
+        self.timestamp = Utc::now().timestamp_nanos_opt().unwrap_or(self.timestamp);
+        true
+    }
+
+    /// Trigger price for stop and stop-limit orders.
+    pub fn stop_price(&self) -> Option<Decimal> {
+        match self.order_type {
+            OrderType::Stop { stop_price } | OrderType::StopLimit { stop_price } => {
+                Some(stop_price)
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether a trade at `last_price` fires this stop: buy stops trigger at or
+    /// above the stop price, sell stops at or below it.
+    pub fn is_stop_triggered(&self, last_price: Decimal) -> bool {
+        match (self.stop_price(), &self.side) {
+            (Some(stop_price), OrderSide::Buy) => last_price >= stop_price,
+            (Some(stop_price), OrderSide::Sell) => last_price <= stop_price,
+            (None, _) => false,
+        }
+    }
+
+    /// Converts a fired stop into the order it releases: stops become market
+    /// orders and stop-limits become limit orders, queued from the trigger time.
+    pub fn into_triggered(mut self) -> Order {
+        self.order_type = match self.order_type {
+            OrderType::Stop { .. } => OrderType::Market,
+            OrderType::StopLimit { .. } => OrderType::Limit,
+            other => other,
+        };
+        self.timestamp = Utc::now().timestamp_nanos_opt().unwrap_or(self.timestamp);
+        self
+    }
+}
+
+/// Outcome of [`OrderBook::amend_order`].
+#[derive(Debug)]
+pub enum AmendResult {
+    /// Quantity reduced in place; the order keeps its place in the queue.
+    Amended,
+    /// Order taken out of the book with the new terms applied, to be
+    /// re-entered behind everything already resting.
+    Replaced(Order),
+    NotFound,
+    Rejected(&'static str),
 }
 
+/// Aggregated `(price, displayed quantity)` levels, best price first.
+#[derive(Debug, Clone, Default)]
+pub struct BookSnapshot {
+    pub bids: Vec<(Decimal, Decimal)>,
+    pub asks: Vec<(Decimal, Decimal)>,
+}
+
+/// Stop and stop-limit orders for one symbol, waiting on the last trade price.
+#[derive(Default)]
+pub struct StopBook {
+    buy_stops: BTreeMap<Decimal, Vec<Order>>,
+    sell_stops: BTreeMap<Reverse<Decimal>, Vec<Order>>,
+}
+
+#[allow(dead_code)]
+impl StopBook {
+    pub fn len(&self) -> usize {
+        self.buy_stops
+            .values()
+            .map(|orders| orders.len())
+            .sum::<usize>()
+            + self
+                .sell_stops
+                .values()
+                .map(|orders| orders.len())
+                .sum::<usize>()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buy_stops.is_empty() && self.sell_stops.is_empty()
+    }
+}
+
+/// Default minimum price increment for symbols without an explicit tick size.
+pub const DEFAULT_TICK_SIZE: Decimal = Decimal::from_parts(1, 0, 0, false, 2);
+
 pub struct OrderBook {
     pub bids: DashMap<String, BTreeMap<Reverse<Decimal>, Vec<Order>>>,
     pub asks: DashMap<String, BTreeMap<Decimal, Vec<Order>>>,
     pub order_index: DashMap<u64, (String, Decimal, OrderSide)>,
 
+    // Trigger book for stop orders, indexed by stop price
+    pub stops: DashMap<String, StopBook>,
+    stop_index: DashMap<u64, (String, Decimal, OrderSide)>,
+    last_trade_prices: DashMap<String, Decimal>,
+
+    tick_sizes: DashMap<String, Decimal>,
+    // Reference quotes pegged orders were last priced from, per symbol with pegs
+    peg_references: DashMap<String, (Option<Decimal>, Option<Decimal>)>,
+
     // Performance tracking
     order_operations: AtomicUsize,
 }
 
 #[allow(dead_code)]
+impl Default for OrderBook {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl OrderBook {
     pub fn new() -> Self {
         OrderBook {
             bids: DashMap::new(),
             asks: DashMap::new(),
             order_index: DashMap::new(),
+            stops: DashMap::new(),
+            stop_index: DashMap::new(),
+            last_trade_prices: DashMap::new(),
+            tick_sizes: DashMap::new(),
+            peg_references: DashMap::new(),
             order_operations: AtomicUsize::new(0),
         }
     }
@@ -102,17 +327,40 @@ impl OrderBook {
         historical_data: &[MinuteData],
         symbol: &str,
         tick_size: Decimal,
+        sequencer: &Sequencer,
     ) {
         for data in historical_data {
-            self.update_from_market_data(symbol, data, tick_size);
+            self.update_from_market_data(symbol, data, tick_size, sequencer);
+        }
+    }
+
+    /// Replays timestamped bars, as produced by `bar_loader`, in order.
+    #[allow(dead_code)]
+    pub fn load_historical_bars(
+        &self,
+        bars: &[(DateTime<Utc>, MinuteData)],
+        symbol: &str,
+        tick_size: Decimal,
+        sequencer: &Sequencer,
+    ) {
+        for (_, data) in bars {
+            self.update_from_market_data(symbol, data, tick_size, sequencer);
         }
     }
 
-    pub fn add_order(&self, order: Order) {
+    pub fn add_order(&self, mut order: Order) {
         // Increment operation counter
         self.order_operations.fetch_add(1, Ordering::Relaxed);
 
-        if order.order_type != OrderType::Limit {
+        if order.stop_price().is_some() {
+            self.add_stop_order(order);
+            return;
+        }
+
+        if !matches!(
+            order.order_type,
+            OrderType::Limit | OrderType::PostOnly { .. } | OrderType::Pegged { .. }
+        ) {
             log::warn!(
                 "Unsupported order type for OrderBook: {:?}",
                 order.order_type
@@ -130,6 +378,26 @@ impl OrderBook {
             return;
         }
 
+        if order
+            .display_quantity
+            .is_some_and(|peak| peak <= Decimal::ZERO)
+        {
+            log::warn!(
+                "Invalid display quantity {:?} for order ID {}",
+                order.display_quantity,
+                order.id
+            );
+            return;
+        }
+        order.split_display();
+
+        if matches!(order.order_type, OrderType::Pegged { .. }) {
+            let reference = self.reference_quotes(&order.symbol);
+            self.peg_references
+                .entry(order.symbol.clone())
+                .or_insert(reference);
+        }
+
         // Store order index
         self.order_index.insert(
             order.id,
@@ -139,75 +407,525 @@ impl OrderBook {
         match order.side {
             OrderSide::Buy => {
                 let price_key = Reverse(order.price);
-                let mut bids = self
-                    .bids
-                    .entry(order.symbol.clone())
-                    .or_insert_with(BTreeMap::new);
-                bids.entry(price_key).or_insert_with(Vec::new).push(order);
+                let mut bids = self.bids.entry(order.symbol.clone()).or_default();
+                bids.entry(price_key).or_default().push(order);
             }
             OrderSide::Sell => {
                 let price_key = order.price;
-                let mut asks = self
-                    .asks
-                    .entry(order.symbol.clone())
-                    .or_insert_with(BTreeMap::new);
-                asks.entry(price_key).or_insert_with(Vec::new).push(order);
+                let mut asks = self.asks.entry(order.symbol.clone()).or_default();
+                asks.entry(price_key).or_default().push(order);
             }
         }
     }
 
-    pub fn cancel_order(&self, order_id: u64) -> bool {
-        // Increment operation counter
-        self.order_operations.fetch_add(1, Ordering::Relaxed);
+    fn add_stop_order(&self, order: Order) {
+        let stop_price = order.stop_price().unwrap_or(Decimal::ZERO);
+        let limit_ok =
+            matches!(order.order_type, OrderType::Stop { .. }) || order.price > Decimal::ZERO;
 
-        if let Some((_, (sym, price, side))) = self.order_index.remove(&order_id) {
-            match side {
+        if stop_price <= Decimal::ZERO || order.quantity <= Decimal::ZERO || !limit_ok {
+            log::warn!(
+                "Invalid stop order: stop {} price {} quantity {} for order ID {}",
+                stop_price,
+                order.price,
+                order.quantity,
+                order.id
+            );
+            return;
+        }
+
+        self.stop_index.insert(
+            order.id,
+            (order.symbol.clone(), stop_price, order.side.clone()),
+        );
+
+        let mut stops = self.stops.entry(order.symbol.clone()).or_default();
+        match order.side {
+            OrderSide::Buy => stops.buy_stops.entry(stop_price).or_default().push(order),
+            OrderSide::Sell => stops
+                .sell_stops
+                .entry(Reverse(stop_price))
+                .or_default()
+                .push(order),
+        }
+    }
+
+    /// Removes and returns every stop on `symbol` fired by a trade at
+    /// `last_price`, in the order the price crossed them: lowest buy stop and
+    /// highest sell stop first, and in time order within a level.
+    pub fn take_triggered_stops(&self, symbol: &str, last_price: Decimal) -> Vec<Order> {
+        let mut triggered = Vec::new();
+
+        if let Some(mut stops) = self.stops.get_mut(symbol) {
+            let buy_levels: Vec<Decimal> = stops
+                .buy_stops
+                .range(..=last_price)
+                .map(|(p, _)| *p)
+                .collect();
+            for price in buy_levels {
+                if let Some(orders) = stops.buy_stops.remove(&price) {
+                    triggered.extend(orders);
+                }
+            }
+
+            let sell_levels: Vec<Reverse<Decimal>> = stops
+                .sell_stops
+                .range(..=Reverse(last_price))
+                .map(|(p, _)| *p)
+                .collect();
+            for price_key in sell_levels {
+                if let Some(orders) = stops.sell_stops.remove(&price_key) {
+                    triggered.extend(orders);
+                }
+            }
+        }
+
+        for order in &triggered {
+            self.stop_index.remove(&order.id);
+        }
+
+        triggered
+    }
+
+    /// Quantity resting against `side` at prices an order limited to `limit`
+    /// would trade with, or across the whole opposite side when `limit` is `None`.
+    /// Orders from `account_id` are left out since they can never fill it.
+    pub fn available_liquidity(
+        &self,
+        symbol: &str,
+        side: &OrderSide,
+        limit: Option<Decimal>,
+        account_id: u64,
+    ) -> Decimal {
+        let tradable = |o: &Order| (o.account_id != account_id).then(|| o.total_quantity());
+
+        match side {
+            OrderSide::Buy => self
+                .asks
+                .get(symbol)
+                .map(|asks| {
+                    asks.iter()
+                        .take_while(|(price, _)| limit.is_none_or(|limit| **price <= limit))
+                        .flat_map(|(_, orders)| orders.iter().filter_map(tradable))
+                        .sum()
+                })
+                .unwrap_or(Decimal::ZERO),
+            OrderSide::Sell => self
+                .bids
+                .get(symbol)
+                .map(|bids| {
+                    bids.iter()
+                        .take_while(|(price, _)| limit.is_none_or(|limit| price.0 >= limit))
+                        .flat_map(|(_, orders)| orders.iter().filter_map(tradable))
+                        .sum()
+                })
+                .unwrap_or(Decimal::ZERO),
+        }
+    }
+
+    pub fn set_tick_size(&self, symbol: &str, tick_size: Decimal) {
+        self.tick_sizes.insert(symbol.to_string(), tick_size);
+    }
+
+    pub fn get_tick_size(&self, symbol: &str) -> Decimal {
+        self.tick_sizes
+            .get(symbol)
+            .map(|tick| *tick)
+            .unwrap_or(DEFAULT_TICK_SIZE)
+    }
+
+    /// Pulls `price` back one tick inside the opposite best price if it would
+    /// lock or cross it. Returns `None` if nothing positive is left.
+    fn passive_price(
+        side: &OrderSide,
+        price: Decimal,
+        opposite: Option<Decimal>,
+        tick_size: Decimal,
+    ) -> Option<Decimal> {
+        let price = match (side, opposite) {
+            (OrderSide::Buy, Some(ask)) if price >= ask => ask - tick_size,
+            (OrderSide::Sell, Some(bid)) if price <= bid => bid + tick_size,
+            _ => price,
+        };
+        (price > Decimal::ZERO).then_some(price)
+    }
+
+    /// Price a post-only order can rest at. Returns the order's own price when
+    /// it does not lock or cross, the nearest passive price when it does and
+    /// `reprice` is set, and `None` when it has to be rejected.
+    pub fn post_only_price(&self, order: &Order, reprice: bool) -> Option<Decimal> {
+        let opposite = match order.side {
+            OrderSide::Buy => self.get_best_ask(&order.symbol),
+            OrderSide::Sell => self.get_best_bid(&order.symbol),
+        };
+        let tick_size = self.get_tick_size(&order.symbol);
+        let passive = Self::passive_price(&order.side, order.price, opposite, tick_size)?;
+
+        if passive == order.price || reprice {
+            Some(passive)
+        } else {
+            None
+        }
+    }
+
+    /// Best bid and ask set by non-pegged orders. Pegs are priced off these so
+    /// they never chase each other.
+    pub fn reference_quotes(&self, symbol: &str) -> (Option<Decimal>, Option<Decimal>) {
+        let is_pegged = |o: &Order| matches!(o.order_type, OrderType::Pegged { .. });
+
+        let bid = self.bids.get(symbol).and_then(|bids| {
+            bids.iter()
+                .find(|(_, orders)| orders.iter().any(|o| !is_pegged(o)))
+                .map(|(price, _)| price.0)
+        });
+        let ask = self.asks.get(symbol).and_then(|asks| {
+            asks.iter()
+                .find(|(_, orders)| orders.iter().any(|o| !is_pegged(o)))
+                .map(|(price, _)| *price)
+        });
+
+        (bid, ask)
+    }
+
+    /// Current price for a pegged order, held one tick inside the opposite
+    /// reference quote. `None` if the order is not pegged or its reference is
+    /// missing.
+    ///
+    /// Pegs only match on arrival. After that `reprice_pegged_orders` moves
+    /// them without running them through matching, so a peg priced at the
+    /// opposite touch would sit locked or crossed against it. Keeping every
+    /// peg passive is what lets repricing skip the matcher.
+    pub fn peg_price(&self, order: &Order) -> Option<Decimal> {
+        self.peg_price_from(
+            order,
+            self.reference_quotes(&order.symbol),
+            self.get_tick_size(&order.symbol),
+        )
+    }
+
+    fn peg_price_from(
+        &self,
+        order: &Order,
+        (bid, ask): (Option<Decimal>, Option<Decimal>),
+        tick_size: Decimal,
+    ) -> Option<Decimal> {
+        let OrderType::Pegged { peg, offset } = order.order_type else {
+            return None;
+        };
+
+        let base = match (peg, &order.side) {
+            (PegType::Primary, OrderSide::Buy) | (PegType::Market, OrderSide::Sell) => bid?,
+            (PegType::Primary, OrderSide::Sell) | (PegType::Market, OrderSide::Buy) => ask?,
+            (PegType::Midpoint, _) => (bid? + ask?) / Decimal::from(2),
+        };
+        let opposite = match order.side {
+            OrderSide::Buy => ask,
+            OrderSide::Sell => bid,
+        };
+
+        Self::passive_price(&order.side, base + offset, opposite, tick_size)
+    }
+
+    /// Symbols holding pegged orders.
+    pub fn pegged_symbols(&self) -> Vec<String> {
+        self.peg_references
+            .iter()
+            .map(|entry| entry.key().clone())
+            .collect()
+    }
+
+    /// Moves pegged orders on `symbol` to follow the reference quotes if they
+    /// changed since the last reprice. A moved peg keeps its original
+    /// timestamp and is queued at its new level by that timestamp. Returns the
+    /// number of orders moved.
+    pub fn reprice_pegged_orders(&self, symbol: &str) -> usize {
+        let reference = self.reference_quotes(symbol);
+        match self.peg_references.get_mut(symbol) {
+            Some(mut last) if *last != reference => *last = reference,
+            _ => return 0,
+        }
+
+        let tick_size = self.get_tick_size(symbol);
+        let needs_move = |o: &Order| {
+            self.peg_price_from(o, reference, tick_size)
+                .is_some_and(|price| price != o.price)
+        };
+
+        let mut moved = Vec::new();
+        if let Some(mut bids) = self.bids.get_mut(symbol) {
+            bids.retain(|_, orders| {
+                take_matching(orders, needs_move, &mut moved);
+                !orders.is_empty()
+            });
+        }
+        if let Some(mut asks) = self.asks.get_mut(symbol) {
+            asks.retain(|_, orders| {
+                take_matching(orders, needs_move, &mut moved);
+                !orders.is_empty()
+            });
+        }
+
+        let count = moved.len();
+        for mut order in moved {
+            if let Some(price) = self.peg_price_from(&order, reference, tick_size) {
+                order.price = price;
+            }
+            self.order_index.insert(
+                order.id,
+                (order.symbol.clone(), order.price, order.side.clone()),
+            );
+            match order.side {
                 OrderSide::Buy => {
-                    if let Some(mut bids) = self.bids.get_mut(&sym) {
-                        let price_key = Reverse(price);
-                        if let Entry::Occupied(mut price_entry) = bids.entry(price_key) {
-                            let (modified, is_empty) = {
-                                let orders = price_entry.get_mut();
-                                let len_before = orders.len();
-                                orders.retain(|o| o.id != order_id);
-                                (len_before != orders.len(), orders.is_empty())
-                            };
-
-                            if is_empty {
-                                price_entry.remove_entry();
-                            }
-                            return modified;
-                        }
-                    }
+                    let mut bids = self.bids.entry(order.symbol.clone()).or_default();
+                    insert_by_time(bids.entry(Reverse(order.price)).or_default(), order);
                 }
                 OrderSide::Sell => {
-                    if let Some(mut asks) = self.asks.get_mut(&sym) {
-                        let price_key = price;
-                        if let Entry::Occupied(mut price_entry) = asks.entry(price_key) {
-                            let (modified, is_empty) = {
-                                let orders = price_entry.get_mut();
-                                let len_before = orders.len();
-                                orders.retain(|o| o.id != order_id);
-                                (len_before != orders.len(), orders.is_empty())
-                            };
-
-                            if is_empty {
-                                price_entry.remove_entry();
-                            }
-                            return modified;
-                        }
-                    }
+                    let mut asks = self.asks.entry(order.symbol.clone()).or_default();
+                    insert_by_time(asks.entry(order.price).or_default(), order);
+                }
+            }
+        }
+
+        count
+    }
+
+    pub fn record_trade_price(&self, symbol: &str, price: Decimal) {
+        self.last_trade_prices.insert(symbol.to_string(), price);
+    }
+
+    pub fn get_last_trade_price(&self, symbol: &str) -> Option<Decimal> {
+        self.last_trade_prices.get(symbol).map(|price| *price)
+    }
+
+    /// Last trade price of every symbol that has traded.
+    pub fn last_trade_prices(&self) -> Vec<(String, Decimal)> {
+        self.last_trade_prices
+            .iter()
+            .map(|entry| (entry.key().clone(), *entry.value()))
+            .collect()
+    }
+
+    pub fn get_stop_depth(&self, symbol: &str) -> usize {
+        self.stops.get(symbol).map(|stops| stops.len()).unwrap_or(0)
+    }
+
+    fn take_stop_order(&self, order_id: u64) -> Option<Order> {
+        let (_, (sym, stop_price, side)) = self.stop_index.remove(&order_id)?;
+        let mut stops = self.stops.get_mut(&sym)?;
+        let orders = match side {
+            OrderSide::Buy => stops.buy_stops.get_mut(&stop_price),
+            OrderSide::Sell => stops.sell_stops.get_mut(&Reverse(stop_price)),
+        }?;
+
+        let order = take_from_level(orders, order_id)?;
+        if orders.is_empty() {
+            match side {
+                OrderSide::Buy => stops.buy_stops.remove(&stop_price),
+                OrderSide::Sell => stops.sell_stops.remove(&Reverse(stop_price)),
+            };
+        }
+        Some(order)
+    }
+
+    /// Changes the price and/or total quantity of a resting order. A quantity
+    /// cut at the same price is applied in place and keeps time priority. Any
+    /// other change takes the order out of the book, and its index entry with
+    /// it, so the caller can re-enter it at the back of the queue.
+    pub fn amend_order(
+        &self,
+        order_id: u64,
+        new_price: Option<Decimal>,
+        new_quantity: Option<Decimal>,
+    ) -> AmendResult {
+        self.order_operations.fetch_add(1, Ordering::Relaxed);
+
+        if new_quantity.is_some_and(|qty| qty <= Decimal::ZERO)
+            || new_price.is_some_and(|price| price <= Decimal::ZERO)
+        {
+            return AmendResult::Rejected("price and quantity must be positive");
+        }
+
+        let Some((sym, price, side)) = self.order_index.get(&order_id).map(|e| e.clone()) else {
+            return match self.take_stop_order(order_id) {
+                Some(order) => AmendResult::Replaced(order.amended(new_price, new_quantity)),
+                None => AmendResult::NotFound,
+            };
+        };
+
+        match side {
+            OrderSide::Buy => {
+                let Some(mut bids) = self.bids.get_mut(&sym) else {
+                    return AmendResult::NotFound;
+                };
+                let Entry::Occupied(mut level) = bids.entry(Reverse(price)) else {
+                    return AmendResult::NotFound;
+                };
+                let result = amend_in_level(level.get_mut(), order_id, new_price, new_quantity);
+                if level.get().is_empty() {
+                    level.remove_entry();
+                }
+                if let AmendResult::Replaced(_) = result {
+                    self.order_index.remove(&order_id);
+                }
+                result
+            }
+            OrderSide::Sell => {
+                let Some(mut asks) = self.asks.get_mut(&sym) else {
+                    return AmendResult::NotFound;
+                };
+                let Entry::Occupied(mut level) = asks.entry(price) else {
+                    return AmendResult::NotFound;
+                };
+                let result = amend_in_level(level.get_mut(), order_id, new_price, new_quantity);
+                if level.get().is_empty() {
+                    level.remove_entry();
+                }
+                if let AmendResult::Replaced(_) = result {
+                    self.order_index.remove(&order_id);
+                }
+                result
+            }
+        }
+    }
+
+    /// Puts back an order `amend_order` took out, in the place it had: it is
+    /// queued at its level by its original timestamp.
+    pub fn restore_order(&self, order: Order) {
+        self.order_operations.fetch_add(1, Ordering::Relaxed);
+
+        if let Some(stop_price) = order.stop_price() {
+            self.stop_index.insert(
+                order.id,
+                (order.symbol.clone(), stop_price, order.side.clone()),
+            );
+            let mut stops = self.stops.entry(order.symbol.clone()).or_default();
+            match order.side {
+                OrderSide::Buy => {
+                    insert_by_time(stops.buy_stops.entry(stop_price).or_default(), order)
+                }
+                OrderSide::Sell => insert_by_time(
+                    stops.sell_stops.entry(Reverse(stop_price)).or_default(),
+                    order,
+                ),
+            }
+            return;
+        }
+
+        self.order_index.insert(
+            order.id,
+            (order.symbol.clone(), order.price, order.side.clone()),
+        );
+        match order.side {
+            OrderSide::Buy => {
+                let mut bids = self.bids.entry(order.symbol.clone()).or_default();
+                insert_by_time(bids.entry(Reverse(order.price)).or_default(), order);
+            }
+            OrderSide::Sell => {
+                let mut asks = self.asks.entry(order.symbol.clone()).or_default();
+                insert_by_time(asks.entry(order.price).or_default(), order);
+            }
+        }
+    }
+
+    /// Removes a resting or stop order and returns it.
+    pub fn take_order(&self, order_id: u64) -> Option<Order> {
+        // Increment operation counter
+        self.order_operations.fetch_add(1, Ordering::Relaxed);
+
+        let Some((_, (sym, price, side))) = self.order_index.remove(&order_id) else {
+            return self.take_stop_order(order_id);
+        };
+
+        match side {
+            OrderSide::Buy => {
+                let mut bids = self.bids.get_mut(&sym)?;
+                let Entry::Occupied(mut level) = bids.entry(Reverse(price)) else {
+                    return None;
+                };
+                let order = take_from_level(level.get_mut(), order_id);
+                if level.get().is_empty() {
+                    level.remove_entry();
+                }
+                order
+            }
+            OrderSide::Sell => {
+                let mut asks = self.asks.get_mut(&sym)?;
+                let Entry::Occupied(mut level) = asks.entry(price) else {
+                    return None;
+                };
+                let order = take_from_level(level.get_mut(), order_id);
+                if level.get().is_empty() {
+                    level.remove_entry();
                 }
+                order
             }
         }
-        false
+    }
+
+    pub fn cancel_order(&self, order_id: u64) -> bool {
+        self.take_order(order_id).is_some()
+    }
+
+    /// Removes every resting and stop order belonging to `account_id`.
+    pub fn take_account_orders(&self, account_id: u64) -> Vec<Order> {
+        let owned = |orders: &Vec<Order>| {
+            orders
+                .iter()
+                .filter(|o| o.account_id == account_id)
+                .map(|o| o.id)
+                .collect::<Vec<_>>()
+        };
+
+        let mut ids: Vec<u64> = Vec::new();
+        for bids in self.bids.iter() {
+            ids.extend(bids.values().flat_map(owned));
+        }
+        for asks in self.asks.iter() {
+            ids.extend(asks.values().flat_map(owned));
+        }
+        for stops in self.stops.iter() {
+            ids.extend(stops.buy_stops.values().flat_map(owned));
+            ids.extend(stops.sell_stops.values().flat_map(owned));
+        }
+
+        ids.into_iter()
+            .filter_map(|order_id| self.take_order(order_id))
+            .collect()
+    }
+
+    /// Copy of a resting or stop order.
+    pub fn get_order(&self, order_id: u64) -> Option<Order> {
+        if let Some(entry) = self.order_index.get(&order_id) {
+            let (sym, price, side) = entry.value();
+            let find = |orders: &Vec<Order>| orders.iter().find(|o| o.id == order_id).cloned();
+            return match side {
+                OrderSide::Buy => self
+                    .bids
+                    .get(sym)
+                    .and_then(|bids| bids.get(&Reverse(*price)).and_then(find)),
+                OrderSide::Sell => self
+                    .asks
+                    .get(sym)
+                    .and_then(|asks| asks.get(price).and_then(find)),
+            };
+        }
+
+        let entry = self.stop_index.get(&order_id)?;
+        let (sym, stop_price, side) = entry.value();
+        let stops = self.stops.get(sym)?;
+        let orders = match side {
+            OrderSide::Buy => stops.buy_stops.get(stop_price),
+            OrderSide::Sell => stops.sell_stops.get(&Reverse(*stop_price)),
+        }?;
+        orders.iter().find(|o| o.id == order_id).cloned()
     }
 
     // Existing methods from original implementation
     pub fn get_best_bid(&self, symbol: &str) -> Option<Decimal> {
+        // Bids are keyed by `Reverse(price)`, so the highest bid sorts first
         self.bids
             .get(symbol)
-            .and_then(|bids| bids.last_key_value().map(|(price, _)| price.0))
+            .and_then(|bids| bids.first_key_value().map(|(price, _)| price.0))
     }
 
     pub fn get_best_ask(&self, symbol: &str) -> Option<Decimal> {
@@ -223,6 +941,8 @@ impl OrderBook {
         }
     }
 
+    /// Number of resting orders on each side. Iceberg reserves are not counted
+    /// as separate orders.
     pub fn get_order_book_depth(&self, symbol: &str) -> (usize, usize) {
         let bid_depth = self
             .bids
@@ -239,16 +959,248 @@ impl OrderBook {
         (bid_depth, ask_depth)
     }
 
+    /// Best `levels` price levels per side with their displayed size; hidden
+    /// iceberg reserves are left out, as on a public feed.
+    pub fn get_book_snapshot(&self, symbol: &str, levels: usize) -> BookSnapshot {
+        let displayed = |orders: &Vec<Order>| orders.iter().map(|o| o.quantity).sum();
+
+        let bids = self
+            .bids
+            .get(symbol)
+            .map(|bids| {
+                bids.iter()
+                    .take(levels)
+                    .map(|(price, orders)| (price.0, displayed(orders)))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let asks = self
+            .asks
+            .get(symbol)
+            .map(|asks| {
+                asks.iter()
+                    .take(levels)
+                    .map(|(price, orders)| (*price, displayed(orders)))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        BookSnapshot { bids, asks }
+    }
+
     // New performance monitoring method
     pub fn get_operation_count(&self) -> usize {
         self.order_operations.load(Ordering::Relaxed)
     }
 
     #[allow(dead_code)]
-    pub fn update_from_market_data(&self, symbol: &str, data: &MinuteData, tick_size: Decimal) {
+    pub fn update_from_market_data(
+        &self,
+        symbol: &str,
+        data: &MinuteData,
+        tick_size: Decimal,
+        sequencer: &Sequencer,
+    ) {
         let orders = data.to_orders(symbol, tick_size);
-        for order in orders {
+        for mut order in orders {
+            // These bypass the engine, so take IDs from its sequencer directly
+            order.id = sequencer.next_order_id();
             self.add_order(order);
         }
     }
 }
+
+fn take_from_level(orders: &mut Vec<Order>, order_id: u64) -> Option<Order> {
+    let idx = orders.iter().position(|o| o.id == order_id)?;
+    Some(orders.remove(idx))
+}
+
+/// Moves every order matching `pred` from `orders` into `taken`.
+fn take_matching(orders: &mut Vec<Order>, pred: impl Fn(&Order) -> bool, taken: &mut Vec<Order>) {
+    let mut idx = 0;
+    while idx < orders.len() {
+        if pred(&orders[idx]) {
+            taken.push(orders.remove(idx));
+        } else {
+            idx += 1;
+        }
+    }
+}
+
+/// Queues `order` at a price level behind every order with an earlier or equal
+/// timestamp.
+fn insert_by_time(orders: &mut Vec<Order>, order: Order) {
+    let pos = orders
+        .iter()
+        .position(|o| o.timestamp > order.timestamp)
+        .unwrap_or(orders.len());
+    orders.insert(pos, order);
+}
+
+/// Applies an amend to `order_id` within one price level.
+fn amend_in_level(
+    orders: &mut Vec<Order>,
+    order_id: u64,
+    new_price: Option<Decimal>,
+    new_quantity: Option<Decimal>,
+) -> AmendResult {
+    let Some(idx) = orders.iter().position(|o| o.id == order_id) else {
+        return AmendResult::NotFound;
+    };
+    let order = &mut orders[idx];
+
+    let price_change = new_price.is_some_and(|price| price != order.price);
+    if price_change && matches!(order.order_type, OrderType::Pegged { .. }) {
+        return AmendResult::Rejected("pegged orders are priced by the book");
+    }
+
+    let total = order.total_quantity();
+    let quantity = new_quantity.unwrap_or(total);
+    if !price_change && quantity <= total {
+        order.reduce_quantity(total - quantity);
+        return AmendResult::Amended;
+    }
+
+    AmendResult::Replaced(orders.remove(idx).amended(new_price, new_quantity))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn bid(id: u64, price: Decimal, quantity: Decimal) -> Order {
+        Order {
+            id,
+            client_order_id: id,
+            account_id: 1,
+            symbol: "AAPL".to_string(),
+            price,
+            quantity,
+            order_type: OrderType::Limit,
+            side: OrderSide::Buy,
+            timestamp: id as i64,
+            time_in_force: TimeInForce::GoodTillCancel,
+            display_quantity: None,
+            hidden_quantity: Decimal::ZERO,
+        }
+    }
+
+    fn level_ids(book: &OrderBook, price: Decimal) -> Vec<u64> {
+        book.bids
+            .get("AAPL")
+            .and_then(|bids| {
+                bids.get(&Reverse(price))
+                    .map(|orders| orders.iter().map(|o| o.id).collect())
+            })
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn quantity_cut_keeps_queue_position() {
+        let book = OrderBook::new();
+        book.add_order(bid(1, dec!(100), dec!(10)));
+        book.add_order(bid(2, dec!(100), dec!(10)));
+
+        assert!(matches!(
+            book.amend_order(1, None, Some(dec!(4))),
+            AmendResult::Amended
+        ));
+        assert_eq!(level_ids(&book, dec!(100)), vec![1, 2]);
+        assert_eq!(book.get_order(1).unwrap().quantity, dec!(4));
+    }
+
+    #[test]
+    fn quantity_increase_loses_priority() {
+        let book = OrderBook::new();
+        book.add_order(bid(1, dec!(100), dec!(10)));
+        book.add_order(bid(2, dec!(100), dec!(10)));
+
+        let AmendResult::Replaced(order) = book.amend_order(1, None, Some(dec!(15))) else {
+            panic!("quantity increase should replace the order");
+        };
+        assert_eq!(order.quantity, dec!(15));
+        assert!(book.get_order(1).is_none());
+
+        book.add_order(order);
+        assert_eq!(level_ids(&book, dec!(100)), vec![2, 1]);
+    }
+
+    #[test]
+    fn price_change_takes_the_order_out() {
+        let book = OrderBook::new();
+        book.add_order(bid(1, dec!(100), dec!(10)));
+
+        let AmendResult::Replaced(order) = book.amend_order(1, Some(dec!(101)), None) else {
+            panic!("price change should replace the order");
+        };
+        assert_eq!((order.price, order.quantity), (dec!(101), dec!(10)));
+        assert!(book.order_index.get(&1).is_none());
+        assert_eq!(book.get_best_bid("AAPL"), None);
+    }
+
+    #[test]
+    fn iceberg_cut_comes_out_of_the_reserve() {
+        let book = OrderBook::new();
+        let mut iceberg = bid(1, dec!(100), dec!(10));
+        iceberg.display_quantity = Some(dec!(2));
+        book.add_order(iceberg);
+        book.add_order(bid(2, dec!(100), dec!(10)));
+
+        assert!(matches!(
+            book.amend_order(1, None, Some(dec!(5))),
+            AmendResult::Amended
+        ));
+        let order = book.get_order(1).unwrap();
+        assert_eq!((order.quantity, order.hidden_quantity), (dec!(2), dec!(3)));
+        assert_eq!(level_ids(&book, dec!(100)), vec![1, 2]);
+    }
+
+    #[test]
+    fn pegged_orders_reject_price_amends() {
+        let book = OrderBook::new();
+        let mut peg = bid(1, dec!(100), dec!(10));
+        peg.order_type = OrderType::Pegged {
+            peg: PegType::Primary,
+            offset: Decimal::ZERO,
+        };
+        book.add_order(peg);
+
+        assert!(matches!(
+            book.amend_order(1, Some(dec!(99)), None),
+            AmendResult::Rejected(_)
+        ));
+        assert!(matches!(
+            book.amend_order(1, None, Some(dec!(5))),
+            AmendResult::Amended
+        ));
+    }
+
+    #[test]
+    fn unknown_order_is_not_found() {
+        let book = OrderBook::new();
+        assert!(matches!(
+            book.amend_order(7, None, Some(dec!(5))),
+            AmendResult::NotFound
+        ));
+    }
+
+    #[test]
+    fn snapshot_shows_only_the_displayed_size() {
+        let book = OrderBook::new();
+        let mut iceberg = bid(1, dec!(100), dec!(10));
+        iceberg.display_quantity = Some(dec!(2));
+        book.add_order(iceberg);
+        book.add_order(bid(2, dec!(100), dec!(3)));
+        book.add_order(bid(3, dec!(99), dec!(4)));
+
+        let snapshot = book.get_book_snapshot("AAPL", 5);
+
+        assert_eq!(
+            snapshot.bids,
+            vec![(dec!(100), dec!(5)), (dec!(99), dec!(4))]
+        );
+        assert!(snapshot.asks.is_empty());
+    }
+}
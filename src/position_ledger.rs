@@ -0,0 +1,218 @@
+use rust_decimal::Decimal;
+use std::collections::VecDeque;
+
+/// How a closing trade is matched against open lots.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LotMethod {
+    /// Close the oldest lot first.
+    #[default]
+    Fifo,
+    /// Close the newest lot first.
+    Lifo,
+    /// Keep one lot at the weighted average cost of everything opened.
+    WeightedAverage,
+}
+
+/// Open quantity bought or sold at one price. Short lots carry a negative
+/// quantity.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lot {
+    pub quantity: Decimal,
+    pub price: Decimal,
+}
+
+/// Open lots and realized PnL for one account and symbol.
+#[derive(Debug, Clone, Default)]
+pub struct PositionLedger {
+    lots: VecDeque<Lot>,
+    realized_pnl: Decimal,
+}
+
+#[allow(dead_code)]
+impl PositionLedger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Books a fill of `quantity` (positive for buys, negative for sells) at
+    /// `price`. The part that offsets the open position closes lots in
+    /// `method` order and realizes PnL; anything beyond it opens a new lot on
+    /// the other side. Returns the PnL realized by this fill.
+    pub fn apply(&mut self, quantity: Decimal, price: Decimal, method: LotMethod) -> Decimal {
+        if quantity.is_zero() {
+            return Decimal::ZERO;
+        }
+        if method == LotMethod::WeightedAverage {
+            self.collapse();
+        }
+
+        let mut remaining = quantity;
+        let mut realized = Decimal::ZERO;
+
+        while !remaining.is_zero() {
+            let lot = match method {
+                LotMethod::Lifo => self.lots.back_mut(),
+                LotMethod::Fifo | LotMethod::WeightedAverage => self.lots.front_mut(),
+            };
+            let Some(lot) =
+                lot.filter(|lot| lot.quantity.is_sign_positive() != remaining.is_sign_positive())
+            else {
+                break;
+            };
+
+            // Closing quantity carries the lot's sign
+            let closed = if lot.quantity.abs() <= remaining.abs() {
+                lot.quantity
+            } else {
+                -remaining
+            };
+            realized += (price - lot.price) * closed;
+            lot.quantity -= closed;
+            remaining += closed;
+
+            if lot.quantity.is_zero() {
+                match method {
+                    LotMethod::Lifo => self.lots.pop_back(),
+                    LotMethod::Fifo | LotMethod::WeightedAverage => self.lots.pop_front(),
+                };
+            }
+        }
+
+        if !remaining.is_zero() {
+            self.lots.push_back(Lot {
+                quantity: remaining,
+                price,
+            });
+            if method == LotMethod::WeightedAverage {
+                self.collapse();
+            }
+        }
+
+        self.realized_pnl += realized;
+        realized
+    }
+
+    /// Merges all open lots into one at their weighted average price.
+    fn collapse(&mut self) {
+        if self.lots.len() > 1 {
+            let lot = Lot {
+                quantity: self.position(),
+                price: self.average_price(),
+            };
+            self.lots.clear();
+            if !lot.quantity.is_zero() {
+                self.lots.push_back(lot);
+            }
+        }
+    }
+
+    /// Net signed position.
+    pub fn position(&self) -> Decimal {
+        self.lots.iter().map(|lot| lot.quantity).sum()
+    }
+
+    /// Weighted average price of the open lots, zero when flat.
+    pub fn average_price(&self) -> Decimal {
+        let position = self.position();
+        if position.is_zero() {
+            return Decimal::ZERO;
+        }
+        self.lots
+            .iter()
+            .map(|lot| lot.quantity * lot.price)
+            .sum::<Decimal>()
+            / position
+    }
+
+    pub fn realized_pnl(&self) -> Decimal {
+        self.realized_pnl
+    }
+
+    /// PnL of the open lots marked at `mark_price`.
+    pub fn unrealized_pnl(&self, mark_price: Decimal) -> Decimal {
+        self.lots
+            .iter()
+            .map(|lot| (mark_price - lot.price) * lot.quantity)
+            .sum()
+    }
+
+    /// Open lots, oldest first.
+    pub fn lots(&self) -> Vec<Lot> {
+        self.lots.iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn scale_in_then_sell(method: LotMethod) -> (Decimal, PositionLedger) {
+        let mut ledger = PositionLedger::new();
+        ledger.apply(dec!(10), dec!(100), method);
+        ledger.apply(dec!(10), dec!(110), method);
+        let realized = ledger.apply(dec!(-15), dec!(120), method);
+        (realized, ledger)
+    }
+
+    #[test]
+    fn fifo_closes_the_oldest_lot_first() {
+        let (realized, ledger) = scale_in_then_sell(LotMethod::Fifo);
+        assert_eq!(realized, dec!(250));
+        assert_eq!(
+            ledger.lots(),
+            vec![Lot {
+                quantity: dec!(5),
+                price: dec!(110)
+            }]
+        );
+    }
+
+    #[test]
+    fn lifo_closes_the_newest_lot_first() {
+        let (realized, ledger) = scale_in_then_sell(LotMethod::Lifo);
+        assert_eq!(realized, dec!(200));
+        assert_eq!(
+            ledger.lots(),
+            vec![Lot {
+                quantity: dec!(5),
+                price: dec!(100)
+            }]
+        );
+    }
+
+    #[test]
+    fn weighted_average_closes_at_average_cost() {
+        let (realized, ledger) = scale_in_then_sell(LotMethod::WeightedAverage);
+        assert_eq!(realized, dec!(225));
+        assert_eq!(ledger.position(), dec!(5));
+        assert_eq!(ledger.average_price(), dec!(105));
+    }
+
+    #[test]
+    fn crossing_through_flat_opens_a_lot_on_the_other_side() {
+        let mut ledger = PositionLedger::new();
+        ledger.apply(dec!(5), dec!(100), LotMethod::Fifo);
+
+        assert_eq!(ledger.apply(dec!(-8), dec!(90), LotMethod::Fifo), dec!(-50));
+        assert_eq!(
+            ledger.lots(),
+            vec![Lot {
+                quantity: dec!(-3),
+                price: dec!(90)
+            }]
+        );
+        assert_eq!(ledger.unrealized_pnl(dec!(80)), dec!(30));
+    }
+
+    #[test]
+    fn covering_a_short_realizes_the_price_drop() {
+        let mut ledger = PositionLedger::new();
+        ledger.apply(dec!(-10), dec!(50), LotMethod::Lifo);
+
+        assert_eq!(ledger.apply(dec!(4), dec!(40), LotMethod::Lifo), dec!(40));
+        assert_eq!(ledger.position(), dec!(-6));
+        assert_eq!(ledger.realized_pnl(), dec!(40));
+    }
+}
@@ -0,0 +1,407 @@
+use crate::market_data::MinuteData;
+use crate::matching_engine::Trade;
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use parking_lot::RwLock;
+use rust_decimal::prelude::{FromPrimitive, ToPrimitive};
+use rust_decimal::Decimal;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// Minutes in a regular US equity session; one-day figures scale minute
+/// returns by its square root.
+const SESSION_MINUTES: u32 = 390;
+
+/// Fewer minute returns than this and no estimate is made.
+const MIN_OBSERVATIONS: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VarMethod {
+    /// Empirical quantile of the portfolio's PnL replayed over past returns.
+    Historical,
+    /// Normal quantile from the variance-covariance matrix of returns.
+    Parametric,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VarHorizon {
+    Intraday { minutes: u32 },
+    OneDay,
+}
+
+impl VarHorizon {
+    fn minutes(self) -> u32 {
+        match self {
+            VarHorizon::Intraday { minutes } => minutes,
+            VarHorizon::OneDay => SESSION_MINUTES,
+        }
+    }
+}
+
+/// Loss not exceeded at the confidence level, and the average loss beyond it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VarEstimate {
+    pub var: Decimal,
+    pub expected_shortfall: Decimal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HorizonEstimates {
+    pub intraday: VarEstimate,
+    pub one_day: VarEstimate,
+}
+
+/// Both methods at both horizons. A method is `None` until there is enough
+/// history for every priced symbol it needs.
+#[derive(Debug, Clone, Default)]
+pub struct VarReport {
+    pub confidence: f64,
+    pub intraday_minutes: u32,
+    pub historical: Option<HorizonEstimates>,
+    pub parametric: Option<HorizonEstimates>,
+}
+
+/// Minute closes per symbol, fed from market data bars or from trades, and
+/// the VaR and expected shortfall of positions held against them. Horizons
+/// longer than a minute use the square-root-of-time rule.
+pub struct RiskAnalytics {
+    // Minute (Unix seconds / 60) to closing price
+    closes: DashMap<String, BTreeMap<i64, f64>>,
+    window: usize,
+    confidence: RwLock<f64>,
+    intraday_minutes: RwLock<u32>,
+}
+
+impl RiskAnalytics {
+    /// Keeps the last `window` minutes per symbol.
+    pub fn new(window: usize) -> Self {
+        Self {
+            closes: DashMap::new(),
+            window,
+            confidence: RwLock::new(0.99),
+            intraday_minutes: RwLock::new(60),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn set_confidence(&self, confidence: f64) {
+        *self.confidence.write() = confidence.clamp(0.5, 0.9999);
+    }
+
+    #[allow(dead_code)]
+    pub fn set_intraday_minutes(&self, minutes: u32) {
+        *self.intraday_minutes.write() = minutes.max(1);
+    }
+
+    /// Loads minute bars, such as `EfficientMarketDataBuffer::get_recent_data`.
+    pub fn load_minute_data(&self, symbol: &str, data: &[(DateTime<Utc>, MinuteData)]) {
+        let mut closes = self.closes.entry(symbol.to_string()).or_default();
+        for (timestamp, bar) in data {
+            if let Some(close) = bar.close.to_f64() {
+                closes.insert(timestamp.timestamp().div_euclid(60), close);
+            }
+        }
+        self.trim(&mut closes);
+    }
+
+    /// Takes the last trade in each minute as that minute's close.
+    pub fn record_trade(&self, trade: &Trade) {
+        let Some(price) = trade.price.to_f64() else {
+            return;
+        };
+        let mut closes = self.closes.entry(trade.symbol.clone()).or_default();
+        closes.insert(trade.timestamp.div_euclid(60), price);
+        self.trim(&mut closes);
+    }
+
+    fn trim(&self, closes: &mut BTreeMap<i64, f64>) {
+        while closes.len() > self.window {
+            closes.pop_first();
+        }
+    }
+
+    /// Simple returns between consecutive recorded minutes, keyed by the
+    /// later minute.
+    fn returns(&self, symbol: &str) -> BTreeMap<i64, f64> {
+        let Some(closes) = self.closes.get(symbol) else {
+            return BTreeMap::new();
+        };
+        closes
+            .iter()
+            .zip(closes.iter().skip(1))
+            .filter(|((_, prev), _)| **prev > 0.0)
+            .map(|((_, prev), (minute, close))| (*minute, close / prev - 1.0))
+            .collect()
+    }
+
+    /// Both methods at both horizons for signed notionals per symbol.
+    pub fn report(&self, notionals: &HashMap<String, Decimal>) -> VarReport {
+        let intraday = VarHorizon::Intraday {
+            minutes: *self.intraday_minutes.read(),
+        };
+        let estimates = |method| {
+            Some(HorizonEstimates {
+                intraday: self.estimate(notionals, method, intraday)?,
+                one_day: self.estimate(notionals, method, VarHorizon::OneDay)?,
+            })
+        };
+
+        VarReport {
+            confidence: *self.confidence.read(),
+            intraday_minutes: intraday.minutes(),
+            historical: estimates(VarMethod::Historical),
+            parametric: estimates(VarMethod::Parametric),
+        }
+    }
+
+    pub fn estimate(
+        &self,
+        notionals: &HashMap<String, Decimal>,
+        method: VarMethod,
+        horizon: VarHorizon,
+    ) -> Option<VarEstimate> {
+        let positions: Vec<(f64, BTreeMap<i64, f64>)> = notionals
+            .iter()
+            .filter(|(_, notional)| !notional.is_zero())
+            .map(|(symbol, notional)| Some((notional.to_f64()?, self.returns(symbol))))
+            .collect::<Option<_>>()?;
+        if positions
+            .iter()
+            .any(|(_, returns)| returns.len() < MIN_OBSERVATIONS)
+        {
+            return None;
+        }
+
+        let confidence = *self.confidence.read();
+        let (var, es) = if positions.is_empty() {
+            (0.0, 0.0)
+        } else {
+            match method {
+                VarMethod::Historical => historical_var(&positions, confidence),
+                VarMethod::Parametric => parametric_var(&positions, confidence)?,
+            }
+        };
+
+        let scale = f64::from(horizon.minutes()).sqrt();
+        Some(VarEstimate {
+            var: Decimal::from_f64(var * scale)?.round_dp(2),
+            expected_shortfall: Decimal::from_f64(es * scale)?.round_dp(2),
+        })
+    }
+}
+
+/// One-minute VaR and ES from the portfolio's PnL in each past minute. A
+/// symbol with no return in a minute contributes nothing to it.
+fn historical_var(positions: &[(f64, BTreeMap<i64, f64>)], confidence: f64) -> (f64, f64) {
+    let minutes: BTreeSet<i64> = positions
+        .iter()
+        .flat_map(|(_, returns)| returns.keys().copied())
+        .collect();
+
+    let mut losses: Vec<f64> = minutes
+        .iter()
+        .map(|minute| {
+            -positions
+                .iter()
+                .map(|(notional, returns)| notional * returns.get(minute).copied().unwrap_or(0.0))
+                .sum::<f64>()
+        })
+        .collect();
+    losses.sort_by(f64::total_cmp);
+
+    let cutoff = ((confidence * losses.len() as f64).ceil() as usize).clamp(1, losses.len()) - 1;
+    let tail = &losses[cutoff..];
+    let var = losses[cutoff].max(0.0);
+    let es = (tail.iter().sum::<f64>() / tail.len() as f64).max(var);
+    (var, es)
+}
+
+/// One-minute VaR and ES of a normal PnL with zero mean and the variance
+/// implied by the returns' covariance matrix.
+fn parametric_var(positions: &[(f64, BTreeMap<i64, f64>)], confidence: f64) -> Option<(f64, f64)> {
+    let mut variance = 0.0;
+    for (i, (weight_i, returns_i)) in positions.iter().enumerate() {
+        for (offset, (weight_j, returns_j)) in positions[i..].iter().enumerate() {
+            let cov = covariance(returns_i, returns_j);
+            // Off-diagonal terms appear twice in w'Σw
+            let terms = if offset == 0 { 1.0 } else { 2.0 };
+            variance += terms * weight_i * weight_j * cov;
+        }
+    }
+    let sigma = variance.max(0.0).sqrt();
+
+    let z = inverse_normal_cdf(confidence)?;
+    let density = (-z * z / 2.0).exp() / (2.0 * std::f64::consts::PI).sqrt();
+    Some((z * sigma, sigma * density / (1.0 - confidence)))
+}
+
+/// Sample covariance over the minutes both series have. Too little overlap
+/// counts as uncorrelated.
+fn covariance(a: &BTreeMap<i64, f64>, b: &BTreeMap<i64, f64>) -> f64 {
+    let pairs: Vec<(f64, f64)> = a
+        .iter()
+        .filter_map(|(minute, x)| b.get(minute).map(|y| (*x, *y)))
+        .collect();
+    if pairs.len() < MIN_OBSERVATIONS {
+        return 0.0;
+    }
+
+    let n = pairs.len() as f64;
+    let mean_a = pairs.iter().map(|(x, _)| x).sum::<f64>() / n;
+    let mean_b = pairs.iter().map(|(_, y)| y).sum::<f64>() / n;
+    pairs
+        .iter()
+        .map(|(x, y)| (x - mean_a) * (y - mean_b))
+        .sum::<f64>()
+        / (n - 1.0)
+}
+
+/// Acklam's rational approximation of the standard normal quantile,
+/// accurate to about 1e-9.
+fn inverse_normal_cdf(p: f64) -> Option<f64> {
+    if p <= 0.0 || p >= 1.0 {
+        return None;
+    }
+
+    const A: [f64; 6] = [
+        -3.969_683_028_665_376e1,
+        2.209_460_984_245_205e2,
+        -2.759_285_104_469_687e2,
+        1.383_577_518_672_69e2,
+        -3.066_479_806_614_716e1,
+        2.506_628_277_459_239,
+    ];
+    const B: [f64; 5] = [
+        -5.447_609_879_822_406e1,
+        1.615_858_368_580_409e2,
+        -1.556_989_798_598_866e2,
+        6.680_131_188_771_972e1,
+        -1.328_068_155_288_572e1,
+    ];
+    const C: [f64; 6] = [
+        -7.784_894_002_430_293e-3,
+        -3.223_964_580_411_365e-1,
+        -2.400_758_277_161_838,
+        -2.549_732_539_343_734,
+        4.374_664_141_464_968,
+        2.938_163_982_698_783,
+    ];
+    const D: [f64; 4] = [
+        7.784_695_709_041_462e-3,
+        3.224_671_290_700_398e-1,
+        2.445_134_137_142_996,
+        3.754_408_661_907_416,
+    ];
+    const P_LOW: f64 = 0.02425;
+
+    let tail = |q: f64| {
+        (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5])
+            / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.0)
+    };
+
+    let z = if p < P_LOW {
+        tail((-2.0 * p.ln()).sqrt())
+    } else if p > 1.0 - P_LOW {
+        -tail((-2.0 * (1.0 - p).ln()).sqrt())
+    } else {
+        let q = p - 0.5;
+        let r = q * q;
+        (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5]) * q
+            / (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r + 1.0)
+    };
+    Some(z)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    /// Returns keyed by consecutive minutes from 1.
+    fn series(returns: impl IntoIterator<Item = f64>) -> BTreeMap<i64, f64> {
+        (1..).zip(returns).collect()
+    }
+
+    /// Analytics holding `closes` for AAPL, one per minute.
+    fn with_closes(closes: &[f64]) -> RiskAnalytics {
+        let analytics = RiskAnalytics::new(1_000);
+        analytics.closes.insert(
+            "AAPL".to_string(),
+            (0..).zip(closes.iter().copied()).collect(),
+        );
+        analytics
+    }
+
+    fn alternating(count: usize) -> Vec<f64> {
+        (0..count)
+            .map(|i| if i % 2 == 0 { 100.0 } else { 101.0 })
+            .collect()
+    }
+
+    #[test]
+    fn normal_quantiles_match_tables() {
+        let z = inverse_normal_cdf(0.99).unwrap();
+        assert!((z - 2.3263).abs() < 1e-4, "{z}");
+        assert!((inverse_normal_cdf(0.01).unwrap() + z).abs() < 1e-9);
+        assert!(inverse_normal_cdf(0.5).unwrap().abs() < 1e-12);
+        assert_eq!(inverse_normal_cdf(1.0), None);
+    }
+
+    #[test]
+    fn historical_var_and_es_come_from_the_loss_tail() {
+        // PnL of -49 to 50 in steps of one
+        let returns = series((1..=100).map(|m| f64::from(m - 50) / 1_000.0));
+        let (var, es) = historical_var(&[(1_000.0, returns)], 0.95);
+        assert!((var - 44.0).abs() < 1e-9, "{var}");
+        assert!((es - 46.5).abs() < 1e-9, "{es}");
+    }
+
+    #[test]
+    fn covariance_needs_enough_shared_minutes() {
+        let a = series((0..20).map(|i| if i % 2 == 0 { 0.01 } else { -0.01 }));
+        let b: BTreeMap<i64, f64> = a.iter().map(|(m, r)| (*m, 2.0 * r)).collect();
+        let variance = 20.0 * 0.0001 / 19.0;
+        assert!((covariance(&a, &a) - variance).abs() < 1e-15);
+        assert!((covariance(&a, &b) - 2.0 * variance).abs() < 1e-15);
+
+        let short: BTreeMap<i64, f64> = a.iter().take(19).map(|(m, r)| (*m, *r)).collect();
+        assert_eq!(covariance(&short, &b), 0.0);
+    }
+
+    #[test]
+    fn no_estimate_below_the_minimum_history() {
+        let notionals = HashMap::from([("AAPL".to_string(), dec!(10_000))]);
+        let horizon = VarHorizon::Intraday { minutes: 1 };
+
+        let analytics = with_closes(&alternating(MIN_OBSERVATIONS));
+        assert_eq!(
+            analytics.estimate(&notionals, VarMethod::Historical, horizon),
+            None
+        );
+        let analytics = with_closes(&alternating(MIN_OBSERVATIONS + 1));
+        assert!(analytics
+            .estimate(&notionals, VarMethod::Historical, horizon)
+            .is_some());
+    }
+
+    #[test]
+    fn horizons_scale_with_the_square_root_of_minutes() {
+        let analytics = with_closes(&alternating(41));
+        let notionals = HashMap::from([("AAPL".to_string(), dec!(10_000))]);
+        for method in [VarMethod::Historical, VarMethod::Parametric] {
+            let estimate = |minutes| {
+                analytics
+                    .estimate(&notionals, method, VarHorizon::Intraday { minutes })
+                    .unwrap()
+            };
+            let (one, four) = (estimate(1), estimate(4));
+            assert!(one.var > Decimal::ZERO);
+            assert!(
+                (four.var - one.var * dec!(2)).abs() <= dec!(0.02),
+                "{method:?}"
+            );
+            assert!(
+                (four.expected_shortfall - one.expected_shortfall * dec!(2)).abs() <= dec!(0.02)
+            );
+        }
+    }
+}
@@ -0,0 +1,736 @@
+use crate::margin::{AccountType, MarginRates};
+use crate::matching_engine::MatchingEngine;
+use crate::risk_management::{LossLimits, PriceCollar};
+use crate::throttle::ThrottleLimits;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// Risk limits and accounts, read from TOML, or JSON for a `.json` file.
+///
+/// Everything but `max_order_size` is optional; a limit left out is not
+/// enforced. Unknown keys are rejected so a misspelt limit is not silently
+/// ignored.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RiskConfig {
+    pub max_order_size: Decimal,
+    pub max_order_notional: Option<Decimal>,
+    pub min_market_coverage: Option<Decimal>,
+    /// Loss limits for accounts without their own.
+    #[serde(default)]
+    pub loss_limits: LossLimits,
+    #[serde(default)]
+    pub throttle: ThrottleConfig,
+    #[serde(default)]
+    pub symbols: BTreeMap<String, SymbolConfig>,
+    #[serde(default)]
+    pub accounts: Vec<AccountConfig>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ThrottleConfig {
+    pub max_orders_per_sec: Option<usize>,
+    pub max_cancels_per_sec: Option<usize>,
+    pub max_message_to_trade: Option<usize>,
+    pub ratio_window_secs: Option<u64>,
+}
+
+impl ThrottleConfig {
+    pub fn limits(&self) -> ThrottleLimits {
+        let defaults = ThrottleLimits::default();
+        ThrottleLimits {
+            max_orders_per_sec: self.max_orders_per_sec,
+            max_cancels_per_sec: self.max_cancels_per_sec,
+            max_message_to_trade: self.max_message_to_trade,
+            ratio_window: self
+                .ratio_window_secs
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.ratio_window),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SymbolConfig {
+    pub position_limit: Option<Decimal>,
+    pub notional_limit: Option<Decimal>,
+    pub price_collar: Option<PriceCollar>,
+    pub margin: Option<MarginRates>,
+    /// Reference price for the short sale price test until the first
+    /// session close replaces it with the last trade.
+    pub previous_close: Option<Decimal>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AccountConfig {
+    pub id: u64,
+    pub account_type: AccountType,
+    pub cash: Decimal,
+    pub loss_limits: Option<LossLimits>,
+    /// Message limits in place of the global `throttle` ones.
+    pub throttle: Option<ThrottleConfig>,
+    /// Position limits by symbol, in place of the symbols' own.
+    #[serde(default)]
+    pub position_limits: BTreeMap<String, Decimal>,
+}
+
+#[derive(Debug)]
+pub enum RiskConfigError {
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        message: String,
+    },
+    /// The file parsed but `field` holds a value that makes no sense.
+    Invalid {
+        field: String,
+        reason: &'static str,
+    },
+}
+
+impl std::fmt::Display for RiskConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RiskConfigError::Io { path, source } => {
+                write!(f, "cannot read {}: {}", path.display(), source)
+            }
+            RiskConfigError::Parse { path, message } => {
+                write!(f, "cannot parse {}: {}", path.display(), message)
+            }
+            RiskConfigError::Invalid { field, reason } => write!(f, "{}: {}", field, reason),
+        }
+    }
+}
+
+impl std::error::Error for RiskConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RiskConfigError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+fn invalid(field: impl Into<String>, reason: &'static str) -> RiskConfigError {
+    RiskConfigError::Invalid {
+        field: field.into(),
+        reason,
+    }
+}
+
+fn check_positive(field: impl Into<String>, value: Option<Decimal>) -> Result<(), RiskConfigError> {
+    match value {
+        Some(value) if value <= Decimal::ZERO => Err(invalid(field, "must be positive")),
+        _ => Ok(()),
+    }
+}
+
+fn check_loss_limits(field: &str, limits: &LossLimits) -> Result<(), RiskConfigError> {
+    check_positive(format!("{field}.max_loss"), limits.max_loss)?;
+    check_positive(format!("{field}.max_drawdown"), limits.max_drawdown)
+}
+
+fn check_throttle(field: &str, throttle: &ThrottleConfig) -> Result<(), RiskConfigError> {
+    for (name, value) in [
+        ("max_orders_per_sec", throttle.max_orders_per_sec),
+        ("max_cancels_per_sec", throttle.max_cancels_per_sec),
+        ("max_message_to_trade", throttle.max_message_to_trade),
+    ] {
+        if value == Some(0) {
+            return Err(invalid(format!("{field}.{name}"), "must be positive"));
+        }
+    }
+    if throttle.ratio_window_secs == Some(0) {
+        return Err(invalid(
+            format!("{field}.ratio_window_secs"),
+            "must be positive",
+        ));
+    }
+    Ok(())
+}
+
+/// One limit that was set, changed or lifted by applying a config.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LimitChange {
+    /// `global`, `symbol AAPL`, `account 3`, `account 3 symbol AAPL` and so on.
+    pub scope: String,
+    pub limit: &'static str,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+impl std::fmt::Display for LimitChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let show = |value: &Option<String>| value.clone().unwrap_or_else(|| "unset".to_string());
+        write!(
+            f,
+            "{} {}: {} -> {}",
+            self.scope,
+            self.limit,
+            show(&self.old),
+            show(&self.new)
+        )
+    }
+}
+
+/// Records a change from `old` to `new` and says whether there was one.
+fn diff<T: PartialEq + Debug>(
+    changes: &mut Vec<LimitChange>,
+    scope: &str,
+    limit: &'static str,
+    old: Option<T>,
+    new: Option<T>,
+) -> bool {
+    if old == new {
+        return false;
+    }
+    changes.push(LimitChange {
+        scope: scope.to_string(),
+        limit,
+        old: old.map(|value| format!("{:?}", value)),
+        new: new.map(|value| format!("{:?}", value)),
+    });
+    true
+}
+
+impl RiskConfig {
+    /// Reads and validates a config file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, RiskConfigError> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path).map_err(|source| RiskConfigError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+
+        let is_json = path.extension().is_some_and(|ext| ext == "json");
+        let parsed = if is_json {
+            serde_json::from_str(&contents).map_err(|e| e.to_string())
+        } else {
+            toml::from_str(&contents).map_err(|e| e.to_string())
+        };
+        let config: Self = parsed.map_err(|message| RiskConfigError::Parse {
+            path: path.to_path_buf(),
+            message,
+        })?;
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), RiskConfigError> {
+        check_positive("max_order_size", Some(self.max_order_size))?;
+        check_positive("max_order_notional", self.max_order_notional)?;
+        if self
+            .min_market_coverage
+            .is_some_and(|coverage| coverage < Decimal::ZERO || coverage > Decimal::ONE)
+        {
+            return Err(invalid("min_market_coverage", "must be between 0 and 1"));
+        }
+        check_loss_limits("loss_limits", &self.loss_limits)?;
+
+        check_throttle("throttle", &self.throttle)?;
+
+        for (symbol, limits) in &self.symbols {
+            let field = |name: &str| format!("symbols.{symbol}.{name}");
+            if symbol.trim().is_empty() {
+                return Err(invalid("symbols", "symbol names cannot be empty"));
+            }
+            check_positive(field("position_limit"), limits.position_limit)?;
+            check_positive(field("notional_limit"), limits.notional_limit)?;
+            check_positive(field("previous_close"), limits.previous_close)?;
+            if let Some(collar) = &limits.price_collar {
+                if collar.max_ticks == Some(0) {
+                    return Err(invalid(field("price_collar.max_ticks"), "must be positive"));
+                }
+                check_positive(field("price_collar.max_percent"), collar.max_percent)?;
+            }
+            if let Some(rates) = &limits.margin {
+                let in_range = Decimal::ZERO < rates.maintenance
+                    && rates.maintenance <= rates.initial
+                    && rates.initial <= Decimal::ONE;
+                if !in_range {
+                    return Err(invalid(
+                        field("margin"),
+                        "need 0 < maintenance <= initial <= 1",
+                    ));
+                }
+            }
+        }
+
+        let mut ids = BTreeSet::new();
+        for account in &self.accounts {
+            let field = format!("accounts.{}", account.id);
+            if !ids.insert(account.id) {
+                return Err(invalid(field, "account listed more than once"));
+            }
+            if account.cash < Decimal::ZERO {
+                return Err(invalid(format!("{field}.cash"), "cannot be negative"));
+            }
+            if let Some(limits) = &account.loss_limits {
+                check_loss_limits(&format!("{field}.loss_limits"), limits)?;
+            }
+            if let Some(throttle) = &account.throttle {
+                check_throttle(&format!("{field}.throttle"), throttle)?;
+            }
+            for (symbol, limit) in &account.position_limits {
+                if symbol.trim().is_empty() {
+                    return Err(invalid(
+                        format!("{field}.position_limits"),
+                        "symbol names cannot be empty",
+                    ));
+                }
+                check_positive(format!("{field}.position_limits.{symbol}"), Some(*limit))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Brings the engine's limits from `previous`, or from nothing on the
+    /// first load, to this config, logging and returning every change.
+    ///
+    /// Accounts are opened the first time they appear. The cash and type of
+    /// an open account are trading state rather than limits, so a reload
+    /// leaves them alone, as it does accounts dropped from the file.
+    pub fn apply(
+        &self,
+        previous: Option<&RiskConfig>,
+        engine: &MatchingEngine,
+    ) -> Vec<LimitChange> {
+        let risk = engine.risk_manager();
+        let mut changes = Vec::new();
+
+        if diff(
+            &mut changes,
+            "global",
+            "max_order_size",
+            previous.map(|p| p.max_order_size),
+            Some(self.max_order_size),
+        ) {
+            risk.set_max_order_size(self.max_order_size);
+        }
+        if diff(
+            &mut changes,
+            "global",
+            "max_order_notional",
+            previous.and_then(|p| p.max_order_notional),
+            self.max_order_notional,
+        ) {
+            match self.max_order_notional {
+                Some(limit) => risk.set_max_order_notional(limit),
+                None => risk.clear_max_order_notional(),
+            }
+        }
+        if diff(
+            &mut changes,
+            "global",
+            "min_market_coverage",
+            previous.and_then(|p| p.min_market_coverage),
+            self.min_market_coverage,
+        ) {
+            risk.set_min_market_coverage(self.min_market_coverage.unwrap_or(Decimal::ZERO));
+        }
+        if diff(
+            &mut changes,
+            "global",
+            "loss_limits",
+            Some(previous.map_or_else(LossLimits::default, |p| p.loss_limits)),
+            Some(self.loss_limits),
+        ) {
+            risk.set_default_loss_limits(self.loss_limits);
+        }
+        if diff(
+            &mut changes,
+            "global",
+            "throttle",
+            Some(previous.map_or_else(ThrottleConfig::default, |p| p.throttle)),
+            Some(self.throttle),
+        ) {
+            engine.set_throttle_limits(self.throttle.limits());
+        }
+
+        let no_symbols = BTreeMap::new();
+        let old_symbols = previous.map_or(&no_symbols, |p| &p.symbols);
+        let symbols: BTreeSet<&String> = old_symbols.keys().chain(self.symbols.keys()).collect();
+        for symbol in symbols {
+            let old = old_symbols.get(symbol).copied().unwrap_or_default();
+            let new = self.symbols.get(symbol).copied().unwrap_or_default();
+            let scope = format!("symbol {symbol}");
+
+            if diff(
+                &mut changes,
+                &scope,
+                "position_limit",
+                old.position_limit,
+                new.position_limit,
+            ) {
+                match new.position_limit {
+                    Some(limit) => risk.set_position_limit(symbol, limit),
+                    None => risk.clear_position_limit(symbol),
+                }
+            }
+            if diff(
+                &mut changes,
+                &scope,
+                "notional_limit",
+                old.notional_limit,
+                new.notional_limit,
+            ) {
+                match new.notional_limit {
+                    Some(limit) => risk.set_notional_limit(symbol, limit),
+                    None => risk.clear_notional_limit(symbol),
+                }
+            }
+            if diff(
+                &mut changes,
+                &scope,
+                "price_collar",
+                old.price_collar,
+                new.price_collar,
+            ) {
+                match new.price_collar {
+                    Some(collar) => risk.set_price_collar(symbol, collar),
+                    None => risk.clear_price_collar(symbol),
+                }
+            }
+            if diff(&mut changes, &scope, "margin", old.margin, new.margin) {
+                match new.margin {
+                    Some(rates) => risk.set_margin_rates(symbol, rates),
+                    None => risk.clear_margin_rates(symbol),
+                }
+            }
+            // Dropping it keeps whatever close the engine has, as that may
+            // already come from a session rollover
+            if diff(
+                &mut changes,
+                &scope,
+                "previous_close",
+                old.previous_close,
+                new.previous_close,
+            ) {
+                if let Some(close) = new.previous_close {
+                    risk.short_sales().set_previous_close(symbol, close);
+                }
+            }
+        }
+
+        let old_accounts: BTreeMap<u64, &AccountConfig> = previous
+            .map(|p| p.accounts.iter().map(|a| (a.id, a)).collect())
+            .unwrap_or_default();
+        let new_accounts: BTreeMap<u64, &AccountConfig> =
+            self.accounts.iter().map(|a| (a.id, a)).collect();
+        let ids: BTreeSet<u64> = old_accounts
+            .keys()
+            .chain(new_accounts.keys())
+            .copied()
+            .collect();
+        for id in ids {
+            let old = old_accounts.get(&id);
+            let new = new_accounts.get(&id);
+            let scope = format!("account {id}");
+
+            if let Some(account) = new {
+                if risk.margin_account(id).is_none() {
+                    risk.open_account(id, account.account_type, account.cash);
+                    log::info!(
+                        "Risk config: opened {:?} account {} with {} cash",
+                        account.account_type,
+                        id,
+                        account.cash
+                    );
+                } else if old.is_some_and(|old| {
+                    old.cash != account.cash || old.account_type != account.account_type
+                }) {
+                    log::warn!(
+                        "Risk config: cash and type of open account {} are not reloaded",
+                        id
+                    );
+                }
+            }
+            if diff(
+                &mut changes,
+                &scope,
+                "loss_limits",
+                old.and_then(|a| a.loss_limits),
+                new.and_then(|a| a.loss_limits),
+            ) {
+                match new.and_then(|a| a.loss_limits) {
+                    Some(limits) => risk.set_loss_limits(id, limits),
+                    None => risk.clear_loss_limits(id),
+                }
+            }
+            if diff(
+                &mut changes,
+                &scope,
+                "throttle",
+                old.and_then(|a| a.throttle),
+                new.and_then(|a| a.throttle),
+            ) {
+                match new.and_then(|a| a.throttle) {
+                    Some(throttle) => engine.set_account_throttle_limits(id, throttle.limits()),
+                    None => engine.clear_account_throttle_limits(id),
+                }
+            }
+
+            let no_limits = BTreeMap::new();
+            let old_limits = old.map_or(&no_limits, |a| &a.position_limits);
+            let new_limits = new.map_or(&no_limits, |a| &a.position_limits);
+            let symbols: BTreeSet<&String> = old_limits.keys().chain(new_limits.keys()).collect();
+            for symbol in symbols {
+                let new_limit = new_limits.get(symbol).copied();
+                if diff(
+                    &mut changes,
+                    &format!("{scope} symbol {symbol}"),
+                    "position_limit",
+                    old_limits.get(symbol).copied(),
+                    new_limit,
+                ) {
+                    match new_limit {
+                        Some(limit) => risk.set_account_position_limit(id, symbol, limit),
+                        None => risk.clear_account_position_limit(id, symbol),
+                    }
+                }
+            }
+        }
+
+        for change in &changes {
+            log::info!("Risk limit change: {}", change);
+        }
+        changes
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+}
+
+/// Polls `path` every `interval` and applies it again whenever its
+/// modification time changes. A file that fails to load or validate is
+/// logged and the limits in force stay as they were.
+pub fn watch(
+    path: PathBuf,
+    mut current: RiskConfig,
+    engine: Arc<MatchingEngine>,
+    interval: Duration,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut last_modified = modified(&path);
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+            let modified = modified(&path);
+            if modified == last_modified {
+                continue;
+            }
+            last_modified = modified;
+
+            match RiskConfig::load(&path) {
+                Ok(config) => {
+                    let changes = config.apply(Some(&current), &engine);
+                    log::info!(
+                        "Reloaded risk config {} ({} limit changes)",
+                        path.display(),
+                        changes.len()
+                    );
+                    current = config;
+                }
+                Err(e) => log::error!("Risk config rejected, keeping current limits: {}", e),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::market_data::{exempt_market_data_account, MARKET_DATA_ACCOUNT_ID};
+    use crate::order_book::{Order, OrderBook, OrderSide, OrderType, TimeInForce};
+    use crate::risk_management::{RiskManager, RiskRejection};
+    use crate::throttle::ThrottleRejection;
+    use std::time::Instant;
+
+    fn parse(toml: &str) -> RiskConfig {
+        toml::from_str(toml).expect("config parses")
+    }
+
+    /// Field `validate` rejects in `toml`, if any.
+    fn rejected_field(toml: &str) -> Option<String> {
+        match parse(toml).validate() {
+            Ok(()) => None,
+            Err(RiskConfigError::Invalid { field, .. }) => Some(field),
+            Err(e) => panic!("unexpected error: {e}"),
+        }
+    }
+
+    #[test]
+    fn shipped_config_is_valid() {
+        let config = parse(include_str!("../risk.toml"));
+        assert_eq!(config.validate().map_err(|e| e.to_string()), Ok(()));
+        assert_eq!(config.accounts.len(), 8);
+    }
+
+    #[test]
+    fn out_of_range_limits_name_their_field() {
+        let cases = [
+            ("max_order_size = 0", "max_order_size"),
+            (
+                "max_order_size = 10\nmin_market_coverage = 1.5",
+                "min_market_coverage",
+            ),
+            (
+                "max_order_size = 10\n[loss_limits]\nmax_drawdown = -5",
+                "loss_limits.max_drawdown",
+            ),
+            (
+                "max_order_size = 10\n[throttle]\nmax_cancels_per_sec = 0",
+                "throttle.max_cancels_per_sec",
+            ),
+            (
+                "max_order_size = 10\n[symbols.AAPL]\nprice_collar = { max_ticks = 0 }",
+                "symbols.AAPL.price_collar.max_ticks",
+            ),
+            (
+                "max_order_size = 10\n[symbols.AAPL]\nmargin = { initial = 0.25, maintenance = 0.5 }",
+                "symbols.AAPL.margin",
+            ),
+            (
+                "max_order_size = 10\n[symbols.AAPL]\nprevious_close = 0",
+                "symbols.AAPL.previous_close",
+            ),
+            (
+                "max_order_size = 10\n[[accounts]]\nid = 3\naccount_type = \"cash\"\ncash = -1",
+                "accounts.3.cash",
+            ),
+            (
+                "max_order_size = 10\n[[accounts]]\nid = 3\naccount_type = \"cash\"\ncash = 1\nthrottle = { ratio_window_secs = 0 }",
+                "accounts.3.throttle.ratio_window_secs",
+            ),
+            (
+                "max_order_size = 10\n[[accounts]]\nid = 3\naccount_type = \"cash\"\ncash = 1\nposition_limits = { AAPL = 0 }",
+                "accounts.3.position_limits.AAPL",
+            ),
+        ];
+        for (toml, field) in cases {
+            assert_eq!(rejected_field(toml).as_deref(), Some(field), "{toml}");
+        }
+    }
+
+    #[test]
+    fn accounts_may_only_be_listed_once() {
+        let account = "[[accounts]]\nid = 1\naccount_type = \"margin\"\ncash = 100\n";
+        let toml = format!("max_order_size = 10\n{account}{account}");
+        assert_eq!(rejected_field(&toml).as_deref(), Some("accounts.1"));
+    }
+
+    #[test]
+    fn misspelt_limits_do_not_parse() {
+        let parsed: Result<RiskConfig, _> =
+            toml::from_str("max_order_size = 10\n[symbols.AAPL]\npostion_limit = 5");
+        assert!(parsed.is_err());
+    }
+
+    #[test]
+    fn shipped_config_leaves_the_exempt_market_data_account_unlimited() {
+        let (engine, _, _) = MatchingEngine::new(
+            Arc::new(OrderBook::new()),
+            Arc::new(RiskManager::new(Decimal::from(1_000_000))),
+        );
+        parse(include_str!("../risk.toml")).apply(None, &engine);
+        let feed_bid = Order {
+            id: 1,
+            client_order_id: 1,
+            account_id: MARKET_DATA_ACCOUNT_ID,
+            symbol: "AAPL".to_string(),
+            price: Decimal::from(185),
+            quantity: Decimal::from(20_000),
+            order_type: OrderType::Limit,
+            side: OrderSide::Buy,
+            timestamp: 0,
+            time_in_force: TimeInForce::GoodTillCancel,
+            display_quantity: None,
+            hidden_quantity: Decimal::ZERO,
+        };
+        let book = OrderBook::new();
+
+        assert_eq!(
+            engine.risk_manager().validate_order(&feed_bid, &book),
+            Err(RiskRejection::PositionLimit)
+        );
+        exempt_market_data_account(&engine);
+        assert_eq!(
+            engine.risk_manager().validate_order(&feed_bid, &book),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn reload_reports_changes_and_applies_account_overrides() {
+        let (engine, _, _) = MatchingEngine::new(
+            Arc::new(OrderBook::new()),
+            Arc::new(RiskManager::new(Decimal::from(1_000_000))),
+        );
+        let accounts = |one: &str, two: &str| {
+            format!(
+                "max_order_size = 1_000\n\
+                 [symbols.AAPL]\nposition_limit = 100\n\
+                 [[accounts]]\nid = 1\naccount_type = \"margin\"\ncash = 1_000\n{one}\n\
+                 [[accounts]]\nid = 2\naccount_type = \"margin\"\ncash = 1_000\n{two}\n"
+            )
+        };
+        let previous = parse(&accounts(
+            "position_limits = { AAPL = 20 }",
+            "throttle = { max_orders_per_sec = 1 }",
+        ));
+        let config = parse(&accounts(
+            "position_limits = { AAPL = 50 }\nthrottle = { max_orders_per_sec = 1 }",
+            "",
+        ));
+        previous.apply(None, &engine);
+
+        let changes = config.apply(Some(&previous), &engine);
+
+        let summary: Vec<_> = changes
+            .iter()
+            .map(|c| (c.scope.as_str(), c.limit, c.old.is_some(), c.new.is_some()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("account 1", "throttle", false, true),
+                ("account 1 symbol AAPL", "position_limit", true, true),
+                ("account 2", "throttle", true, false),
+            ]
+        );
+        assert_eq!(
+            (changes[1].old.as_deref(), changes[1].new.as_deref()),
+            (Some("20"), Some("50"))
+        );
+
+        let risk = engine.risk_manager();
+        assert_eq!(risk.position_limit_for(1, "AAPL"), Some(Decimal::from(50)));
+        assert_eq!(risk.position_limit_for(2, "AAPL"), Some(Decimal::from(100)));
+
+        let now = Instant::now();
+        let throttle = engine.throttle();
+        assert_eq!(throttle.check_order(1, now), Ok(()));
+        assert_eq!(
+            throttle.check_order(1, now),
+            Err(ThrottleRejection::OrderRate)
+        );
+        for _ in 0..3 {
+            assert_eq!(throttle.check_order(2, now), Ok(()));
+        }
+    }
+}
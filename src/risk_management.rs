@@ -1,34 +1,103 @@
-use crate::order_book::{Order, OrderSide};
-use dashmap::DashMap;
-use parking_lot::RwLock;
+use crate::margin::{AccountType, MarginAccount, MarginRates, MarginStatus, MarginSummary};
+use crate::matching_engine::Trade;
+use crate::order_book::{Order, OrderBook, OrderSide, OrderType};
+use crate::position_ledger::{LotMethod, PositionLedger};
+use crate::risk_analytics::{RiskAnalytics, VarHorizon, VarMethod, VarReport};
+use crate::short_sale::ShortSaleRules;
+use dashmap::{DashMap, DashSet};
+use parking_lot::{Mutex, RwLock};
 use rust_decimal::Decimal;
+use serde::Deserialize;
 use std::collections::HashMap;
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicI64, Ordering};
+use tokio::sync::broadcast;
 
-// Enhanced AtomicDecimal implementation
-#[derive(Debug)]
+const RISK_EVENT_CHANNEL_CAPACITY: usize = 1024;
+
+/// Minutes of price history kept for VaR, about a week of sessions.
+const VAR_HISTORY_MINUTES: usize = 2_000;
+
+/// Decimal places kept by [`AtomicDecimal`]. Prices and quantities in the
+/// simulator carry far fewer, so positions built from them stay exact.
+pub const ATOMIC_DECIMAL_SCALE: u32 = 8;
+
+/// Why a value cannot be held by an [`AtomicDecimal`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AtomicDecimalError {
+    /// More than `ATOMIC_DECIMAL_SCALE` decimal places.
+    TooPrecise,
+    /// Beyond about ±9.2e10, or a result that would be.
+    OutOfRange,
+}
+
+impl std::fmt::Display for AtomicDecimalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AtomicDecimalError::TooPrecise => {
+                write!(f, "more than {} decimal places", ATOMIC_DECIMAL_SCALE)
+            }
+            AtomicDecimalError::OutOfRange => write!(f, "value out of AtomicDecimal range"),
+        }
+    }
+}
+
+impl std::error::Error for AtomicDecimalError {}
+
+/// Lock-free decimal stored as a count of 10^-8 units in an `AtomicI64`.
+/// Values with more than `ATOMIC_DECIMAL_SCALE` places or outside about
+/// ±9.2e10 are refused rather than rounded or wrapped.
+#[derive(Debug, Default)]
 pub struct AtomicDecimal {
-    value: Mutex<Decimal>,
+    raw: AtomicI64,
 }
 
 impl AtomicDecimal {
-    pub fn new(initial_value: Decimal) -> Self {
-        AtomicDecimal {
-            value: Mutex::new(initial_value),
+    pub fn new(initial_value: Decimal) -> Result<Self, AtomicDecimalError> {
+        Ok(AtomicDecimal {
+            raw: AtomicI64::new(Self::to_raw(initial_value)?),
+        })
+    }
+
+    /// Whether `value` can be stored exactly.
+    pub fn fits(value: Decimal) -> bool {
+        Self::to_raw(value).is_ok()
+    }
+
+    fn to_raw(value: Decimal) -> Result<i64, AtomicDecimalError> {
+        let mut scaled = value.normalize();
+        if scaled.scale() > ATOMIC_DECIMAL_SCALE {
+            return Err(AtomicDecimalError::TooPrecise);
+        }
+        // Rescaling saturates the scale instead of overflowing
+        scaled.rescale(ATOMIC_DECIMAL_SCALE);
+        if scaled.scale() != ATOMIC_DECIMAL_SCALE {
+            return Err(AtomicDecimalError::OutOfRange);
         }
+        i64::try_from(scaled.mantissa()).map_err(|_| AtomicDecimalError::OutOfRange)
+    }
+
+    fn from_raw(raw: i64) -> Decimal {
+        Decimal::new(raw, ATOMIC_DECIMAL_SCALE)
     }
 
     pub fn get(&self) -> Decimal {
-        *self.value.lock().unwrap()
+        Self::from_raw(self.raw.load(Ordering::Acquire))
     }
 
-    pub fn set(&self, new_value: Decimal) {
-        *self.value.lock().unwrap() = new_value;
+    pub fn set(&self, new_value: Decimal) -> Result<(), AtomicDecimalError> {
+        self.raw.store(Self::to_raw(new_value)?, Ordering::Release);
+        Ok(())
     }
 
-    pub fn add(&mut self, delta: Decimal) {
-        let mut value = self.value.lock().unwrap();
-        *value += delta;
+    /// Adds `delta`, leaving the value untouched if the sum is out of range.
+    pub fn add(&self, delta: Decimal) -> Result<(), AtomicDecimalError> {
+        let delta = Self::to_raw(delta)?;
+        self.raw
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |value| {
+                value.checked_add(delta)
+            })
+            .map(|_| ())
+            .map_err(|_| AtomicDecimalError::OutOfRange)
     }
 
     pub fn is_sign_positive(&self) -> bool {
@@ -39,31 +108,39 @@ impl AtomicDecimal {
         self.get().abs()
     }
 
-    pub fn try_increment(&self, delta: Decimal) -> bool {
-        let mut value = self.value.lock().unwrap();
-        if *value + delta >= Decimal::ZERO {
-            *value += delta;
-            true
-        } else {
-            false
-        }
+    /// Adds `delta` unless that would take the value below zero or out of
+    /// range. Returns whether it was added.
+    pub fn try_increment(&self, delta: Decimal) -> Result<bool, AtomicDecimalError> {
+        let delta = Self::to_raw(delta)?;
+        Ok(self
+            .raw
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |value| {
+                value.checked_add(delta).filter(|sum| *sum >= 0)
+            })
+            .is_ok())
     }
 
-    pub fn compare_and_swap(&self, expected: Decimal, new_value: Decimal) -> bool {
-        let mut value = self.value.lock().unwrap();
-        if *value == expected {
-            *value = new_value;
-            true
-        } else {
-            false
-        }
+    pub fn compare_and_swap(
+        &self,
+        expected: Decimal,
+        new_value: Decimal,
+    ) -> Result<bool, AtomicDecimalError> {
+        Ok(self
+            .raw
+            .compare_exchange(
+                Self::to_raw(expected)?,
+                Self::to_raw(new_value)?,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            )
+            .is_ok())
     }
 }
 
 impl Clone for AtomicDecimal {
     fn clone(&self) -> Self {
         AtomicDecimal {
-            value: Mutex::new(self.get()),
+            raw: AtomicI64::new(self.raw.load(Ordering::Acquire)),
         }
     }
 }
@@ -92,12 +169,259 @@ pub struct RiskMetrics {
     utilization: Decimal,
 }
 
+/// Long and short notional of a set of positions.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Exposure {
+    pub long: Decimal,
+    pub short: Decimal,
+}
+
+impl Exposure {
+    fn add(&mut self, notional: Decimal) {
+        if notional.is_sign_positive() {
+            self.long += notional;
+        } else {
+            self.short -= notional;
+        }
+    }
+
+    pub fn gross(&self) -> Decimal {
+        self.long + self.short
+    }
+
+    pub fn net(&self) -> Decimal {
+        self.long - self.short
+    }
+}
+
+/// Caps on an [`Exposure`]; `max_net` bounds the absolute net. An unset cap
+/// is not checked.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ExposureLimits {
+    pub max_gross: Option<Decimal>,
+    pub max_net: Option<Decimal>,
+    pub max_long: Option<Decimal>,
+    pub max_short: Option<Decimal>,
+}
+
+impl ExposureLimits {
+    fn is_empty(&self) -> bool {
+        self.max_gross.is_none()
+            && self.max_net.is_none()
+            && self.max_long.is_none()
+            && self.max_short.is_none()
+    }
+
+    /// Checks a move from `before` to `after`. Only a measure that ends up
+    /// over its cap and larger than it was is rejected, so an account that is
+    /// already over a cap can still trade back under it.
+    fn check(&self, before: &Exposure, after: &Exposure) -> Result<(), RiskRejection> {
+        let breached = |limit: Option<Decimal>, measure: fn(&Exposure) -> Decimal| {
+            let value = measure(after);
+            limit.is_some_and(|max| value > max && value > measure(before))
+        };
+
+        if breached(self.max_gross, Exposure::gross) {
+            return Err(RiskRejection::GrossExposureLimit);
+        }
+        if breached(self.max_net, |exposure| exposure.net().abs()) {
+            return Err(RiskRejection::NetExposureLimit);
+        }
+        if breached(self.max_long, |exposure| exposure.long) {
+            return Err(RiskRejection::LongExposureLimit);
+        }
+        if breached(self.max_short, |exposure| exposure.short) {
+            return Err(RiskRejection::ShortExposureLimit);
+        }
+        Ok(())
+    }
+}
+
+/// Firm-wide risk: per-symbol metrics, exposure in total and per group, and
+/// value at risk.
+#[derive(Debug, Clone, Default)]
+pub struct PortfolioRisk {
+    pub symbols: HashMap<String, RiskMetrics>,
+    pub exposure: Exposure,
+    pub groups: HashMap<String, Exposure>,
+    pub var: VarReport,
+}
+
+/// Largest VaR an account may run, by one method at one horizon. Orders are
+/// let through while there is too little history for an estimate.
+#[derive(Debug, Clone, Copy)]
+pub struct VarLimit {
+    pub max_var: Decimal,
+    pub method: VarMethod,
+    pub horizon: VarHorizon,
+}
+
+/// Positions are held per account and symbol.
+type PositionKey = (u64, String);
+
+/// One account's position in one symbol. The net quantity is mirrored into
+/// an atomic so pre-trade checks read it without locking the lot ledger.
+/// Only reads are lock-free: every fill still goes through the ledger's
+/// mutex in `record_transaction`, which then stores the new net quantity.
+#[derive(Default)]
+struct PositionState {
+    quantity: AtomicDecimal,
+    ledger: Mutex<PositionLedger>,
+}
+
+/// Pre-trade risk check an order failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RiskRejection {
+    MaxOrderSize,
+    PositionLimit,
+    /// Price times quantity above the per-order notional cap.
+    MaxOrderNotional,
+    /// Position value in the symbol would exceed its notional limit.
+    SymbolNotionalLimit,
+    /// Limit price too far from the current mid.
+    PriceCollar,
+    /// Market order with nothing on the opposite side.
+    NoLiquidity,
+    /// Market order larger than the opposite side can reasonably fill.
+    InsufficientLiquidity,
+    /// Account kill switch is tripped.
+    AccountHalted,
+    GrossExposureLimit,
+    NetExposureLimit,
+    LongExposureLimit,
+    ShortExposureLimit,
+    /// Exposure of the symbol's group would exceed one of its limits.
+    GroupExposureLimit,
+    /// Account VaR with the order filled would exceed its limit.
+    VarLimit,
+    /// Initial margin for the order exceeds the account's free equity.
+    InsufficientBuyingPower,
+    /// Cash accounts cannot sell short.
+    CashAccountShort,
+    /// Short sale in a hard-to-borrow symbol with no shares left to locate.
+    NoLocate,
+    /// Short sale at or below the best bid while the Rule 201 price test is on.
+    ShortSalePriceTest,
+}
+
+impl std::fmt::Display for RiskRejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RiskRejection::MaxOrderSize => write!(f, "order exceeds max order size"),
+            RiskRejection::PositionLimit => write!(f, "order would breach position limit"),
+            RiskRejection::MaxOrderNotional => write!(f, "order exceeds max notional"),
+            RiskRejection::SymbolNotionalLimit => {
+                write!(f, "order would breach symbol notional limit")
+            }
+            RiskRejection::PriceCollar => write!(f, "price outside collar"),
+            RiskRejection::NoLiquidity => write!(f, "no liquidity on the opposite side"),
+            RiskRejection::InsufficientLiquidity => {
+                write!(f, "opposite side too thin for market order")
+            }
+            RiskRejection::AccountHalted => write!(f, "account halted by kill switch"),
+            RiskRejection::GrossExposureLimit => write!(f, "would breach gross exposure limit"),
+            RiskRejection::NetExposureLimit => write!(f, "would breach net exposure limit"),
+            RiskRejection::LongExposureLimit => write!(f, "would breach long exposure limit"),
+            RiskRejection::ShortExposureLimit => write!(f, "would breach short exposure limit"),
+            RiskRejection::GroupExposureLimit => write!(f, "would breach group exposure limit"),
+            RiskRejection::VarLimit => write!(f, "would breach VaR limit"),
+            RiskRejection::InsufficientBuyingPower => write!(f, "insufficient buying power"),
+            RiskRejection::CashAccountShort => write!(f, "cash account cannot sell short"),
+            RiskRejection::NoLocate => write!(f, "no locate for short sale"),
+            RiskRejection::ShortSalePriceTest => {
+                write!(f, "short sale restricted to above the best bid")
+            }
+        }
+    }
+}
+
+/// How far a limit price may sit from the mid. Either bound alone is
+/// enough to reject; an unset bound is not checked.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PriceCollar {
+    pub max_ticks: Option<u32>,
+    pub max_percent: Option<Decimal>,
+}
+
+/// Loss thresholds that trip an account's kill switch. An unset bound is
+/// not checked.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LossLimits {
+    /// Largest intraday loss, realized plus unrealized.
+    pub max_loss: Option<Decimal>,
+    /// Largest fall from the account's intraday PnL high.
+    pub max_drawdown: Option<Decimal>,
+}
+
+/// Why an account was halted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HaltReason {
+    MaxLoss { loss: Decimal },
+    MaxDrawdown { drawdown: Decimal },
+    Manual,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RiskEvent {
+    KillSwitchTripped {
+        account_id: u64,
+        reason: HaltReason,
+    },
+    KillSwitchReset {
+        account_id: u64,
+    },
+    /// Trade far enough below the previous close to engage the price test.
+    ShortSaleRestricted {
+        symbol: String,
+    },
+    /// Margin call, forced liquidation flag, or back to healthy.
+    MarginStatusChanged {
+        account_id: u64,
+        status: MarginStatus,
+    },
+}
+
+/// Intraday PnL marks for one account, all measured from the same origin.
+#[derive(Debug, Clone, Copy, Default)]
+struct LossTracker {
+    session_start: Decimal,
+    peak: Decimal,
+    last: Decimal,
+}
+
 pub struct RiskManager {
     max_order_size: RwLock<Decimal>,
+    max_order_notional: RwLock<Option<Decimal>>,
+    // Per-symbol limits, applied to each account's position separately
     position_limits: DashMap<String, Decimal>,
-    current_positions: DashMap<String, AtomicDecimal>,
-    realized_pnl: DashMap<String, AtomicDecimal>,
-    avg_entry_prices: DashMap<String, Decimal>,
+    // Per-account overrides of `position_limits`
+    account_position_limits: DashMap<PositionKey, Decimal>,
+    notional_limits: DashMap<String, Decimal>,
+    price_collars: DashMap<String, PriceCollar>,
+    // Share of a market order the opposite side has to be able to fill
+    min_market_coverage: RwLock<Decimal>,
+    default_loss_limits: RwLock<LossLimits>,
+    loss_limits: DashMap<u64, LossLimits>,
+    loss_trackers: DashMap<u64, LossTracker>,
+    halted_accounts: DashMap<u64, HaltReason>,
+    // Accounts that skip the position, exposure, margin and loss limits
+    exempt_accounts: DashSet<u64>,
+    events: broadcast::Sender<RiskEvent>,
+    positions: DashMap<PositionKey, PositionState>,
+    // Exposure limits apply to each account's portfolio
+    portfolio_limits: RwLock<ExposureLimits>,
+    symbol_groups: DashMap<String, String>,
+    group_limits: DashMap<String, ExposureLimits>,
+    analytics: RiskAnalytics,
+    // Accounts without one here have unlimited capital
+    margin_accounts: DashMap<u64, MarginAccount>,
+    default_margin_rates: RwLock<MarginRates>,
+    margin_rates: DashMap<String, MarginRates>,
+    short_sales: ShortSaleRules,
+    var_limit: RwLock<Option<VarLimit>>,
+    lot_method: RwLock<LotMethod>,
 }
 
 #[allow(dead_code)]
@@ -138,34 +462,137 @@ impl RiskManager {
     pub fn new(max_order_size: Decimal) -> Self {
         RiskManager {
             max_order_size: RwLock::new(max_order_size),
+            max_order_notional: RwLock::new(None),
             position_limits: DashMap::new(),
-            current_positions: DashMap::new(),
-            realized_pnl: DashMap::new(),
-            avg_entry_prices: DashMap::new(),
+            account_position_limits: DashMap::new(),
+            notional_limits: DashMap::new(),
+            price_collars: DashMap::new(),
+            min_market_coverage: RwLock::new(Decimal::ZERO),
+            default_loss_limits: RwLock::new(LossLimits::default()),
+            loss_limits: DashMap::new(),
+            loss_trackers: DashMap::new(),
+            halted_accounts: DashMap::new(),
+            exempt_accounts: DashSet::new(),
+            events: broadcast::channel(RISK_EVENT_CHANNEL_CAPACITY).0,
+            positions: DashMap::new(),
+            portfolio_limits: RwLock::new(ExposureLimits::default()),
+            symbol_groups: DashMap::new(),
+            group_limits: DashMap::new(),
+            analytics: RiskAnalytics::new(VAR_HISTORY_MINUTES),
+            margin_accounts: DashMap::new(),
+            default_margin_rates: RwLock::new(MarginRates::default()),
+            margin_rates: DashMap::new(),
+            short_sales: ShortSaleRules::new(),
+            var_limit: RwLock::new(None),
+            lot_method: RwLock::new(LotMethod::default()),
         }
     }
 
-    pub fn analyze_portfolio_risk(&self) -> HashMap<String, RiskMetrics> {
-        let mut risk_metrics = HashMap::new();
+    /// Firm-wide metrics per symbol: positions and realized PnL summed over
+    /// all accounts, with utilization taken from the account closest to its
+    /// limit. Exposure nets positions across accounts, marked at `get_price`.
+    pub fn analyze_portfolio_risk(
+        &self,
+        get_price: impl Fn(&str) -> Option<Decimal>,
+    ) -> PortfolioRisk {
+        let mut symbols: HashMap<String, RiskMetrics> = HashMap::new();
 
-        for entry in self.current_positions.iter() {
-            let symbol = entry.key().clone();
-            let current_position = entry.value().get();
+        for ((_, symbol), account_metrics) in self.account_metrics(|_| true) {
+            symbols
+                .entry(symbol)
+                .and_modify(|firm| {
+                    firm.current_position += account_metrics.current_position;
+                    firm.realized_pnl += account_metrics.realized_pnl;
+                    firm.utilization = firm.utilization.max(account_metrics.utilization);
+                })
+                .or_insert(account_metrics);
+        }
 
-            let realized_pnl = self
-                .realized_pnl
-                .get(&symbol)
-                .map(|p| p.get())
-                .unwrap_or(Decimal::ZERO);
+        let notionals = symbols
+            .iter()
+            .filter_map(|(symbol, metrics)| {
+                get_price(symbol).map(|price| (symbol.clone(), metrics.current_position * price))
+            })
+            .collect();
+        let (exposure, groups) = self.aggregate_exposure(&notionals);
+        let var = self.analytics.report(&notionals);
+
+        PortfolioRisk {
+            symbols,
+            exposure,
+            groups,
+            var,
+        }
+    }
+
+    /// Exposure of one account, in total and per group.
+    #[allow(dead_code)]
+    pub fn analyze_account_exposure(
+        &self,
+        account_id: u64,
+        get_price: impl Fn(&str) -> Option<Decimal>,
+    ) -> (Exposure, HashMap<String, Exposure>) {
+        self.aggregate_exposure(&self.account_notionals(account_id, get_price))
+    }
+
+    /// Signed notional per symbol for one account. Symbols without a price
+    /// are left out.
+    fn account_notionals(
+        &self,
+        account_id: u64,
+        get_price: impl Fn(&str) -> Option<Decimal>,
+    ) -> HashMap<String, Decimal> {
+        self.positions
+            .iter()
+            .filter(|entry| entry.key().0 == account_id)
+            .filter_map(|entry| {
+                let symbol = &entry.key().1;
+                get_price(symbol).map(|price| (symbol.clone(), entry.quantity.get() * price))
+            })
+            .collect()
+    }
+
+    fn aggregate_exposure(
+        &self,
+        notionals: &HashMap<String, Decimal>,
+    ) -> (Exposure, HashMap<String, Exposure>) {
+        let mut total = Exposure::default();
+        let mut groups: HashMap<String, Exposure> = HashMap::new();
+        for (symbol, notional) in notionals {
+            total.add(*notional);
+            if let Some(group) = self.symbol_groups.get(symbol) {
+                groups.entry(group.clone()).or_default().add(*notional);
+            }
+        }
+        (total, groups)
+    }
+
+    /// Metrics per symbol for a single account.
+    #[allow(dead_code)]
+    pub fn analyze_account_risk(&self, account_id: u64) -> HashMap<String, RiskMetrics> {
+        self.account_metrics(|account| account == account_id)
+            .into_iter()
+            .map(|((_, symbol), metrics)| (symbol, metrics))
+            .collect()
+    }
+
+    fn account_metrics(&self, include: impl Fn(u64) -> bool) -> Vec<(PositionKey, RiskMetrics)> {
+        let mut risk_metrics = Vec::new();
+
+        for entry in self.positions.iter() {
+            let key = entry.key().clone();
+            if !include(key.0) {
+                continue;
+            }
+            let current_position = entry.quantity.get();
+            let realized_pnl = entry.ledger.lock().realized_pnl();
 
             let position_limit = self
-                .position_limits
-                .get(&symbol)
-                .map(|limit| *limit)
+                .position_limit_for(key.0, &key.1)
                 .unwrap_or(Decimal::ZERO);
 
-            risk_metrics.insert(
-                symbol,
+            risk_metrics.push((
+                key,
                 RiskMetrics {
                     current_position,
                     realized_pnl,
@@ -177,21 +604,470 @@ impl RiskManager {
                         Decimal::ZERO
                     },
                 },
-            );
+            ));
         }
 
         risk_metrics
     }
 
+    /// Lot matching used for fills booked from now on.
+    #[allow(dead_code)]
+    pub fn set_lot_method(&self, method: LotMethod) {
+        *self.lot_method.write() = method;
+    }
+
+    /// Open lots and realized PnL of one account in one symbol.
+    #[allow(dead_code)]
+    pub fn lot_ledger(&self, account_id: u64, symbol: &str) -> Option<PositionLedger> {
+        self.positions
+            .get(&(account_id, symbol.to_string()))
+            .map(|state| state.ledger.lock().clone())
+    }
+
     pub fn set_position_limit(&self, symbol: &str, limit: Decimal) {
         self.position_limits.insert(symbol.to_string(), limit);
     }
 
-    pub fn validate_order(&self, order: &Order) -> bool {
+    pub fn clear_position_limit(&self, symbol: &str) {
+        self.position_limits.remove(symbol);
+    }
+
+    /// Position limit for one account in `symbol`, in place of the symbol's.
+    pub fn set_account_position_limit(&self, account_id: u64, symbol: &str, limit: Decimal) {
+        self.account_position_limits
+            .insert((account_id, symbol.to_string()), limit);
+    }
+
+    /// Puts the account back on the symbol's position limit.
+    pub fn clear_account_position_limit(&self, account_id: u64, symbol: &str) {
+        self.account_position_limits
+            .remove(&(account_id, symbol.to_string()));
+    }
+
+    /// Position limit in force for `account_id` in `symbol`.
+    pub fn position_limit_for(&self, account_id: u64, symbol: &str) -> Option<Decimal> {
+        self.account_position_limits
+            .get(&(account_id, symbol.to_string()))
+            .map(|limit| *limit)
+            .or_else(|| self.position_limits.get(symbol).map(|limit| *limit))
+    }
+
+    /// Gives an account starting cash; from then on its orders are checked
+    /// against buying power.
+    pub fn open_account(&self, account_id: u64, account_type: AccountType, cash: Decimal) {
+        self.margin_accounts
+            .insert(account_id, MarginAccount::new(account_type, cash));
+    }
+
+    #[allow(dead_code)]
+    pub fn margin_account(&self, account_id: u64) -> Option<MarginAccount> {
+        self.margin_accounts
+            .get(&account_id)
+            .map(|account| account.clone())
+    }
+
+    /// Rates for symbols without their own.
+    #[allow(dead_code)]
+    pub fn set_default_margin_rates(&self, rates: MarginRates) {
+        *self.default_margin_rates.write() = rates;
+    }
+
+    #[allow(dead_code)]
+    pub fn set_margin_rates(&self, symbol: &str, rates: MarginRates) {
+        self.margin_rates.insert(symbol.to_string(), rates);
+    }
+
+    /// Puts `symbol` back on the default rates.
+    pub fn clear_margin_rates(&self, symbol: &str) {
+        self.margin_rates.remove(symbol);
+    }
+
+    fn symbol_margin_rates(&self, symbol: &str) -> MarginRates {
+        self.margin_rates
+            .get(symbol)
+            .map(|rates| *rates)
+            .unwrap_or_else(|| *self.default_margin_rates.read())
+    }
+
+    /// Equity and margin requirements of an account with a margin account.
+    /// Positions without a mark are valued at their average price.
+    pub fn margin_summary(
+        &self,
+        account_id: u64,
+        get_price: impl Fn(&str) -> Option<Decimal>,
+    ) -> Option<MarginSummary> {
+        // Copied out so no account guard is held while ledgers are locked
+        let account = self.margin_accounts.get(&account_id)?.clone();
+        let mut summary = MarginSummary {
+            equity: account.cash(),
+            initial_requirement: Decimal::ZERO,
+            maintenance_requirement: Decimal::ZERO,
+            reserved: account.reserved(),
+        };
+
+        for entry in self.positions.iter() {
+            let (owner, symbol) = entry.key();
+            if *owner != account_id {
+                continue;
+            }
+            let quantity = entry.quantity.get();
+            let price = get_price(symbol).unwrap_or_else(|| entry.ledger.lock().average_price());
+            let value = quantity * price;
+            let rates = account.rates(self.symbol_margin_rates(symbol));
+
+            summary.equity += value;
+            summary.initial_requirement += value.abs() * rates.initial;
+            summary.maintenance_requirement += value.abs() * rates.maintenance;
+        }
+        Some(summary)
+    }
+
+    /// Largest position value the account could still open in `symbol`.
+    #[allow(dead_code)]
+    pub fn buying_power(
+        &self,
+        account_id: u64,
+        symbol: &str,
+        get_price: impl Fn(&str) -> Option<Decimal>,
+    ) -> Option<Decimal> {
+        let summary = self.margin_summary(account_id, get_price)?;
+        let rates = self
+            .margin_accounts
+            .get(&account_id)?
+            .rates(self.symbol_margin_rates(symbol));
+        Some((summary.excess() / rates.initial).max(Decimal::ZERO))
+    }
+
+    /// Holds initial margin for a working order's unfilled quantity, on the
+    /// part that would open a position given the account's current one. The
+    /// matching engine calls it whenever the order or the position changes.
+    pub fn reserve_margin(&self, order: &Order) {
+        if !self.margin_accounts.contains_key(&order.account_id) {
+            return;
+        }
+        let delta = match order.side {
+            OrderSide::Buy => order.total_quantity(),
+            OrderSide::Sell => -order.total_quantity(),
+        };
+        // Stops have no limit price and are held at the price they trigger at
+        let price = match order.order_type {
+            OrderType::Stop { stop_price } => stop_price,
+            _ => order.price,
+        };
+        let amount = self.opening_quantity(order.account_id, &order.symbol, delta)
+            * price
+            * self.initial_rate(order.account_id, &order.symbol);
+
+        if let Some(mut account) = self.margin_accounts.get_mut(&order.account_id) {
+            account.reserve(order.id, amount);
+        }
+    }
+
+    /// Frees the margin held for an order that is no longer working.
+    pub fn release_margin(&self, account_id: u64, order_id: u64) {
+        if let Some(mut account) = self.margin_accounts.get_mut(&account_id) {
+            account.release(order_id);
+        }
+    }
+
+    fn initial_rate(&self, account_id: u64, symbol: &str) -> Decimal {
+        let symbol_rates = self.symbol_margin_rates(symbol);
+        self.margin_accounts
+            .get(&account_id)
+            .map(|account| account.rates(symbol_rates).initial)
+            .unwrap_or(symbol_rates.initial)
+    }
+
+    /// Part of a signed quantity that opens or adds to a position rather
+    /// than closing the current one.
+    fn opening_quantity(&self, account_id: u64, symbol: &str, delta: Decimal) -> Decimal {
+        let current = self
+            .positions
+            .get(&(account_id, symbol.to_string()))
+            .map(|state| state.quantity.get())
+            .unwrap_or(Decimal::ZERO);
+
+        if current.is_zero() || current.is_sign_positive() == delta.is_sign_positive() {
+            delta.abs()
+        } else {
+            (delta.abs() - current.abs()).max(Decimal::ZERO)
+        }
+    }
+
+    /// Marks those of `accounts` with a margin account to `get_price` and
+    /// flags the ones whose equity has fallen below maintenance. Returns the
+    /// accounts whose status changed.
+    pub fn check_margin_calls(
+        &self,
+        accounts: &[u64],
+        get_price: impl Fn(&str) -> Option<Decimal>,
+    ) -> Vec<(u64, MarginStatus)> {
+        let mut changed = Vec::new();
+        for &account_id in accounts {
+            let Some(summary) = self.margin_summary(account_id, &get_price) else {
+                continue;
+            };
+            let status = MarginStatus::from_equity(summary.equity, summary.maintenance_requirement);
+            let Some(status) = self
+                .margin_accounts
+                .get_mut(&account_id)
+                .and_then(|mut account| account.update_status(status))
+            else {
+                continue;
+            };
+
+            match status {
+                MarginStatus::Healthy => {
+                    log::info!("Account {} back above maintenance", account_id)
+                }
+                _ => log::warn!("Account {} margin status: {:?}", account_id, status),
+            }
+            let _ = self
+                .events
+                .send(RiskEvent::MarginStatusChanged { account_id, status });
+            changed.push((account_id, status));
+        }
+        changed
+    }
+
+    /// Borrow inventory, locates and the short sale price test.
+    pub fn short_sales(&self) -> &ShortSaleRules {
+        &self.short_sales
+    }
+
+    /// Price history behind the VaR figures.
+    pub fn analytics(&self) -> &RiskAnalytics {
+        &self.analytics
+    }
+
+    #[allow(dead_code)]
+    pub fn set_var_limit(&self, limit: VarLimit) {
+        *self.var_limit.write() = Some(limit);
+    }
+
+    /// Caps each account's total exposure across all symbols.
+    #[allow(dead_code)]
+    pub fn set_portfolio_limits(&self, limits: ExposureLimits) {
+        *self.portfolio_limits.write() = limits;
+    }
+
+    /// Puts `symbol` in a sector or other bucket for group exposure limits.
+    #[allow(dead_code)]
+    pub fn set_symbol_group(&self, symbol: &str, group: &str) {
+        self.symbol_groups
+            .insert(symbol.to_string(), group.to_string());
+    }
+
+    /// Caps each account's exposure to the symbols in `group`.
+    #[allow(dead_code)]
+    pub fn set_group_limits(&self, group: &str, limits: ExposureLimits) {
+        self.group_limits.insert(group.to_string(), limits);
+    }
+
+    /// Caps price times quantity on any single order.
+    #[allow(dead_code)]
+    pub fn set_max_order_notional(&self, limit: Decimal) {
+        *self.max_order_notional.write() = Some(limit);
+    }
+
+    pub fn clear_max_order_notional(&self) {
+        *self.max_order_notional.write() = None;
+    }
+
+    /// Caps the quantity of any single order.
+    pub fn set_max_order_size(&self, limit: Decimal) {
+        *self.max_order_size.write() = limit;
+    }
+
+    /// Caps the value of each account's position in `symbol`.
+    #[allow(dead_code)]
+    pub fn set_notional_limit(&self, symbol: &str, limit: Decimal) {
+        self.notional_limits.insert(symbol.to_string(), limit);
+    }
+
+    pub fn clear_notional_limit(&self, symbol: &str) {
+        self.notional_limits.remove(symbol);
+    }
+
+    #[allow(dead_code)]
+    pub fn set_price_collar(&self, symbol: &str, collar: PriceCollar) {
+        self.price_collars.insert(symbol.to_string(), collar);
+    }
+
+    pub fn clear_price_collar(&self, symbol: &str) {
+        self.price_collars.remove(symbol);
+    }
+
+    /// Market orders are rejected unless the opposite side holds at least
+    /// this share of their quantity. Zero only rejects an empty side.
+    #[allow(dead_code)]
+    pub fn set_min_market_coverage(&self, coverage: Decimal) {
+        *self.min_market_coverage.write() = coverage;
+    }
+
+    /// Loss limits for accounts without their own.
+    #[allow(dead_code)]
+    pub fn set_default_loss_limits(&self, limits: LossLimits) {
+        *self.default_loss_limits.write() = limits;
+    }
+
+    #[allow(dead_code)]
+    pub fn set_loss_limits(&self, account_id: u64, limits: LossLimits) {
+        self.loss_limits.insert(account_id, limits);
+    }
+
+    /// Puts the account back on the default loss limits.
+    pub fn clear_loss_limits(&self, account_id: u64) {
+        self.loss_limits.remove(&account_id);
+    }
+
+    /// Kill switch trips and resets, as they happen.
+    #[allow(dead_code)]
+    pub fn subscribe_events(&self) -> broadcast::Receiver<RiskEvent> {
+        self.events.subscribe()
+    }
+
+    /// Exempts an account from every limit on its positions and PnL, as for
+    /// replayed market data. Its orders are still checked for size, price
+    /// collars and liquidity, and its trades still count towards positions.
+    pub fn exempt_account(&self, account_id: u64) {
+        self.exempt_accounts.insert(account_id);
+    }
+
+    pub fn is_exempt(&self, account_id: u64) -> bool {
+        self.exempt_accounts.contains(&account_id)
+    }
+
+    pub fn is_halted(&self, account_id: u64) -> bool {
+        self.halted_accounts.contains_key(&account_id)
+    }
+
+    /// Halts the account. Returns false if it was already halted. Resting
+    /// orders are left alone; `MatchingEngine::halt_account` also pulls them.
+    pub fn trip_kill_switch(&self, account_id: u64, reason: HaltReason) -> bool {
+        if self.halted_accounts.insert(account_id, reason).is_some() {
+            return false;
+        }
+        log::warn!(
+            "Kill switch tripped for account {}: {:?}",
+            account_id,
+            reason
+        );
+        let _ = self
+            .events
+            .send(RiskEvent::KillSwitchTripped { account_id, reason });
+        true
+    }
+
+    /// Lets a halted account trade again. Loss and drawdown are measured
+    /// afresh from the account's last marked PnL, so it does not trip again
+    /// on the loss it was halted for.
+    #[allow(dead_code)]
+    pub fn reset_kill_switch(&self, account_id: u64) -> bool {
+        if self.halted_accounts.remove(&account_id).is_none() {
+            return false;
+        }
+        if let Some(mut tracker) = self.loss_trackers.get_mut(&account_id) {
+            tracker.session_start = tracker.last;
+            tracker.peak = tracker.last;
+        }
+        log::info!("Kill switch reset for account {}", account_id);
+        let _ = self.events.send(RiskEvent::KillSwitchReset { account_id });
+        true
+    }
+
+    /// Starts a new trading day: intraday loss and drawdown restart from
+    /// each account's last marked PnL, and short sale restrictions age.
+    pub fn begin_session(&self) {
+        for mut tracker in self.loss_trackers.iter_mut() {
+            tracker.session_start = tracker.last;
+            tracker.peak = tracker.last;
+        }
+        self.short_sales.begin_session();
+    }
+
+    /// Realized plus unrealized PnL of an account across all its symbols.
+    pub fn account_pnl(
+        &self,
+        account_id: u64,
+        get_price: impl Fn(&str) -> Option<Decimal>,
+    ) -> Decimal {
+        self.positions
+            .iter()
+            .filter(|entry| entry.key().0 == account_id)
+            .map(|entry| {
+                let ledger = entry.ledger.lock();
+                let unrealized = get_price(&entry.key().1)
+                    .map(|mark| ledger.unrealized_pnl(mark))
+                    .unwrap_or(Decimal::ZERO);
+                ledger.realized_pnl() + unrealized
+            })
+            .sum()
+    }
+
+    /// Every account that has traded, in ID order.
+    pub fn position_accounts(&self) -> Vec<u64> {
+        let mut accounts: Vec<u64> = self.positions.iter().map(|entry| entry.key().0).collect();
+        accounts.sort_unstable();
+        accounts.dedup();
+        accounts
+    }
+
+    /// Marks `accounts` to `get_price` and halts those past their loss or
+    /// drawdown limit. Returns the accounts halted by this call.
+    pub fn check_loss_limits(
+        &self,
+        accounts: &[u64],
+        get_price: impl Fn(&str) -> Option<Decimal>,
+    ) -> Vec<u64> {
+        let default_limits = *self.default_loss_limits.read();
+        let mut tripped = Vec::new();
+        for &account_id in accounts {
+            let pnl = self.account_pnl(account_id, &get_price);
+            let tracker = {
+                let mut tracker = self.loss_trackers.entry(account_id).or_default();
+                tracker.last = pnl;
+                tracker.peak = tracker.peak.max(pnl);
+                *tracker
+            };
+            if self.is_halted(account_id) || self.is_exempt(account_id) {
+                continue;
+            }
+
+            let limits = self
+                .loss_limits
+                .get(&account_id)
+                .map(|limits| *limits)
+                .unwrap_or(default_limits);
+            let loss = tracker.session_start - pnl;
+            let drawdown = tracker.peak - pnl;
+
+            let reason = if limits.max_loss.is_some_and(|max| loss >= max) {
+                Some(HaltReason::MaxLoss { loss })
+            } else if limits.max_drawdown.is_some_and(|max| drawdown >= max) {
+                Some(HaltReason::MaxDrawdown { drawdown })
+            } else {
+                None
+            };
+            if let Some(reason) = reason {
+                if self.trip_kill_switch(account_id, reason) {
+                    tripped.push(account_id);
+                }
+            }
+        }
+        tripped
+    }
+
+    /// Pre-trade check run by the matching engine on every new order.
+    pub fn validate_order(&self, order: &Order, book: &OrderBook) -> Result<(), RiskRejection> {
+        if self.is_halted(order.account_id) {
+            return Err(RiskRejection::AccountHalted);
+        }
+
         let max_size = *self.max_order_size.read();
 
         if order.quantity > max_size {
-            return false;
+            return Err(RiskRejection::MaxOrderSize);
         }
 
         let symbol = &order.symbol;
@@ -202,24 +1078,267 @@ impl RiskManager {
         };
 
         let current = self
-            .current_positions
-            .get(symbol)
-            .map(|p| p.get())
+            .positions
+            .get(&(order.account_id, symbol.clone()))
+            .map(|state| state.quantity.get())
             .unwrap_or(Decimal::ZERO);
 
         let new_position = current + delta;
+        let exempt = self.is_exempt(order.account_id);
 
-        if let Some(limit) = self.position_limits.get(symbol) {
-            if new_position.abs() > *limit {
-                return false;
+        if let Some(limit) = self
+            .position_limit_for(order.account_id, symbol)
+            .filter(|_| !exempt)
+        {
+            if new_position.abs() > limit {
+                return Err(RiskRejection::PositionLimit);
             }
         }
 
-        true
+        let mid = book.get_mid_price(symbol);
+
+        if order.order_type == OrderType::Market {
+            self.check_market_liquidity(order, book)?;
+        } else if let Some(mid) = mid {
+            self.check_price_collar(order, mid, book.get_tick_size(symbol))?;
+        }
+
+        // Market and stop orders are valued at the price they are likely to fill at
+        let price = match order.order_type {
+            OrderType::Limit | OrderType::StopLimit { .. } | OrderType::PostOnly { .. } => {
+                Some(order.price)
+            }
+            OrderType::Stop { stop_price } => Some(stop_price),
+            OrderType::Market | OrderType::Pegged { .. } => mid.or_else(|| match order.side {
+                OrderSide::Buy => book.get_best_ask(symbol),
+                OrderSide::Sell => book.get_best_bid(symbol),
+            }),
+        };
+
+        if let Some(price) = price {
+            if let Some(limit) = *self.max_order_notional.read() {
+                if order.quantity * price > limit {
+                    return Err(RiskRejection::MaxOrderNotional);
+                }
+            }
+            if !exempt {
+                if let Some(limit) = self.notional_limits.get(symbol) {
+                    if new_position.abs() * price > *limit {
+                        return Err(RiskRejection::SymbolNotionalLimit);
+                    }
+                }
+                self.check_exposure(order, new_position * price, book)?;
+                self.check_buying_power(order, delta, new_position, price, book)?;
+            }
+        }
+
+        if !exempt && order.side == OrderSide::Sell && new_position.is_sign_negative() {
+            self.check_short_sale(order, new_position, book)?;
+        }
+
+        Ok(())
+    }
+
+    /// Price test and locate for a sell that opens or grows a short.
+    fn check_short_sale(
+        &self,
+        order: &Order,
+        new_position: Decimal,
+        book: &OrderBook,
+    ) -> Result<(), RiskRejection> {
+        if self.short_sales.is_restricted(&order.symbol) {
+            // Orders without a limit price of their own could sell into the bid
+            let price = match order.order_type {
+                OrderType::Limit | OrderType::StopLimit { .. } | OrderType::PostOnly { .. } => {
+                    Some(order.price)
+                }
+                _ => None,
+            };
+            let best_bid = book.get_best_bid(&order.symbol);
+            let allowed = match (price, best_bid) {
+                (Some(price), Some(bid)) => price > bid,
+                (Some(_), None) => true,
+                (None, _) => false,
+            };
+            if !allowed {
+                return Err(RiskRejection::ShortSalePriceTest);
+            }
+        }
+
+        if !self
+            .short_sales
+            .can_locate(order.account_id, &order.symbol, new_position.abs())
+        {
+            return Err(RiskRejection::NoLocate);
+        }
+        Ok(())
+    }
+
+    /// Rejects orders whose initial margin, on the part that opens a
+    /// position, is more than the account has free.
+    fn check_buying_power(
+        &self,
+        order: &Order,
+        delta: Decimal,
+        new_position: Decimal,
+        price: Decimal,
+        book: &OrderBook,
+    ) -> Result<(), RiskRejection> {
+        let Some(account_type) = self
+            .margin_accounts
+            .get(&order.account_id)
+            .map(|account| account.account_type())
+        else {
+            return Ok(());
+        };
+        if account_type == AccountType::Cash && new_position.is_sign_negative() {
+            return Err(RiskRejection::CashAccountShort);
+        }
+
+        let required = self.opening_quantity(order.account_id, &order.symbol, delta)
+            * price
+            * self.initial_rate(order.account_id, &order.symbol);
+        if required.is_zero() {
+            return Ok(());
+        }
+
+        let mark = |symbol: &str| {
+            book.get_mid_price(symbol)
+                .or_else(|| book.get_last_trade_price(symbol))
+        };
+        let free = self
+            .margin_summary(order.account_id, mark)
+            .map(|summary| summary.excess())
+            .unwrap_or(Decimal::ZERO);
+        if required > free {
+            return Err(RiskRejection::InsufficientBuyingPower);
+        }
+        Ok(())
+    }
+
+    /// Checks the account's exposure and VaR as they would stand with the
+    /// order filled, the order's symbol valued at `new_notional`.
+    fn check_exposure(
+        &self,
+        order: &Order,
+        new_notional: Decimal,
+        book: &OrderBook,
+    ) -> Result<(), RiskRejection> {
+        let portfolio_limits = *self.portfolio_limits.read();
+        let var_limit = *self.var_limit.read();
+        if portfolio_limits.is_empty() && self.group_limits.is_empty() && var_limit.is_none() {
+            return Ok(());
+        }
+
+        let mark = |symbol: &str| {
+            book.get_mid_price(symbol)
+                .or_else(|| book.get_last_trade_price(symbol))
+        };
+        let mut notionals = self.account_notionals(order.account_id, mark);
+        let (total_before, groups_before) = self.aggregate_exposure(&notionals);
+        notionals.insert(order.symbol.clone(), new_notional);
+        let (total, groups) = self.aggregate_exposure(&notionals);
+
+        portfolio_limits.check(&total_before, &total)?;
+
+        // Only the order's own group can have moved
+        if let Some(group) = self.symbol_groups.get(&order.symbol) {
+            if let (Some(limits), Some(exposure)) = (
+                self.group_limits.get(group.as_str()),
+                groups.get(group.as_str()),
+            ) {
+                let before = groups_before
+                    .get(group.as_str())
+                    .copied()
+                    .unwrap_or_default();
+                limits
+                    .check(&before, exposure)
+                    .map_err(|_| RiskRejection::GroupExposureLimit)?;
+            }
+        }
+
+        if let Some(limit) = var_limit {
+            let estimate = self
+                .analytics
+                .estimate(&notionals, limit.method, limit.horizon);
+            if estimate.is_some_and(|estimate| estimate.var > limit.max_var) {
+                return Err(RiskRejection::VarLimit);
+            }
+        }
+        Ok(())
+    }
+
+    fn check_market_liquidity(&self, order: &Order, book: &OrderBook) -> Result<(), RiskRejection> {
+        let available =
+            book.available_liquidity(&order.symbol, &order.side, None, order.account_id);
+        if available.is_zero() {
+            return Err(RiskRejection::NoLiquidity);
+        }
+        if available < order.quantity * *self.min_market_coverage.read() {
+            return Err(RiskRejection::InsufficientLiquidity);
+        }
+        Ok(())
+    }
+
+    fn check_price_collar(
+        &self,
+        order: &Order,
+        mid: Decimal,
+        tick_size: Decimal,
+    ) -> Result<(), RiskRejection> {
+        // Pegs are priced off the book, so only explicit limit prices are collared
+        let price = match order.order_type {
+            OrderType::Limit | OrderType::StopLimit { .. } | OrderType::PostOnly { .. } => {
+                order.price
+            }
+            _ => return Ok(()),
+        };
+        let Some(collar) = self.price_collars.get(&order.symbol).map(|c| *c) else {
+            return Ok(());
+        };
+
+        let distance = (price - mid).abs();
+        if let Some(max_ticks) = collar.max_ticks {
+            if distance > tick_size * Decimal::from(max_ticks) {
+                return Err(RiskRejection::PriceCollar);
+            }
+        }
+        if let Some(max_percent) = collar.max_percent {
+            if distance > mid * max_percent / Decimal::ONE_HUNDRED {
+                return Err(RiskRejection::PriceCollar);
+            }
+        }
+        Ok(())
+    }
+
+    /// Books a trade against both the buying and the selling account.
+    pub fn record_trade(&self, trade: &Trade) {
+        self.analytics.record_trade(trade);
+        if self.short_sales.on_trade(&trade.symbol, trade.price) {
+            log::warn!("Short sale price test engaged on {}", trade.symbol);
+            let _ = self.events.send(RiskEvent::ShortSaleRestricted {
+                symbol: trade.symbol.clone(),
+            });
+        }
+        self.record_transaction(
+            trade.buyer_account,
+            &trade.symbol,
+            trade.price,
+            trade.quantity,
+            OrderSide::Buy,
+        );
+        self.record_transaction(
+            trade.seller_account,
+            &trade.symbol,
+            trade.price,
+            trade.quantity,
+            OrderSide::Sell,
+        );
     }
 
     pub fn record_transaction(
         &self,
+        account_id: u64,
         symbol: &str,
         price: Decimal,
         quantity: Decimal,
@@ -235,65 +1354,454 @@ impl RiskManager {
             -quantity
         };
 
-        // Get or insert current position
-        let mut position_entry = self
-            .current_positions
-            .entry(symbol.to_string())
-            .or_insert_with(|| AtomicDecimal::new(Decimal::ZERO));
-
-        // Add to the position
-        position_entry.add(signed_quantity);
-
-        // Update or insert average entry price
-        self.avg_entry_prices
-            .entry(symbol.to_string())
-            .and_modify(|avg_price| {
-                let current_position = position_entry.get();
-                if current_position != Decimal::ZERO {
-                    *avg_price = (((*avg_price) * current_position.abs())
-                        + (price * quantity.abs()))
-                        / current_position.abs();
-                }
-            })
-            .or_insert(price);
+        if let Some(mut account) = self.margin_accounts.get_mut(&account_id) {
+            account.apply_fill(signed_quantity, price);
+        }
 
-        // Ensure realized PnL entry exists
-        self.realized_pnl
-            .entry(symbol.to_string())
-            .or_insert_with(|| AtomicDecimal::new(Decimal::ZERO));
+        let key = (account_id, symbol.to_string());
+        let method = *self.lot_method.read();
+
+        // Only the first fill in a symbol takes the shard write lock
+        let state = match self.positions.get(&key) {
+            Some(state) => state,
+            None => self.positions.entry(key).or_default().downgrade(),
+        };
+        let mut ledger = state.ledger.lock();
+        let realized = ledger.apply(signed_quantity, price, method);
+        let position = ledger.position();
+        if let Err(e) = state.quantity.set(position) {
+            // Order quantities are checked on arrival, so only a position
+            // beyond ±9.2e10 gets here
+            log::error!(
+                "Position of account {} in {} not mirrored: {}",
+                account_id,
+                symbol,
+                e
+            );
+        }
+
+        if !realized.is_zero() {
+            log::debug!(
+                "Realized {} on {} for account {}",
+                realized,
+                symbol,
+                account_id
+            );
+        }
+        drop(ledger);
+
+        // Sales are only checked against the inventory, so working orders
+        // filling together can outrun it
+        let unlocated = self.short_sales.settle(account_id, symbol, position);
+        if unlocated > Decimal::ZERO {
+            log::warn!(
+                "Account {} is short {} {} without a locate",
+                account_id,
+                unlocated,
+                symbol
+            );
+        }
     }
 
     pub fn report_positions(&self, get_price: impl Fn(&str) -> Option<Decimal>) {
-        for entry in self.current_positions.iter() {
-            let symbol = entry.key();
-            let position = entry.value().get();
-
-            let realized = self
-                .realized_pnl
-                .get(symbol)
-                .map(|v| v.get())
-                .unwrap_or(Decimal::ZERO);
-
-            let avg_price = self
-                .avg_entry_prices
-                .get(symbol)
-                .map(|r| *r)
-                .unwrap_or(Decimal::ZERO);
+        for entry in self.positions.iter() {
+            let (account_id, symbol) = entry.key();
+            let position = entry.quantity.get();
+            let ledger = entry.ledger.lock();
 
+            let realized = ledger.realized_pnl();
+            let avg_price = ledger.average_price();
             let unrealized = get_price(symbol)
-                .map(|mp| {
-                    if position > Decimal::ZERO {
-                        (mp - avg_price) * position
-                    } else {
-                        (avg_price - mp) * position.abs()
-                    }
-                })
+                .map(|mp| ledger.unrealized_pnl(mp))
                 .unwrap_or(Decimal::ZERO);
 
             log::info!(
-                "Position Report | {} | Size: {:.2} | Avg: {:.2} | Realized: {:.2} | Unrealized: {:.2}",
-                symbol, position, avg_price, realized, unrealized
+                "Position Report | Account {} | {} | Size: {:.2} | Avg: {:.2} | Realized: {:.2} | Unrealized: {:.2}",
+                account_id, symbol, position, avg_price, realized, unrealized
             );
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::market_data::MinuteData;
+    use crate::order_book::TimeInForce;
+    use chrono::Utc;
+    use rust_decimal_macros::dec;
+
+    fn order(side: OrderSide, price: Decimal, quantity: Decimal) -> Order {
+        Order {
+            id: 1,
+            client_order_id: 1,
+            account_id: 1,
+            symbol: "AAPL".to_string(),
+            price,
+            quantity,
+            order_type: OrderType::Limit,
+            side,
+            timestamp: 0,
+            time_in_force: TimeInForce::GoodTillCancel,
+            display_quantity: None,
+            hidden_quantity: Decimal::ZERO,
+        }
+    }
+
+    fn market(side: OrderSide, quantity: Decimal) -> Order {
+        Order {
+            order_type: OrderType::Market,
+            ..order(side, Decimal::ZERO, quantity)
+        }
+    }
+
+    /// Book quoted 99 / 101 by account 2, ten shares a side.
+    fn quoted_book() -> OrderBook {
+        let book = OrderBook::new();
+        for (id, side, price) in [
+            (100, OrderSide::Buy, dec!(99)),
+            (101, OrderSide::Sell, dec!(101)),
+        ] {
+            book.add_order(Order {
+                id,
+                account_id: 2,
+                ..order(side, price, dec!(10))
+            });
+        }
+        book
+    }
+
+    #[test]
+    fn halted_accounts_are_rejected() {
+        let risk = RiskManager::new(dec!(1_000));
+        risk.trip_kill_switch(1, HaltReason::Manual);
+        assert_eq!(
+            risk.validate_order(&order(OrderSide::Buy, dec!(100), dec!(1)), &quoted_book()),
+            Err(RiskRejection::AccountHalted)
+        );
+    }
+
+    #[test]
+    fn order_size_and_notional_caps() {
+        let risk = RiskManager::new(dec!(100));
+        let book = quoted_book();
+        assert_eq!(
+            risk.validate_order(&order(OrderSide::Buy, dec!(100), dec!(101)), &book),
+            Err(RiskRejection::MaxOrderSize)
+        );
+
+        risk.set_max_order_notional(dec!(5_000));
+        assert_eq!(
+            risk.validate_order(&order(OrderSide::Buy, dec!(100), dec!(51)), &book),
+            Err(RiskRejection::MaxOrderNotional)
+        );
+        assert!(risk
+            .validate_order(&order(OrderSide::Buy, dec!(100), dec!(50)), &book)
+            .is_ok());
+    }
+
+    #[test]
+    fn position_limit_counts_the_current_position() {
+        let risk = RiskManager::new(dec!(1_000));
+        risk.set_position_limit("AAPL", dec!(10));
+        risk.record_transaction(1, "AAPL", dec!(100), dec!(8), OrderSide::Buy);
+        let book = quoted_book();
+
+        assert_eq!(
+            risk.validate_order(&order(OrderSide::Buy, dec!(100), dec!(3)), &book),
+            Err(RiskRejection::PositionLimit)
+        );
+        assert!(risk
+            .validate_order(&order(OrderSide::Sell, dec!(100), dec!(18)), &book)
+            .is_ok());
+    }
+
+    #[test]
+    fn price_collar_limits_distance_from_mid() {
+        let risk = RiskManager::new(dec!(1_000));
+        risk.set_price_collar(
+            "AAPL",
+            PriceCollar {
+                max_ticks: Some(100),
+                max_percent: None,
+            },
+        );
+        let book = quoted_book();
+
+        assert!(risk
+            .validate_order(&order(OrderSide::Buy, dec!(101), dec!(1)), &book)
+            .is_ok());
+        assert_eq!(
+            risk.validate_order(&order(OrderSide::Buy, dec!(101.01), dec!(1)), &book),
+            Err(RiskRejection::PriceCollar)
+        );
+    }
+
+    #[test]
+    fn market_orders_need_opposite_liquidity() {
+        let risk = RiskManager::new(dec!(1_000));
+        assert_eq!(
+            risk.validate_order(&market(OrderSide::Buy, dec!(5)), &OrderBook::new()),
+            Err(RiskRejection::NoLiquidity)
+        );
+
+        risk.set_min_market_coverage(dec!(0.5));
+        let book = quoted_book();
+        assert_eq!(
+            risk.validate_order(&market(OrderSide::Buy, dec!(21)), &book),
+            Err(RiskRejection::InsufficientLiquidity)
+        );
+        assert!(risk
+            .validate_order(&market(OrderSide::Buy, dec!(20)), &book)
+            .is_ok());
+    }
+
+    #[test]
+    fn buying_power_and_cash_account_shorts() {
+        let risk = RiskManager::new(dec!(1_000));
+        let book = quoted_book();
+        risk.open_account(1, AccountType::Margin, dec!(1_000));
+
+        // Reg-T: 1,000 of cash opens 2,000 of stock
+        assert!(risk
+            .validate_order(&order(OrderSide::Buy, dec!(100), dec!(20)), &book)
+            .is_ok());
+        assert_eq!(
+            risk.validate_order(&order(OrderSide::Buy, dec!(100), dec!(21)), &book),
+            Err(RiskRejection::InsufficientBuyingPower)
+        );
+
+        risk.open_account(1, AccountType::Cash, dec!(1_000));
+        assert_eq!(
+            risk.validate_order(&order(OrderSide::Sell, dec!(100), dec!(1)), &book),
+            Err(RiskRejection::CashAccountShort)
+        );
+    }
+
+    #[test]
+    fn stop_orders_reserve_margin_at_their_stop_price() {
+        let risk = RiskManager::new(dec!(1_000));
+        risk.open_account(1, AccountType::Margin, dec!(1_000));
+        let stop = Order {
+            order_type: OrderType::Stop {
+                stop_price: dec!(100),
+            },
+            ..order(OrderSide::Buy, Decimal::ZERO, dec!(10))
+        };
+
+        risk.reserve_margin(&stop);
+
+        assert_eq!(risk.margin_account(1).unwrap().reserved(), dec!(500));
+    }
+
+    #[test]
+    fn atomic_decimal_refuses_values_it_cannot_hold_exactly() {
+        assert_eq!(
+            AtomicDecimal::new(dec!(0.123456789)).err(),
+            Some(AtomicDecimalError::TooPrecise)
+        );
+        assert_eq!(
+            AtomicDecimal::new(dec!(100_000_000_000)).err(),
+            Some(AtomicDecimalError::OutOfRange)
+        );
+
+        let value = AtomicDecimal::new(dec!(1.5)).unwrap();
+        assert_eq!(
+            value.set(dec!(2.000000001)),
+            Err(AtomicDecimalError::TooPrecise)
+        );
+        assert_eq!(value.get(), dec!(1.5));
+        assert_eq!(value.add(dec!(0.12345678)), Ok(()));
+        assert_eq!(value.get(), dec!(1.62345678));
+    }
+
+    #[test]
+    fn atomic_decimal_add_does_not_wrap() {
+        let value = AtomicDecimal::new(dec!(90_000_000_000)).unwrap();
+        assert_eq!(
+            value.add(dec!(5_000_000_000)),
+            Err(AtomicDecimalError::OutOfRange)
+        );
+        assert_eq!(value.get(), dec!(90_000_000_000));
+
+        assert_eq!(value.try_increment(dec!(-90_000_000_001)), Ok(false));
+        assert_eq!(value.try_increment(dec!(-90_000_000_000)), Ok(true));
+        assert!(value.get().is_zero());
+    }
+
+    #[test]
+    fn accounts_over_an_exposure_limit_can_still_reduce() {
+        let risk = RiskManager::new(dec!(1_000));
+        let book = quoted_book();
+        risk.record_transaction(1, "AAPL", dec!(100), dec!(20), OrderSide::Buy);
+        risk.set_portfolio_limits(ExposureLimits {
+            max_gross: Some(dec!(1_000)),
+            ..ExposureLimits::default()
+        });
+
+        assert_eq!(
+            risk.validate_order(&order(OrderSide::Buy, dec!(100), dec!(1)), &book),
+            Err(RiskRejection::GrossExposureLimit)
+        );
+        assert!(risk
+            .validate_order(&order(OrderSide::Sell, dec!(100), dec!(5)), &book)
+            .is_ok());
+        assert!(risk
+            .validate_order(&order(OrderSide::Sell, dec!(100), dec!(30)), &book)
+            .is_ok());
+        assert_eq!(
+            risk.validate_order(&order(OrderSide::Sell, dec!(100), dec!(45)), &book),
+            Err(RiskRejection::GrossExposureLimit)
+        );
+    }
+
+    #[test]
+    fn group_limits_only_block_orders_that_add_to_the_breach() {
+        let risk = RiskManager::new(dec!(1_000));
+        let book = quoted_book();
+        risk.record_transaction(1, "AAPL", dec!(100), dec!(20), OrderSide::Buy);
+        risk.set_symbol_group("AAPL", "tech");
+        risk.set_group_limits(
+            "tech",
+            ExposureLimits {
+                max_long: Some(dec!(1_000)),
+                ..ExposureLimits::default()
+            },
+        );
+
+        assert_eq!(
+            risk.validate_order(&order(OrderSide::Buy, dec!(100), dec!(1)), &book),
+            Err(RiskRejection::GroupExposureLimit)
+        );
+        assert!(risk
+            .validate_order(&order(OrderSide::Sell, dec!(100), dec!(10)), &book)
+            .is_ok());
+    }
+
+    #[test]
+    fn locates_are_taken_on_the_fill_and_returned_on_cover() {
+        let risk = RiskManager::new(dec!(1_000));
+        let book = quoted_book();
+        risk.short_sales().set_borrow_inventory("AAPL", dec!(10));
+
+        assert_eq!(
+            risk.validate_order(&order(OrderSide::Sell, dec!(100), dec!(11)), &book),
+            Err(RiskRejection::NoLocate)
+        );
+        assert_eq!(
+            risk.validate_order(&order(OrderSide::Sell, dec!(100), dec!(10)), &book),
+            Ok(())
+        );
+        assert_eq!(risk.short_sales().borrow_available("AAPL"), Some(dec!(10)));
+
+        risk.record_transaction(1, "AAPL", dec!(100), dec!(6), OrderSide::Sell);
+        assert_eq!(risk.short_sales().located(1, "AAPL"), dec!(6));
+        assert_eq!(risk.short_sales().borrow_available("AAPL"), Some(dec!(4)));
+
+        risk.record_transaction(1, "AAPL", dec!(100), dec!(4), OrderSide::Buy);
+        assert_eq!(risk.short_sales().located(1, "AAPL"), dec!(2));
+        assert_eq!(risk.short_sales().borrow_available("AAPL"), Some(dec!(8)));
+    }
+
+    #[test]
+    fn restricted_symbols_only_take_short_sales_above_the_bid() {
+        let risk = RiskManager::new(dec!(1_000));
+        let book = quoted_book();
+        risk.short_sales().set_previous_close("AAPL", dec!(100));
+        risk.record_trade(&Trade {
+            id: 1,
+            sequence: 1,
+            symbol: "AAPL".to_string(),
+            price: dec!(90),
+            quantity: dec!(1),
+            buyer_id: 10,
+            seller_id: 11,
+            buyer_account: 3,
+            seller_account: 4,
+            timestamp: 0,
+        });
+        assert!(risk.short_sales().is_restricted("AAPL"));
+
+        for price in [dec!(99), dec!(98)] {
+            assert_eq!(
+                risk.validate_order(&order(OrderSide::Sell, price, dec!(10)), &book),
+                Err(RiskRejection::ShortSalePriceTest)
+            );
+        }
+        assert_eq!(
+            risk.validate_order(&order(OrderSide::Sell, dec!(99.01), dec!(10)), &book),
+            Ok(())
+        );
+
+        // Restricted for the rest of the day and all of the next
+        risk.begin_session();
+        assert_eq!(
+            risk.validate_order(&order(OrderSide::Sell, dec!(99), dec!(10)), &book),
+            Err(RiskRejection::ShortSalePriceTest)
+        );
+        risk.begin_session();
+        assert!(!risk.short_sales().is_restricted("AAPL"));
+        assert_eq!(
+            risk.validate_order(&order(OrderSide::Sell, dec!(99), dec!(10)), &book),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn exempt_accounts_skip_position_and_loss_limits() {
+        let risk = RiskManager::new(dec!(1_000));
+        let book = quoted_book();
+        risk.set_position_limit("AAPL", dec!(5));
+        risk.set_default_loss_limits(LossLimits {
+            max_loss: Some(dec!(1)),
+            max_drawdown: None,
+        });
+        risk.exempt_account(1);
+
+        let buy = order(OrderSide::Buy, dec!(100), dec!(50));
+        assert_eq!(risk.validate_order(&buy, &book), Ok(()));
+        assert_eq!(
+            risk.validate_order(&order(OrderSide::Buy, dec!(100), dec!(2_000)), &book),
+            Err(RiskRejection::MaxOrderSize)
+        );
+
+        risk.record_transaction(1, "AAPL", dec!(100), dec!(50), OrderSide::Buy);
+        assert!(risk.check_loss_limits(&[1], |_| Some(dec!(90))).is_empty());
+        assert!(!risk.is_halted(1));
+    }
+
+    #[test]
+    fn orders_that_would_breach_the_var_limit_are_rejected() {
+        let risk = RiskManager::new(dec!(1_000));
+        let book = quoted_book();
+        let start = Utc::now() - chrono::Duration::minutes(40);
+        let bars: Vec<_> = (0..31)
+            .map(|i| {
+                let close = if i % 2 == 0 { dec!(100) } else { dec!(101) };
+                let bar = MinuteData {
+                    open: close,
+                    high: close,
+                    low: close,
+                    close,
+                    volume: dec!(1_000),
+                };
+                (start + chrono::Duration::minutes(i), bar)
+            })
+            .collect();
+        risk.analytics().load_minute_data("AAPL", &bars);
+        risk.set_var_limit(VarLimit {
+            max_var: dec!(50),
+            method: VarMethod::Historical,
+            horizon: VarHorizon::Intraday { minutes: 1 },
+        });
+
+        assert_eq!(
+            risk.validate_order(&order(OrderSide::Buy, dec!(100), dec!(100)), &book),
+            Err(RiskRejection::VarLimit)
+        );
+        assert_eq!(
+            risk.validate_order(&order(OrderSide::Buy, dec!(100), dec!(10)), &book),
+            Ok(())
+        );
+    }
+}
@@ -0,0 +1,133 @@
+use dashmap::DashMap;
+use parking_lot::RwLock;
+use rust_decimal::Decimal;
+
+/// Rule 201 restrictions last for the rest of the day they trigger and all
+/// of the next one.
+const RESTRICTED_SESSIONS: u32 = 2;
+
+/// Borrow inventory, locates and the Rule 201 price test.
+///
+/// Symbols with no borrow inventory set are easy to borrow and need no
+/// locate. For the rest, a short sale is only accepted while the inventory
+/// can cover it, and shares are located when the sale fills. Covering a
+/// short returns its locate to the inventory.
+pub struct ShortSaleRules {
+    borrow_inventory: DashMap<String, Decimal>,
+    locates: DashMap<(u64, String), Decimal>,
+    previous_closes: DashMap<String, Decimal>,
+    // Symbol to sessions left under the price test, this one included
+    restricted: DashMap<String, u32>,
+    trigger_percent: RwLock<Decimal>,
+}
+
+impl Default for ShortSaleRules {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ShortSaleRules {
+    pub fn new() -> Self {
+        Self {
+            borrow_inventory: DashMap::new(),
+            locates: DashMap::new(),
+            previous_closes: DashMap::new(),
+            restricted: DashMap::new(),
+            trigger_percent: RwLock::new(Decimal::TEN),
+        }
+    }
+
+    /// Shares of `symbol` left to locate. Setting it makes the symbol hard
+    /// to borrow.
+    #[allow(dead_code)]
+    pub fn set_borrow_inventory(&self, symbol: &str, quantity: Decimal) {
+        self.borrow_inventory
+            .insert(symbol.to_string(), quantity.max(Decimal::ZERO));
+    }
+
+    #[allow(dead_code)]
+    pub fn borrow_available(&self, symbol: &str) -> Option<Decimal> {
+        self.borrow_inventory.get(symbol).map(|quantity| *quantity)
+    }
+
+    pub fn located(&self, account_id: u64, symbol: &str) -> Decimal {
+        self.locates
+            .get(&(account_id, symbol.to_string()))
+            .map(|quantity| *quantity)
+            .unwrap_or(Decimal::ZERO)
+    }
+
+    /// Whether `account_id` could be short `short_quantity` of `symbol`
+    /// with what it has located plus what is left in the inventory.
+    pub fn can_locate(&self, account_id: u64, symbol: &str, short_quantity: Decimal) -> bool {
+        let Some(inventory) = self.borrow_inventory.get(symbol).map(|quantity| *quantity) else {
+            return true;
+        };
+        short_quantity <= self.located(account_id, symbol) + inventory
+    }
+
+    /// Brings the shares located for `account_id` in line with its
+    /// `position` after a fill. A short that grew borrows the shortfall, as
+    /// much as the inventory still holds, and one that shrank returns the
+    /// excess. Returns the shortfall the inventory could not cover.
+    pub fn settle(&self, account_id: u64, symbol: &str, position: Decimal) -> Decimal {
+        let Some(mut inventory) = self.borrow_inventory.get_mut(symbol) else {
+            return Decimal::ZERO;
+        };
+        let mut located = self
+            .locates
+            .entry((account_id, symbol.to_string()))
+            .or_default();
+
+        let short_quantity = (-position).max(Decimal::ZERO);
+        let borrowed = (short_quantity - *located).min(*inventory);
+        *inventory -= borrowed;
+        *located += borrowed;
+        short_quantity - *located
+    }
+
+    /// Reference price for the circuit breaker, normally the prior close.
+    pub fn set_previous_close(&self, symbol: &str, price: Decimal) {
+        self.previous_closes.insert(symbol.to_string(), price);
+    }
+
+    /// Drop from the previous close, in percent, that triggers the price
+    /// test. Rule 201 uses 10.
+    #[allow(dead_code)]
+    pub fn set_trigger_percent(&self, percent: Decimal) {
+        *self.trigger_percent.write() = percent;
+    }
+
+    pub fn is_restricted(&self, symbol: &str) -> bool {
+        self.restricted.contains_key(symbol)
+    }
+
+    /// Applies the circuit breaker to a trade. Returns true if this trade
+    /// put the symbol under the price test.
+    pub fn on_trade(&self, symbol: &str, price: Decimal) -> bool {
+        if self.is_restricted(symbol) {
+            return false;
+        }
+        let Some(previous_close) = self.previous_closes.get(symbol).map(|close| *close) else {
+            return false;
+        };
+
+        let trigger = previous_close * (Decimal::ONE_HUNDRED - *self.trigger_percent.read())
+            / Decimal::ONE_HUNDRED;
+        if price > trigger {
+            return false;
+        }
+        self.restricted
+            .insert(symbol.to_string(), RESTRICTED_SESSIONS);
+        true
+    }
+
+    /// Rolls over to a new day, ageing restrictions by a session.
+    pub fn begin_session(&self) {
+        self.restricted.retain(|_, sessions| {
+            *sessions -= 1;
+            *sessions > 0
+        });
+    }
+}
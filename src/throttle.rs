@@ -0,0 +1,252 @@
+use dashmap::{DashMap, DashSet};
+use parking_lot::RwLock;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Per-account message limits. An unset limit is not enforced.
+#[derive(Debug, Clone, Copy)]
+pub struct ThrottleLimits {
+    pub max_orders_per_sec: Option<usize>,
+    pub max_cancels_per_sec: Option<usize>,
+    /// Messages allowed per trade over `ratio_window`. An account with no
+    /// trades in the window may still send this many.
+    pub max_message_to_trade: Option<usize>,
+    pub ratio_window: Duration,
+}
+
+impl Default for ThrottleLimits {
+    fn default() -> Self {
+        Self {
+            max_orders_per_sec: None,
+            max_cancels_per_sec: None,
+            max_message_to_trade: None,
+            ratio_window: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Which limit a throttled message ran into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ThrottleRejection {
+    OrderRate,
+    CancelRate,
+    MessageToTradeRatio,
+}
+
+impl std::fmt::Display for ThrottleRejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ThrottleRejection::OrderRate => write!(f, "order rate limit exceeded"),
+            ThrottleRejection::CancelRate => write!(f, "cancel rate limit exceeded"),
+            ThrottleRejection::MessageToTradeRatio => {
+                write!(f, "message-to-trade ratio exceeded")
+            }
+        }
+    }
+}
+
+/// Arrival times of one account's accepted messages and trades.
+#[derive(Default)]
+struct AccountActivity {
+    orders: VecDeque<Instant>,
+    cancels: VecDeque<Instant>,
+    messages: VecDeque<Instant>,
+    trades: VecDeque<Instant>,
+}
+
+impl AccountActivity {
+    fn expire(&mut self, now: Instant, ratio_window: Duration) {
+        let drop_before = |times: &mut VecDeque<Instant>, window: Duration| {
+            while times
+                .front()
+                .is_some_and(|t| now.duration_since(*t) >= window)
+            {
+                times.pop_front();
+            }
+        };
+        drop_before(&mut self.orders, RATE_WINDOW);
+        drop_before(&mut self.cancels, RATE_WINDOW);
+        drop_before(&mut self.messages, ratio_window);
+        drop_before(&mut self.trades, ratio_window);
+    }
+
+    fn ratio_exceeded(&self, limits: &ThrottleLimits) -> bool {
+        limits
+            .max_message_to_trade
+            .is_some_and(|ratio| self.messages.len() >= ratio * self.trades.len().max(1))
+    }
+}
+
+/// Sliding-window order and cancel rate limits, plus a rolling
+/// message-to-trade ratio, kept per account. Only accepted messages count.
+pub struct Throttle {
+    limits: RwLock<ThrottleLimits>,
+    // Accounts with limits of their own in place of `limits`
+    account_limits: DashMap<u64, ThrottleLimits>,
+    activity: DashMap<u64, AccountActivity>,
+    exempt: DashSet<u64>,
+}
+
+impl Default for Throttle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Throttle {
+    pub fn new() -> Self {
+        Self {
+            limits: RwLock::new(ThrottleLimits::default()),
+            account_limits: DashMap::new(),
+            activity: DashMap::new(),
+            exempt: DashSet::new(),
+        }
+    }
+
+    pub fn set_limits(&self, limits: ThrottleLimits) {
+        *self.limits.write() = limits;
+    }
+
+    /// Limits for one account in place of the shared ones.
+    pub fn set_account_limits(&self, account_id: u64, limits: ThrottleLimits) {
+        self.account_limits.insert(account_id, limits);
+    }
+
+    /// Puts the account back on the shared limits.
+    pub fn clear_account_limits(&self, account_id: u64) {
+        self.account_limits.remove(&account_id);
+    }
+
+    fn limits_for(&self, account_id: u64) -> ThrottleLimits {
+        self.account_limits
+            .get(&account_id)
+            .map(|limits| *limits)
+            .unwrap_or_else(|| *self.limits.read())
+    }
+
+    /// Admits every message from `account_id` without counting it.
+    pub fn exempt(&self, account_id: u64) {
+        self.exempt.insert(account_id);
+    }
+
+    /// Admits a new order from `account_id`, or says which limit it hit.
+    pub fn check_order(&self, account_id: u64, now: Instant) -> Result<(), ThrottleRejection> {
+        if self.exempt.contains(&account_id) {
+            return Ok(());
+        }
+        let limits = self.limits_for(account_id);
+        let mut activity = self.activity.entry(account_id).or_default();
+        activity.expire(now, limits.ratio_window);
+
+        if limits
+            .max_orders_per_sec
+            .is_some_and(|max| activity.orders.len() >= max)
+        {
+            return Err(ThrottleRejection::OrderRate);
+        }
+        if activity.ratio_exceeded(&limits) {
+            return Err(ThrottleRejection::MessageToTradeRatio);
+        }
+
+        activity.orders.push_back(now);
+        activity.messages.push_back(now);
+        Ok(())
+    }
+
+    /// Admits a cancel from `account_id`, or says which limit it hit.
+    pub fn check_cancel(&self, account_id: u64, now: Instant) -> Result<(), ThrottleRejection> {
+        if self.exempt.contains(&account_id) {
+            return Ok(());
+        }
+        let limits = self.limits_for(account_id);
+        let mut activity = self.activity.entry(account_id).or_default();
+        activity.expire(now, limits.ratio_window);
+
+        if limits
+            .max_cancels_per_sec
+            .is_some_and(|max| activity.cancels.len() >= max)
+        {
+            return Err(ThrottleRejection::CancelRate);
+        }
+        if activity.ratio_exceeded(&limits) {
+            return Err(ThrottleRejection::MessageToTradeRatio);
+        }
+
+        activity.cancels.push_back(now);
+        activity.messages.push_back(now);
+        Ok(())
+    }
+
+    pub fn record_trade(&self, account_id: u64, now: Instant) {
+        let ratio_window = self.limits_for(account_id).ratio_window;
+        let mut activity = self.activity.entry(account_id).or_default();
+        activity.expire(now, ratio_window);
+        activity.trades.push_back(now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trades_older_than_the_ratio_window_are_dropped() {
+        let throttle = Throttle::new();
+        let start = Instant::now();
+        for i in 0..5 {
+            throttle.record_trade(1, start + Duration::from_secs(i));
+        }
+        throttle.record_trade(1, start + Duration::from_secs(62));
+
+        let activity = throttle.activity.get(&1).unwrap();
+        assert_eq!(activity.trades.len(), 3);
+    }
+
+    #[test]
+    fn orders_and_cancels_have_separate_rate_limits() {
+        let throttle = Throttle::new();
+        throttle.set_limits(ThrottleLimits {
+            max_orders_per_sec: Some(1),
+            max_cancels_per_sec: Some(1),
+            ..ThrottleLimits::default()
+        });
+        let now = Instant::now();
+
+        assert_eq!(throttle.check_order(1, now), Ok(()));
+        assert_eq!(
+            throttle.check_order(1, now),
+            Err(ThrottleRejection::OrderRate)
+        );
+        assert_eq!(throttle.check_cancel(1, now), Ok(()));
+        assert_eq!(
+            throttle.check_cancel(1, now),
+            Err(ThrottleRejection::CancelRate)
+        );
+        assert_eq!(
+            throttle.check_order(1, now + Duration::from_secs(1)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn exempt_accounts_are_not_throttled() {
+        let throttle = Throttle::new();
+        throttle.set_limits(ThrottleLimits {
+            max_orders_per_sec: Some(1),
+            ..ThrottleLimits::default()
+        });
+        throttle.exempt(1);
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            assert_eq!(throttle.check_order(1, now), Ok(()));
+        }
+        assert_eq!(throttle.check_order(2, now), Ok(()));
+        assert_eq!(
+            throttle.check_order(2, now),
+            Err(ThrottleRejection::OrderRate)
+        );
+    }
+}
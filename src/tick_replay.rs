@@ -0,0 +1,323 @@
+use crate::market_data::{self, MARKET_DATA_ACCOUNT_ID};
+use crate::matching_engine::{EngineMessage, MatchingEngine, Sequencer};
+use crate::order_book::{Order, OrderBook, OrderSide, OrderType, TimeInForce};
+use crate::ticks::{QuoteTick, Tick, TickError, TradeTick};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+/// Account that sends replayed trade prints into the engine. Quotes rest
+/// under `MARKET_DATA_ACCOUNT_ID`, so prints can trade against them.
+pub const TAPE_ACCOUNT_ID: u64 = u64::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplaySpeed {
+    /// No pauses between ticks.
+    AsFastAsPossible,
+    /// Tick time runs at this multiple of wall-clock time. Zero or less
+    /// replays as fast as possible.
+    Multiple(f64),
+}
+
+/// Where replayed ticks go.
+pub enum ReplayTarget {
+    /// As engine messages, through risk checks and matching like live flow.
+    /// Each quote replaces the previous one's resting orders; each trade is
+    /// an immediate-or-cancel order from `TAPE_ACCOUNT_ID`, buying at or
+    /// above the last mid and selling below it. Unless the engine has been
+    /// passed to `exempt_replay_accounts`, the two replay accounts are held
+    /// to the same position, loss and throttle limits as any other.
+    Engine(mpsc::UnboundedSender<EngineMessage>),
+    /// Straight into the book with no matching. Quotes replace the resting
+    /// quote orders and trades set the last trade price.
+    Book {
+        book: Arc<OrderBook>,
+        sequencer: Arc<Sequencer>,
+    },
+}
+
+/// Resting orders standing in for one symbol's current quote.
+#[derive(Debug, Default)]
+struct LiveQuote {
+    // Client order IDs for the engine, order IDs for the book
+    bid: Option<u64>,
+    ask: Option<u64>,
+    mid: Option<Decimal>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ReplayStats {
+    pub trades: u64,
+    pub quotes: u64,
+    pub elapsed: Duration,
+}
+
+/// Replays trade and quote ticks in source order, paced against their
+/// timestamps.
+pub struct TickReplayer {
+    target: ReplayTarget,
+    speed: ReplaySpeed,
+    quotes: HashMap<String, LiveQuote>,
+    next_client_order_id: u64,
+}
+
+impl TickReplayer {
+    pub fn new(target: ReplayTarget, speed: ReplaySpeed) -> Self {
+        Self {
+            target,
+            speed,
+            quotes: HashMap::new(),
+            next_client_order_id: 1,
+        }
+    }
+
+    /// Replays every tick, stopping at the first read error or once the
+    /// engine is gone. Ticks stamped earlier than one already replayed are
+    /// sent straight away rather than reordered.
+    pub async fn replay(
+        &mut self,
+        ticks: impl IntoIterator<Item = Result<Tick, TickError>>,
+    ) -> Result<ReplayStats, TickError> {
+        let started = Instant::now();
+        let mut origin: Option<i64> = None;
+        let mut stats = ReplayStats::default();
+
+        for tick in ticks {
+            let tick = tick?;
+            let multiple = match self.speed {
+                ReplaySpeed::Multiple(multiple) if multiple > 0.0 => Some(multiple),
+                _ => None,
+            };
+            if let Some(multiple) = multiple {
+                let origin = *origin.get_or_insert(tick.timestamp_ns());
+                let offset_ns = (tick.timestamp_ns() - origin).max(0) as f64 / multiple;
+                let due = started + Duration::from_nanos(offset_ns as u64);
+                tokio::time::sleep_until(due.into()).await;
+            }
+
+            match &tick {
+                Tick::Trade(_) => stats.trades += 1,
+                Tick::Quote(_) => stats.quotes += 1,
+            }
+            if !self.apply(&tick) {
+                log::warn!("Replay stopped: matching engine has shut down");
+                break;
+            }
+        }
+
+        stats.elapsed = started.elapsed();
+        Ok(stats)
+    }
+
+    /// Sends one tick to the target now. Returns false if the engine has
+    /// shut down.
+    pub fn apply(&mut self, tick: &Tick) -> bool {
+        match tick {
+            Tick::Quote(quote) => self.apply_quote(quote),
+            Tick::Trade(trade) => self.apply_trade(trade),
+        }
+    }
+
+    fn apply_quote(&mut self, quote: &QuoteTick) -> bool {
+        let previous = self.quotes.remove(&quote.symbol).unwrap_or_default();
+        let mut live = LiveQuote {
+            mid: (quote.bid_size > Decimal::ZERO && quote.ask_size > Decimal::ZERO)
+                .then(|| (quote.bid + quote.ask) / Decimal::TWO)
+                .or(previous.mid),
+            ..LiveQuote::default()
+        };
+
+        let mut orders = Vec::new();
+        for (side, price, size) in [
+            (OrderSide::Buy, quote.bid, quote.bid_size),
+            (OrderSide::Sell, quote.ask, quote.ask_size),
+        ] {
+            if size > Decimal::ZERO {
+                orders.push(Order {
+                    client_order_id: self.next_client_order_id(),
+                    ..replay_order(&quote.symbol, side, price, size, quote.timestamp_ns)
+                });
+            }
+        }
+
+        let sent = match &self.target {
+            ReplayTarget::Engine(engine_tx) => {
+                let cancels =
+                    [previous.bid, previous.ask]
+                        .into_iter()
+                        .flatten()
+                        .map(|client_order_id| EngineMessage::CancelClientOrder {
+                            account_id: MARKET_DATA_ACCOUNT_ID,
+                            client_order_id,
+                        });
+                let mut sent = true;
+                for message in cancels.chain(orders.iter().cloned().map(EngineMessage::NewOrder)) {
+                    sent &= engine_tx.send(message).is_ok();
+                }
+                for order in &orders {
+                    match order.side {
+                        OrderSide::Buy => live.bid = Some(order.client_order_id),
+                        OrderSide::Sell => live.ask = Some(order.client_order_id),
+                    }
+                }
+                sent
+            }
+            ReplayTarget::Book { book, sequencer } => {
+                for order_id in [previous.bid, previous.ask].into_iter().flatten() {
+                    book.take_order(order_id);
+                }
+                for mut order in orders {
+                    order.id = sequencer.next_order_id();
+                    match order.side {
+                        OrderSide::Buy => live.bid = Some(order.id),
+                        OrderSide::Sell => live.ask = Some(order.id),
+                    }
+                    book.add_order(order);
+                }
+                true
+            }
+        };
+
+        self.quotes.insert(quote.symbol.clone(), live);
+        sent
+    }
+
+    fn apply_trade(&mut self, trade: &TradeTick) -> bool {
+        let client_order_id = self.next_client_order_id();
+        match &self.target {
+            ReplayTarget::Engine(engine_tx) => {
+                let mid = self.quotes.get(&trade.symbol).and_then(|quote| quote.mid);
+                let side = match mid {
+                    Some(mid) if trade.price < mid => OrderSide::Sell,
+                    _ => OrderSide::Buy,
+                };
+                let order = Order {
+                    client_order_id,
+                    account_id: TAPE_ACCOUNT_ID,
+                    time_in_force: TimeInForce::ImmediateOrCancel,
+                    ..replay_order(
+                        &trade.symbol,
+                        side,
+                        trade.price,
+                        trade.size,
+                        trade.timestamp_ns,
+                    )
+                };
+                engine_tx.send(EngineMessage::NewOrder(order)).is_ok()
+            }
+            ReplayTarget::Book { book, .. } => {
+                book.record_trade_price(&trade.symbol, trade.price);
+                true
+            }
+        }
+    }
+
+    fn next_client_order_id(&mut self) -> u64 {
+        let id = self.next_client_order_id;
+        self.next_client_order_id += 1;
+        id
+    }
+}
+
+/// Lets the replay accounts build whatever positions the tape implies and
+/// send messages at its rate. Their orders are still checked for size,
+/// price collars and liquidity.
+pub fn exempt_replay_accounts(engine: &MatchingEngine) {
+    market_data::exempt_market_data_account(engine);
+    engine.exempt_account(TAPE_ACCOUNT_ID);
+}
+
+/// Resting limit order for the replayed quote; trades override the rest.
+fn replay_order(
+    symbol: &str,
+    side: OrderSide,
+    price: Decimal,
+    quantity: Decimal,
+    timestamp_ns: i64,
+) -> Order {
+    Order {
+        id: 0,
+        client_order_id: 0,
+        account_id: MARKET_DATA_ACCOUNT_ID,
+        symbol: symbol.to_string(),
+        price,
+        quantity,
+        order_type: OrderType::Limit,
+        side,
+        timestamp: timestamp_ns,
+        time_in_force: TimeInForce::GoodTillCancel,
+        display_quantity: None,
+        hidden_quantity: Decimal::ZERO,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn quote(bid: Decimal, ask: Decimal) -> Tick {
+        Tick::Quote(QuoteTick {
+            timestamp_ns: 1,
+            symbol: "AAPL".to_string(),
+            bid,
+            bid_size: dec!(100),
+            ask,
+            ask_size: dec!(100),
+        })
+    }
+
+    fn trade(price: Decimal) -> Tick {
+        Tick::Trade(TradeTick {
+            timestamp_ns: 2,
+            symbol: "AAPL".to_string(),
+            price,
+            size: dec!(10),
+        })
+    }
+
+    #[test]
+    fn new_quote_replaces_the_previous_quote_in_the_book() {
+        let book = Arc::new(OrderBook::new());
+        let mut replayer = TickReplayer::new(
+            ReplayTarget::Book {
+                book: book.clone(),
+                sequencer: Arc::new(Sequencer::new()),
+            },
+            ReplaySpeed::AsFastAsPossible,
+        );
+
+        replayer.apply(&quote(dec!(99), dec!(101)));
+        replayer.apply(&quote(dec!(100), dec!(102)));
+
+        assert_eq!(book.get_order_book_depth("AAPL"), (1, 1));
+        assert_eq!(book.get_best_bid("AAPL"), Some(dec!(100)));
+        assert_eq!(book.get_best_ask("AAPL"), Some(dec!(102)));
+    }
+
+    #[test]
+    fn trade_side_is_inferred_from_the_last_mid() {
+        let (engine_tx, mut engine_rx) = mpsc::unbounded_channel();
+        let mut replayer = TickReplayer::new(
+            ReplayTarget::Engine(engine_tx),
+            ReplaySpeed::AsFastAsPossible,
+        );
+
+        replayer.apply(&quote(dec!(99), dec!(101)));
+        for price in [dec!(100), dec!(101), dec!(99.5)] {
+            replayer.apply(&trade(price));
+        }
+
+        let sides: Vec<_> = std::iter::from_fn(|| engine_rx.try_recv().ok())
+            .filter_map(|message| match message {
+                EngineMessage::NewOrder(order) if order.account_id == TAPE_ACCOUNT_ID => {
+                    Some(order.side)
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(sides, vec![OrderSide::Buy, OrderSide::Buy, OrderSide::Sell]);
+    }
+}
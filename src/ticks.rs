@@ -0,0 +1,523 @@
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Prices and sizes in binary tick files are integers in units of 1e-8.
+const FIXED_POINT_SCALE: u32 = 8;
+
+/// First bytes of a binary tick file.
+const BINARY_MAGIC: &[u8; 8] = b"HFTTICK1";
+
+const SYMBOL_TAG: u8 = b'S';
+const TRADE_TAG: u8 = b'T';
+const QUOTE_TAG: u8 = b'Q';
+
+/// A print on the tape.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TradeTick {
+    /// Nanoseconds since the Unix epoch.
+    pub timestamp_ns: i64,
+    pub symbol: String,
+    pub price: Decimal,
+    pub size: Decimal,
+}
+
+/// National best bid and offer. A side with zero size is empty.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuoteTick {
+    /// Nanoseconds since the Unix epoch.
+    pub timestamp_ns: i64,
+    pub symbol: String,
+    pub bid: Decimal,
+    pub bid_size: Decimal,
+    pub ask: Decimal,
+    pub ask_size: Decimal,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Tick {
+    Trade(TradeTick),
+    Quote(QuoteTick),
+}
+
+impl Tick {
+    pub fn timestamp_ns(&self) -> i64 {
+        match self {
+            Tick::Trade(trade) => trade.timestamp_ns,
+            Tick::Quote(quote) => quote.timestamp_ns,
+        }
+    }
+
+    pub fn symbol(&self) -> &str {
+        match self {
+            Tick::Trade(trade) => &trade.symbol,
+            Tick::Quote(quote) => &quote.symbol,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TickError {
+    Io(std::io::Error),
+    Csv(csv::Error),
+    /// A record that does not follow the format. `position` is the line for
+    /// CSV and the byte offset for binary files.
+    Malformed {
+        position: u64,
+        message: String,
+    },
+    /// A value a binary file cannot hold exactly.
+    Unrepresentable(Decimal),
+}
+
+impl std::fmt::Display for TickError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TickError::Io(e) => write!(f, "IO error: {}", e),
+            TickError::Csv(e) => write!(f, "CSV error: {}", e),
+            TickError::Malformed { position, message } => {
+                write!(f, "malformed tick at {}: {}", position, message)
+            }
+            TickError::Unrepresentable(value) => {
+                write!(f, "{} does not fit the binary tick format", value)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TickError {}
+
+impl From<std::io::Error> for TickError {
+    fn from(e: std::io::Error) -> Self {
+        TickError::Io(e)
+    }
+}
+
+impl From<csv::Error> for TickError {
+    fn from(e: csv::Error) -> Self {
+        TickError::Csv(e)
+    }
+}
+
+/// Reads ticks from a headerless CSV file, in file order. Lines starting
+/// with `#` are comments. Each line is one of
+///
+/// ```text
+/// timestamp_ns,T,symbol,price,size
+/// timestamp_ns,Q,symbol,bid,bid_size,ask,ask_size
+/// ```
+pub fn read_csv_ticks(reader: impl Read) -> impl Iterator<Item = Result<Tick, TickError>> {
+    csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(reader)
+        .into_records()
+        // Comments are skipped here rather than by the reader, which leaves
+        // them out of its line count and would misplace later errors
+        .filter(|record| {
+            !record
+                .as_ref()
+                .is_ok_and(|record| record.get(0).is_some_and(|f| f.starts_with('#')))
+        })
+        .map(|record| parse_csv_tick(&record?))
+}
+
+pub fn load_csv_ticks(
+    path: impl AsRef<Path>,
+) -> Result<impl Iterator<Item = Result<Tick, TickError>>, TickError> {
+    Ok(read_csv_ticks(BufReader::new(std::fs::File::open(path)?)))
+}
+
+fn parse_csv_tick(record: &csv::StringRecord) -> Result<Tick, TickError> {
+    let position = record.position().map_or(0, |position| position.line());
+    let malformed = |message: String| TickError::Malformed { position, message };
+    let field = |i: usize| {
+        record
+            .get(i)
+            .ok_or_else(|| malformed(format!("missing field {}", i + 1)))
+    };
+    let decimal = |i: usize| {
+        let text = field(i)?;
+        Decimal::from_str(text).map_err(|_| malformed(format!("invalid number `{}`", text)))
+    };
+
+    let timestamp_ns = field(0)?.parse().map_err(|_| {
+        malformed(format!(
+            "invalid timestamp `{}`",
+            record.get(0).unwrap_or("")
+        ))
+    })?;
+    let symbol = field(2)?.to_string();
+    match field(1)? {
+        "T" => Ok(Tick::Trade(TradeTick {
+            timestamp_ns,
+            symbol,
+            price: decimal(3)?,
+            size: decimal(4)?,
+        })),
+        "Q" => Ok(Tick::Quote(QuoteTick {
+            timestamp_ns,
+            symbol,
+            bid: decimal(3)?,
+            bid_size: decimal(4)?,
+            ask: decimal(5)?,
+            ask_size: decimal(6)?,
+        })),
+        kind => Err(malformed(format!("unknown tick type `{}`", kind))),
+    }
+}
+
+/// Writes the binary tick format: a magic header, then tagged
+/// little-endian records. Symbols are sent once as `S` records and then
+/// referred to by a 16-bit ID, so a trade takes 27 bytes and a quote 43.
+///
+/// ```text
+/// S id:u16 len:u8 name:[u8; len]
+/// T timestamp_ns:i64 symbol:u16 price:i64 size:i64
+/// Q timestamp_ns:i64 symbol:u16 bid:i64 bid_size:i64 ask:i64 ask_size:i64
+/// ```
+pub struct BinaryTickWriter<W: Write> {
+    writer: W,
+    symbols: HashMap<String, u16>,
+}
+
+impl BinaryTickWriter<BufWriter<std::fs::File>> {
+    pub fn create(path: impl AsRef<Path>) -> Result<Self, TickError> {
+        Self::new(BufWriter::new(std::fs::File::create(path)?))
+    }
+}
+
+impl<W: Write> BinaryTickWriter<W> {
+    pub fn new(mut writer: W) -> Result<Self, TickError> {
+        writer.write_all(BINARY_MAGIC)?;
+        Ok(Self {
+            writer,
+            symbols: HashMap::new(),
+        })
+    }
+
+    pub fn write(&mut self, tick: &Tick) -> Result<(), TickError> {
+        let symbol = self.symbol_id(tick.symbol())?;
+        match tick {
+            Tick::Trade(trade) => {
+                let mut record = [0u8; 27];
+                record[0] = TRADE_TAG;
+                record[1..9].copy_from_slice(&trade.timestamp_ns.to_le_bytes());
+                record[9..11].copy_from_slice(&symbol.to_le_bytes());
+                record[11..19].copy_from_slice(&to_fixed(trade.price)?.to_le_bytes());
+                record[19..27].copy_from_slice(&to_fixed(trade.size)?.to_le_bytes());
+                self.writer.write_all(&record)?;
+            }
+            Tick::Quote(quote) => {
+                let mut record = [0u8; 43];
+                record[0] = QUOTE_TAG;
+                record[1..9].copy_from_slice(&quote.timestamp_ns.to_le_bytes());
+                record[9..11].copy_from_slice(&symbol.to_le_bytes());
+                let values = [quote.bid, quote.bid_size, quote.ask, quote.ask_size];
+                for (i, value) in values.into_iter().enumerate() {
+                    let start = 11 + i * 8;
+                    record[start..start + 8].copy_from_slice(&to_fixed(value)?.to_le_bytes());
+                }
+                self.writer.write_all(&record)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Flushes and hands back the underlying writer.
+    pub fn finish(mut self) -> Result<W, TickError> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn symbol_id(&mut self, symbol: &str) -> Result<u16, TickError> {
+        if let Some(id) = self.symbols.get(symbol) {
+            return Ok(*id);
+        }
+        let malformed = |message: &str| TickError::Malformed {
+            position: 0,
+            message: format!("symbol `{}`: {}", symbol, message),
+        };
+        let id = u16::try_from(self.symbols.len()).map_err(|_| malformed("too many symbols"))?;
+        let len = u8::try_from(symbol.len()).map_err(|_| malformed("name too long"))?;
+
+        self.writer.write_all(&[SYMBOL_TAG])?;
+        self.writer.write_all(&id.to_le_bytes())?;
+        self.writer.write_all(&[len])?;
+        self.writer.write_all(symbol.as_bytes())?;
+        self.symbols.insert(symbol.to_string(), id);
+        Ok(id)
+    }
+}
+
+fn to_fixed(value: Decimal) -> Result<i64, TickError> {
+    let mut scaled = value;
+    scaled.rescale(FIXED_POINT_SCALE);
+    if scaled != value {
+        return Err(TickError::Unrepresentable(value));
+    }
+    i64::try_from(scaled.mantissa()).map_err(|_| TickError::Unrepresentable(value))
+}
+
+fn from_fixed(value: i64) -> Decimal {
+    Decimal::new(value, FIXED_POINT_SCALE).normalize()
+}
+
+/// Reads the binary tick format written by `BinaryTickWriter`, in file
+/// order.
+pub struct BinaryTickReader<R: Read> {
+    reader: R,
+    symbols: HashMap<u16, String>,
+    offset: u64,
+    // Set after an error; the stream cannot be resynchronised
+    failed: bool,
+}
+
+impl BinaryTickReader<BufReader<std::fs::File>> {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, TickError> {
+        Self::new(BufReader::new(std::fs::File::open(path)?))
+    }
+}
+
+impl<R: Read> BinaryTickReader<R> {
+    pub fn new(mut reader: R) -> Result<Self, TickError> {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != BINARY_MAGIC {
+            return Err(TickError::Malformed {
+                position: 0,
+                message: "not a binary tick file".to_string(),
+            });
+        }
+        Ok(Self {
+            reader,
+            symbols: HashMap::new(),
+            offset: BINARY_MAGIC.len() as u64,
+            failed: false,
+        })
+    }
+
+    fn read_bytes<const N: usize>(&mut self) -> Result<[u8; N], TickError> {
+        let mut buf = [0u8; N];
+        self.read_into(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// Reads the rest of a record; running out of input here is an error.
+    fn read_into(&mut self, buf: &mut [u8]) -> Result<(), TickError> {
+        self.reader.read_exact(buf).map_err(|e| match e.kind() {
+            ErrorKind::UnexpectedEof => TickError::Malformed {
+                position: self.offset,
+                message: "truncated record".to_string(),
+            },
+            _ => e.into(),
+        })?;
+        self.offset += buf.len() as u64;
+        Ok(())
+    }
+
+    fn read_i64(&mut self) -> Result<i64, TickError> {
+        Ok(i64::from_le_bytes(self.read_bytes()?))
+    }
+
+    fn read_symbol(&mut self, position: u64) -> Result<String, TickError> {
+        let id = u16::from_le_bytes(self.read_bytes()?);
+        self.symbols
+            .get(&id)
+            .cloned()
+            .ok_or_else(|| TickError::Malformed {
+                position,
+                message: format!("undefined symbol {}", id),
+            })
+    }
+
+    fn read_tick(&mut self) -> Result<Option<Tick>, TickError> {
+        loop {
+            let position = self.offset;
+            let mut tag = [0u8; 1];
+            match self.reader.read_exact(&mut tag) {
+                Ok(()) => self.offset += 1,
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) => return Err(e.into()),
+            }
+
+            match tag[0] {
+                SYMBOL_TAG => {
+                    let id = u16::from_le_bytes(self.read_bytes()?);
+                    let [len] = self.read_bytes()?;
+                    let mut name = vec![0u8; len as usize];
+                    self.read_into(&mut name)?;
+                    let name = String::from_utf8(name).map_err(|_| TickError::Malformed {
+                        position,
+                        message: "symbol is not UTF-8".to_string(),
+                    })?;
+                    self.symbols.insert(id, name);
+                }
+                TRADE_TAG => {
+                    let timestamp_ns = self.read_i64()?;
+                    let symbol = self.read_symbol(position)?;
+                    return Ok(Some(Tick::Trade(TradeTick {
+                        timestamp_ns,
+                        symbol,
+                        price: from_fixed(self.read_i64()?),
+                        size: from_fixed(self.read_i64()?),
+                    })));
+                }
+                QUOTE_TAG => {
+                    let timestamp_ns = self.read_i64()?;
+                    let symbol = self.read_symbol(position)?;
+                    return Ok(Some(Tick::Quote(QuoteTick {
+                        timestamp_ns,
+                        symbol,
+                        bid: from_fixed(self.read_i64()?),
+                        bid_size: from_fixed(self.read_i64()?),
+                        ask: from_fixed(self.read_i64()?),
+                        ask_size: from_fixed(self.read_i64()?),
+                    })));
+                }
+                tag => {
+                    return Err(TickError::Malformed {
+                        position,
+                        message: format!("unknown record tag {:#04x}", tag),
+                    })
+                }
+            }
+        }
+    }
+}
+
+impl<R: Read> Iterator for BinaryTickReader<R> {
+    type Item = Result<Tick, TickError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let tick = self.read_tick().transpose();
+        self.failed = matches!(tick, Some(Err(_)));
+        tick
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn trade(timestamp_ns: i64, symbol: &str, price: Decimal, size: Decimal) -> Tick {
+        Tick::Trade(TradeTick {
+            timestamp_ns,
+            symbol: symbol.to_string(),
+            price,
+            size,
+        })
+    }
+
+    fn write_binary(ticks: &[Tick]) -> Vec<u8> {
+        let mut writer = BinaryTickWriter::new(Vec::new()).unwrap();
+        for tick in ticks {
+            writer.write(tick).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn binary_ticks_round_trip() {
+        let ticks = vec![
+            trade(1_700_000_000_000_000_001, "AAPL", dec!(189.25), dec!(100)),
+            Tick::Quote(QuoteTick {
+                timestamp_ns: 1_700_000_000_000_000_002,
+                symbol: "MSFT".to_string(),
+                bid: dec!(370.01),
+                bid_size: dec!(0.5),
+                ask: Decimal::ZERO,
+                ask_size: Decimal::ZERO,
+            }),
+            trade(
+                1_700_000_000_000_000_003,
+                "AAPL",
+                dec!(0.00000001),
+                dec!(-3),
+            ),
+        ];
+
+        let bytes = write_binary(&ticks);
+        let read: Vec<Tick> = BinaryTickReader::new(bytes.as_slice())
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(read, ticks);
+    }
+
+    #[test]
+    fn values_finer_than_the_fixed_point_scale_are_refused() {
+        let mut writer = BinaryTickWriter::new(Vec::new()).unwrap();
+        let tick = trade(1, "AAPL", dec!(1.000000001), dec!(1));
+        assert!(matches!(
+            writer.write(&tick),
+            Err(TickError::Unrepresentable(value)) if value == dec!(1.000000001)
+        ));
+    }
+
+    #[test]
+    fn truncated_binary_file_stops_at_the_broken_record() {
+        let ticks = [
+            trade(1, "AAPL", dec!(100), dec!(1)),
+            trade(2, "AAPL", dec!(101), dec!(1)),
+        ];
+        let bytes = write_binary(&ticks);
+        let mut reader = BinaryTickReader::new(&bytes[..bytes.len() - 1]).unwrap();
+
+        assert_eq!(reader.next().unwrap().unwrap(), ticks[0]);
+        let second_record = (bytes.len() - 27) as u64;
+        assert!(matches!(
+            reader.next(),
+            Some(Err(TickError::Malformed { position, .. })) if position > second_record
+        ));
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn binary_reader_checks_the_magic() {
+        assert!(matches!(
+            BinaryTickReader::new(&b"NOTTICKS"[..]),
+            Err(TickError::Malformed { position: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn csv_ticks_skip_comments_and_report_malformed_rows() {
+        let csv = "\
+# timestamp_ns,type,symbol,...
+1,T,AAPL,189.25,100
+2, Q, AAPL, 189.20, 300, 189.30, 200
+# trailing note
+3,T,AAPL,abc,100
+";
+        let mut ticks = read_csv_ticks(csv.as_bytes());
+
+        assert_eq!(
+            ticks.next().unwrap().unwrap(),
+            trade(1, "AAPL", dec!(189.25), dec!(100))
+        );
+        assert_eq!(
+            ticks.next().unwrap().unwrap(),
+            Tick::Quote(QuoteTick {
+                timestamp_ns: 2,
+                symbol: "AAPL".to_string(),
+                bid: dec!(189.20),
+                bid_size: dec!(300),
+                ask: dec!(189.30),
+                ask_size: dec!(200),
+            })
+        );
+        assert!(matches!(
+            ticks.next(),
+            Some(Err(TickError::Malformed { position: 5, message })) if message.contains("abc")
+        ));
+        assert!(ticks.next().is_none());
+    }
+}